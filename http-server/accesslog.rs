@@ -0,0 +1,286 @@
+use crate::date;
+use crate::files::json_escape;
+use crate::request::Request;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+// set by the SIGHUP handler, the next line written reopens the file
+static REOPEN: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+pub enum LogFormat {
+    Common,
+    Combined,
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "common" => Some(LogFormat::Common),
+            "combined" => Some(LogFormat::Combined),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+enum Sink {
+    Stdout,
+    File(String, File),
+}
+
+pub struct AccessLog {
+    format: LogFormat,
+    sink: Mutex<Sink>,
+}
+
+// one finished exchange, request is None when the head couldn't be parsed
+pub struct Entry<'a> {
+    pub client: &'a str,
+    pub request: Option<&'a Request>,
+    pub status: u16,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+impl AccessLog {
+    // "-" logs to stdout, anything else is a file that is appended to
+    pub fn open(destination: &str, format: LogFormat) -> io::Result<Self> {
+        let sink = if destination == "-" {
+            Sink::Stdout
+        } else {
+            let file = append(destination)?;
+            // stdout has nothing to reopen, SIGHUP keeps terminating the server then
+            reopen_on_sighup();
+            Sink::File(destination.to_string(), file)
+        };
+
+        Ok(Self {
+            format,
+            sink: Mutex::new(sink),
+        })
+    }
+
+    pub fn log(&self, entry: &Entry) {
+        // the latency trails the usual fields in microseconds, like Apache's %D
+        let micros = entry.duration.as_micros();
+        let line = match self.format {
+            LogFormat::Common => format!("{} {}", common(entry), micros),
+            LogFormat::Combined => format!(
+                "{} \"{}\" \"{}\" {}",
+                common(entry),
+                clf_escape(&header(entry, "Referer")),
+                clf_escape(&header(entry, "User-Agent")),
+                micros
+            ),
+            LogFormat::Json => json(entry),
+        };
+
+        let mut sink = match self.sink.lock() {
+            Ok(sink) => sink,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if let Sink::File(ref path, ref mut file) = *sink {
+            if REOPEN.swap(false, Ordering::Relaxed) {
+                match append(path) {
+                    Ok(reopened) => *file = reopened,
+                    Err(e) => println!("err: reopening access log {}: {}", path, e),
+                }
+            }
+        }
+
+        let result = match *sink {
+            Sink::Stdout => writeln!(io::stdout().lock(), "{line}"),
+            Sink::File(_, ref mut file) => writeln!(file, "{line}"),
+        };
+        if let Err(e) = result {
+            println!("err: writing access log: {}", e);
+        }
+    }
+}
+
+fn append(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// logrotate moves the file away and sends SIGHUP so a new one gets created
+#[cfg(unix)]
+fn reopen_on_sighup() {
+    const SIGHUP: i32 = 1;
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+
+    extern "C" fn on_sighup(_: i32) {
+        REOPEN.store(true, Ordering::Relaxed);
+    }
+
+    // only an atomic store happens inside the handler
+    unsafe {
+        signal(SIGHUP, on_sighup);
+    }
+}
+
+#[cfg(not(unix))]
+fn reopen_on_sighup() {}
+
+fn header(entry: &Entry, name: &str) -> String {
+    entry
+        .request
+        .and_then(|request| request.headers.get(name))
+        .unwrap_or("-")
+        .to_string()
+}
+
+// host ident authuser [date] "request" status bytes
+fn common(entry: &Entry) -> String {
+    let request_line = match entry.request {
+        Some(request) => format!(
+            "{} {} {}",
+            request.method,
+            request.target,
+            request.version.str()
+        ),
+        None => "-".to_string(),
+    };
+    let bytes = match entry.bytes {
+        0 => "-".to_string(),
+        bytes => bytes.to_string(),
+    };
+
+    format!(
+        "{} - - [{}] \"{}\" {} {}",
+        entry.client,
+        date::clf_date(SystemTime::now()),
+        clf_escape(&request_line),
+        entry.status,
+        bytes
+    )
+}
+
+fn json(entry: &Entry) -> String {
+    let field = |value: Option<&str>| match value {
+        Some(value) => format!("\"{}\"", json_escape(value)),
+        None => "null".to_string(),
+    };
+    let request = entry.request;
+
+    format!(
+        "{{\"time\":\"{}\",\"client\":\"{}\",\"method\":{},\"path\":{},\"protocol\":{},\"status\":{},\"bytes\":{},\"referer\":{},\"user_agent\":{},\"duration_ms\":{:.3}}}",
+        date::iso8601(SystemTime::now()),
+        json_escape(entry.client),
+        field(request.map(|request| request.method.as_str())),
+        field(request.map(|request| request.target.as_str())),
+        field(request.map(|request| request.version.str())),
+        entry.status,
+        entry.bytes,
+        field(request.and_then(|request| request.headers.get("Referer"))),
+        field(request.and_then(|request| request.headers.get("User-Agent"))),
+        entry.duration.as_secs_f64() * 1000.0
+    )
+}
+
+// quotes and non-printable bytes would make the line ambiguous
+fn clf_escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+    use crate::request::Version;
+    use std::collections::HashMap;
+
+    fn request() -> Request {
+        let mut headers = Headers::new();
+        headers.append("User-Agent", "curl \"x\"\n");
+        Request {
+            method: "GET".to_string(),
+            target: "/a%20b?q=1".to_string(),
+            path: "/a%20b".to_string(),
+            query: HashMap::new(),
+            version: Version::Http11,
+            headers,
+            body: Vec::new(),
+        }
+    }
+
+    // the line written for entry, with the timestamp taken out
+    fn line(format: LogFormat, entry: &Entry) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "access-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        let log = AccessLog::open(path.to_str().unwrap(), format).unwrap();
+        log.log(entry);
+        let line = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let (start, end) = match format {
+            LogFormat::Json => ("\"time\":\"", "\","),
+            _ => ("[", "]"),
+        };
+        let from = line.find(start).unwrap() + start.len();
+        let to = from + line[from..].find(end).unwrap();
+        format!("{}{}", &line[..from], &line[to..])
+    }
+
+    #[test]
+    fn writes_each_format() {
+        let request = request();
+        let entry = Entry {
+            client: "127.0.0.1",
+            request: Some(&request),
+            status: 200,
+            bytes: 5,
+            duration: Duration::from_micros(1500),
+        };
+        assert_eq!(
+            line(LogFormat::Common, &entry),
+            "127.0.0.1 - - [] \"GET /a%20b?q=1 HTTP/1.1\" 200 5 1500\n"
+        );
+        assert_eq!(
+            line(LogFormat::Combined, &entry),
+            "127.0.0.1 - - [] \"GET /a%20b?q=1 HTTP/1.1\" 200 5 \"-\" \"curl \\\"x\\\"\\x0a\" 1500\n"
+        );
+        assert_eq!(
+            line(LogFormat::Json, &entry),
+            "{\"time\":\"\",\"client\":\"127.0.0.1\",\"method\":\"GET\",\"path\":\"/a%20b?q=1\",\
+             \"protocol\":\"HTTP/1.1\",\"status\":200,\"bytes\":5,\"referer\":null,\
+             \"user_agent\":\"curl \\\"x\\\"\\u000a\",\"duration_ms\":1.500}\n"
+        );
+    }
+
+    #[test]
+    fn logs_requests_that_failed_to_parse() {
+        let entry = Entry {
+            client: "::1",
+            request: None,
+            status: 400,
+            bytes: 0,
+            duration: Duration::ZERO,
+        };
+        assert_eq!(
+            line(LogFormat::Common, &entry),
+            "::1 - - [] \"-\" 400 - 0\n"
+        );
+        assert!(line(LogFormat::Json, &entry).contains("\"method\":null,\"path\":null"));
+    }
+}
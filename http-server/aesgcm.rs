@@ -0,0 +1,268 @@
+// AES-128 in Galois/Counter Mode (FIPS 197, SP 800-38D), encryption direction only
+// since GCM runs the block cipher as a key stream
+
+pub const KEY_LEN: usize = 16;
+pub const TAG_LEN: usize = 16;
+
+pub struct Aes128 {
+    round_keys: [[u8; 16]; 11],
+}
+
+impl Aes128 {
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        let mut words = [[0u8; 4]; 44];
+        for i in 0..4 {
+            words[i].copy_from_slice(&key[i * 4..i * 4 + 4]);
+        }
+
+        let mut rcon = 1u8;
+        for i in 4..44 {
+            let mut word = words[i - 1];
+            if i % 4 == 0 {
+                word = [
+                    sub_byte(word[1]) ^ rcon,
+                    sub_byte(word[2]),
+                    sub_byte(word[3]),
+                    sub_byte(word[0]),
+                ];
+                rcon = xtime(rcon);
+            }
+            for j in 0..4 {
+                words[i][j] = words[i - 4][j] ^ word[j];
+            }
+        }
+
+        let mut round_keys = [[0u8; 16]; 11];
+        for (round, key) in round_keys.iter_mut().enumerate() {
+            for j in 0..4 {
+                key[j * 4..j * 4 + 4].copy_from_slice(&words[round * 4 + j]);
+            }
+        }
+        Self { round_keys }
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; 16]) {
+        add_round_key(block, &self.round_keys[0]);
+        for round in 1..11 {
+            for byte in block.iter_mut() {
+                *byte = sub_byte(*byte);
+            }
+            shift_rows(block);
+            if round != 10 {
+                mix_columns(block);
+            }
+            add_round_key(block, &self.round_keys[round]);
+        }
+    }
+}
+
+// every step below masks on secret bits instead of branching on them or
+// indexing a table with them, so timing and cache state stay independent
+// of the key and data
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ (0x1b & (b >> 7).wrapping_neg())
+}
+
+fn gf8_mul(a: u8, b: u8) -> u8 {
+    let mut product = 0;
+    let mut a = a;
+    for i in 0..8 {
+        product ^= a & ((b >> i) & 1).wrapping_neg();
+        a = xtime(a);
+    }
+    product
+}
+
+// the S-box: the inverse in GF(2^8), as x^254 with 0 mapping to 0, then
+// the affine transform
+fn sub_byte(x: u8) -> u8 {
+    let mut square = x;
+    let mut inverse = 1;
+    for _ in 1..8 {
+        square = gf8_mul(square, square);
+        inverse = gf8_mul(inverse, square);
+    }
+    inverse
+        ^ inverse.rotate_left(1)
+        ^ inverse.rotate_left(2)
+        ^ inverse.rotate_left(3)
+        ^ inverse.rotate_left(4)
+        ^ 0x63
+}
+
+fn add_round_key(block: &mut [u8; 16], key: &[u8; 16]) {
+    for (byte, k) in block.iter_mut().zip(key) {
+        *byte ^= k;
+    }
+}
+
+// the block is column major, byte r + 4c is row r of column c
+fn shift_rows(block: &mut [u8; 16]) {
+    let state = *block;
+    for r in 1..4 {
+        for c in 0..4 {
+            block[r + 4 * c] = state[r + 4 * ((c + r) % 4)];
+        }
+    }
+}
+
+fn mix_columns(block: &mut [u8; 16]) {
+    for column in block.chunks_mut(4) {
+        let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+        let all = a0 ^ a1 ^ a2 ^ a3;
+        column[0] ^= all ^ xtime(a0 ^ a1);
+        column[1] ^= all ^ xtime(a1 ^ a2);
+        column[2] ^= all ^ xtime(a2 ^ a3);
+        column[3] ^= all ^ xtime(a3 ^ a0);
+    }
+}
+
+// multiplication in GF(2^128) with GCM's reflected bit order
+fn gf_mul(x: u128, y: u128) -> u128 {
+    let mut z = 0u128;
+    let mut v = y;
+    for i in 0..128 {
+        z ^= v & ((x >> (127 - i)) & 1).wrapping_neg();
+        v = (v >> 1) ^ ((0xe1 << 120) & (v & 1).wrapping_neg());
+    }
+    z
+}
+
+fn ghash(h: u128, aad: &[u8], ciphertext: &[u8]) -> u128 {
+    let mut y = 0u128;
+    for data in [aad, ciphertext] {
+        for chunk in data.chunks(16) {
+            let mut block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            y = gf_mul(y ^ u128::from_be_bytes(block), h);
+        }
+    }
+    let lengths = ((aad.len() as u128 * 8) << 64) | (ciphertext.len() as u128 * 8);
+    gf_mul(y ^ lengths, h)
+}
+
+fn counter_block(nonce: &[u8; 12], counter: u32) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[..12].copy_from_slice(nonce);
+    block[12..].copy_from_slice(&counter.to_be_bytes());
+    block
+}
+
+fn ctr(aes: &Aes128, nonce: &[u8; 12], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(16).enumerate() {
+        let mut keystream = counter_block(nonce, 2 + i as u32);
+        aes.encrypt_block(&mut keystream);
+        for (byte, k) in chunk.iter_mut().zip(keystream.iter()) {
+            *byte ^= k;
+        }
+    }
+}
+
+fn tag(aes: &Aes128, nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_LEN] {
+    let mut h = [0u8; 16];
+    aes.encrypt_block(&mut h);
+    let mut j0 = counter_block(nonce, 1);
+    aes.encrypt_block(&mut j0);
+
+    let s = ghash(u128::from_be_bytes(h), aad, ciphertext);
+    (s ^ u128::from_be_bytes(j0)).to_be_bytes()
+}
+
+// returns the ciphertext followed by the tag
+pub fn seal(key: &[u8; KEY_LEN], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let aes = Aes128::new(key);
+    let mut sealed = plaintext.to_vec();
+    ctr(&aes, nonce, &mut sealed);
+    let tag = tag(&aes, nonce, aad, &sealed);
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+// None when the tag doesn't match
+pub fn open(key: &[u8; KEY_LEN], nonce: &[u8; 12], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < TAG_LEN {
+        return None;
+    }
+    let (ciphertext, received) = sealed.split_at(sealed.len() - TAG_LEN);
+
+    let aes = Aes128::new(key);
+    let expected = tag(&aes, nonce, aad, ciphertext);
+    // compared without an early exit
+    let difference = expected
+        .iter()
+        .zip(received)
+        .fold(0, |acc, (a, b)| acc | (a ^ b));
+    if difference != 0 {
+        return None;
+    }
+
+    let mut plaintext = ciphertext.to_vec();
+    ctr(&aes, nonce, &mut plaintext);
+    Some(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digits: &str) -> Vec<u8> {
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn computes_the_s_box() {
+        for (input, output) in [
+            (0x00, 0x63),
+            (0x01, 0x7c),
+            (0x53, 0xed),
+            (0xc9, 0xdd),
+            (0xff, 0x16),
+        ] {
+            assert_eq!(sub_byte(input), output);
+        }
+    }
+
+    // FIPS 197 appendix C.1
+    #[test]
+    fn encrypts_a_block() {
+        let key: [u8; 16] = hex("000102030405060708090a0b0c0d0e0f").try_into().unwrap();
+        let mut block: [u8; 16] = hex("00112233445566778899aabbccddeeff").try_into().unwrap();
+        Aes128::new(&key).encrypt_block(&mut block);
+        assert_eq!(block.to_vec(), hex("69c4e0d86a7b0430d8cdb78070b4c55a"));
+    }
+
+    // test cases 2 and 4 of the GCM specification
+    #[test]
+    fn seals_and_opens() {
+        let sealed = seal(&[0; 16], &[0; 12], &[], &[0; 16]);
+        assert_eq!(
+            sealed,
+            hex("0388dace60b6a392f328c2b971b2fe78ab6e47d42cec13bdf53a67b21257bddf")
+        );
+
+        let key: [u8; 16] = hex("feffe9928665731c6d6a8f9467308308").try_into().unwrap();
+        let nonce: [u8; 12] = hex("cafebabefacedbaddecaf888").try_into().unwrap();
+        let aad = hex("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+        let plaintext = hex(
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+             1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+        );
+        let sealed = seal(&key, &nonce, &aad, &plaintext);
+        assert_eq!(
+            sealed,
+            hex(
+                "42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e\
+                 21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091\
+                 5bc94fbc3221a5db94fae95ae7121a47"
+            )
+        );
+        assert_eq!(open(&key, &nonce, &aad, &sealed), Some(plaintext));
+
+        let mut tampered = sealed;
+        tampered[0] ^= 1;
+        assert_eq!(open(&key, &nonce, &aad, &tampered), None);
+    }
+}
@@ -0,0 +1,94 @@
+// the standard alphabet with padding (RFC 4648)
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let mut padded = [0u8; 3];
+        padded[..group.len()].copy_from_slice(group);
+        let bits =
+            (u32::from(padded[0]) << 16) | (u32::from(padded[1]) << 8) | u32::from(padded[2]);
+
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn value(c: u8) -> Option<u32> {
+    match c {
+        b'A'..=b'Z' => Some(u32::from(c - b'A')),
+        b'a'..=b'z' => Some(u32::from(c - b'a') + 26),
+        b'0'..=b'9' => Some(u32::from(c - b'0') + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+// whitespace is skipped, as PEM wraps lines
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    let symbols: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !symbols.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut decoded: Vec<u8> = Vec::with_capacity(symbols.len() / 4 * 3);
+    for (i, group) in symbols.chunks(4).enumerate() {
+        let last = i == symbols.len() / 4 - 1;
+        let padding = group.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+
+        let mut bits = 0u32;
+        for &symbol in &group[..4 - padding] {
+            bits = (bits << 6) | value(symbol)?;
+        }
+        bits <<= 6 * padding as u32;
+
+        let bytes = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        decoded.extend_from_slice(&bytes[..3 - padding]);
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 4648 section 10
+    const VECTORS: [(&str, &str); 7] = [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn encodes_and_decodes() {
+        for (plain, encoded) in VECTORS {
+            assert_eq!(encode(plain.as_bytes()), encoded);
+            assert_eq!(decode(encoded).unwrap(), plain.as_bytes());
+        }
+        assert_eq!(decode("Zm9v\r\nYmFy\n").unwrap(), b"foobar");
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&bytes)).unwrap(), bytes);
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for text in ["Zm9", "Zm9v=", "Zg=a", "Z===", "Zg==Zm9v", "Zm9-", "Zm9_"] {
+            assert!(decode(text).is_none(), "{text}");
+        }
+    }
+}
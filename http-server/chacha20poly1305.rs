@@ -0,0 +1,269 @@
+// ChaCha20-Poly1305 AEAD (RFC 8439)
+
+pub const KEY_LEN: usize = 32;
+pub const TAG_LEN: usize = 16;
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn block(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; 12]) -> [u8; 64] {
+    let mut initial = [0u32; 16];
+    initial[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    for i in 0..8 {
+        initial[4 + i] = le32(&key[i * 4..]);
+    }
+    initial[12] = counter;
+    for i in 0..3 {
+        initial[13 + i] = le32(&nonce[i * 4..]);
+    }
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut out = [0u8; 64];
+    for i in 0..16 {
+        let word = state[i].wrapping_add(initial[i]);
+        out[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+// xors the keystream starting at block counter into data
+fn chacha20(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; 12], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let keystream = block(key, counter.wrapping_add(i as u32), nonce);
+        for (byte, k) in chunk.iter_mut().zip(keystream.iter()) {
+            *byte ^= k;
+        }
+    }
+}
+
+// the 130-bit accumulator is kept in five 26-bit limbs
+struct Poly1305 {
+    r: [u32; 5],
+    h: [u32; 5],
+    pad: [u32; 4],
+}
+
+impl Poly1305 {
+    fn new(key: &[u8; 32]) -> Self {
+        Self {
+            r: [
+                le32(&key[0..]) & 0x3ffffff,
+                (le32(&key[3..]) >> 2) & 0x3ffff03,
+                (le32(&key[6..]) >> 4) & 0x3ffc0ff,
+                (le32(&key[9..]) >> 6) & 0x3f03fff,
+                (le32(&key[12..]) >> 8) & 0x00fffff,
+            ],
+            h: [0; 5],
+            pad: [
+                le32(&key[16..]),
+                le32(&key[20..]),
+                le32(&key[24..]),
+                le32(&key[28..]),
+            ],
+        }
+    }
+
+    // the input is zero padded to whole blocks, as the AEAD construction wants
+    fn update_padded(&mut self, data: &[u8]) {
+        for chunk in data.chunks(16) {
+            let mut block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.block(&block);
+        }
+    }
+
+    fn block(&mut self, m: &[u8; 16]) {
+        let [r0, r1, r2, r3, r4] = self.r.map(u64::from);
+        let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+
+        let h0 = u64::from(self.h[0] + (le32(&m[0..]) & 0x3ffffff));
+        let h1 = u64::from(self.h[1] + ((le32(&m[3..]) >> 2) & 0x3ffffff));
+        let h2 = u64::from(self.h[2] + ((le32(&m[6..]) >> 4) & 0x3ffffff));
+        let h3 = u64::from(self.h[3] + ((le32(&m[9..]) >> 6) & 0x3ffffff));
+        let h4 = u64::from(self.h[4] + ((le32(&m[12..]) >> 8) | (1 << 24)));
+
+        let d0 = h0 * r0 + h1 * s4 + h2 * s3 + h3 * s2 + h4 * s1;
+        let mut d1 = h0 * r1 + h1 * r0 + h2 * s4 + h3 * s3 + h4 * s2;
+        let mut d2 = h0 * r2 + h1 * r1 + h2 * r0 + h3 * s4 + h4 * s3;
+        let mut d3 = h0 * r3 + h1 * r2 + h2 * r1 + h3 * r0 + h4 * s4;
+        let mut d4 = h0 * r4 + h1 * r3 + h2 * r2 + h3 * r1 + h4 * r0;
+
+        d1 += d0 >> 26;
+        d2 += d1 >> 26;
+        d3 += d2 >> 26;
+        d4 += d3 >> 26;
+        let mut h0 = (d0 & 0x3ffffff) + (d4 >> 26) * 5;
+        let h1 = (d1 & 0x3ffffff) + (h0 >> 26);
+        h0 &= 0x3ffffff;
+
+        self.h = [
+            h0 as u32,
+            h1 as u32,
+            (d2 & 0x3ffffff) as u32,
+            (d3 & 0x3ffffff) as u32,
+            (d4 & 0x3ffffff) as u32,
+        ];
+    }
+
+    fn finish(self) -> [u8; TAG_LEN] {
+        let [mut h0, mut h1, mut h2, mut h3, mut h4] = self.h;
+
+        h2 += h1 >> 26;
+        h1 &= 0x3ffffff;
+        h3 += h2 >> 26;
+        h2 &= 0x3ffffff;
+        h4 += h3 >> 26;
+        h3 &= 0x3ffffff;
+        h0 += (h4 >> 26) * 5;
+        h4 &= 0x3ffffff;
+        h1 += h0 >> 26;
+        h0 &= 0x3ffffff;
+
+        // h - p, used instead of h when h >= p
+        let mut g0 = h0 + 5;
+        let mut g1 = h1 + (g0 >> 26);
+        g0 &= 0x3ffffff;
+        let mut g2 = h2 + (g1 >> 26);
+        g1 &= 0x3ffffff;
+        let mut g3 = h3 + (g2 >> 26);
+        g2 &= 0x3ffffff;
+        let g4 = (h4 + (g3 >> 26)).wrapping_sub(1 << 26);
+        g3 &= 0x3ffffff;
+
+        let mask = (g4 >> 31).wrapping_sub(1);
+        h0 = (h0 & !mask) | (g0 & mask);
+        h1 = (h1 & !mask) | (g1 & mask);
+        h2 = (h2 & !mask) | (g2 & mask);
+        h3 = (h3 & !mask) | (g3 & mask);
+        h4 = (h4 & !mask) | (g4 & mask);
+
+        let words = [
+            h0 | (h1 << 26),
+            (h1 >> 6) | (h2 << 20),
+            (h2 >> 12) | (h3 << 14),
+            (h3 >> 18) | (h4 << 8),
+        ];
+
+        let mut tag = [0u8; TAG_LEN];
+        let mut carry = 0u64;
+        for i in 0..4 {
+            let sum = u64::from(words[i]) + u64::from(self.pad[i]) + carry;
+            tag[i * 4..i * 4 + 4].copy_from_slice(&(sum as u32).to_le_bytes());
+            carry = sum >> 32;
+        }
+        tag
+    }
+}
+
+fn tag(key: &[u8; KEY_LEN], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_LEN] {
+    let mut poly_key = [0u8; 32];
+    poly_key.copy_from_slice(&block(key, 0, nonce)[..32]);
+
+    let mut poly = Poly1305::new(&poly_key);
+    poly.update_padded(aad);
+    poly.update_padded(ciphertext);
+    let mut lengths = [0u8; 16];
+    lengths[..8].copy_from_slice(&(aad.len() as u64).to_le_bytes());
+    lengths[8..].copy_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly.block(&lengths);
+    poly.finish()
+}
+
+// returns the ciphertext followed by the tag
+pub fn seal(key: &[u8; KEY_LEN], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut sealed = plaintext.to_vec();
+    chacha20(key, 1, nonce, &mut sealed);
+    let tag = tag(key, nonce, aad, &sealed);
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+// None when the tag doesn't match
+pub fn open(key: &[u8; KEY_LEN], nonce: &[u8; 12], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < TAG_LEN {
+        return None;
+    }
+    let (ciphertext, received) = sealed.split_at(sealed.len() - TAG_LEN);
+
+    let expected = tag(key, nonce, aad, ciphertext);
+    // compared without an early exit
+    let difference = expected
+        .iter()
+        .zip(received)
+        .fold(0, |acc, (a, b)| acc | (a ^ b));
+    if difference != 0 {
+        return None;
+    }
+
+    let mut plaintext = ciphertext.to_vec();
+    chacha20(key, 1, nonce, &mut plaintext);
+    Some(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digits: &str) -> Vec<u8> {
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    const PLAINTEXT: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+        only one tip for the future, sunscreen would be it.";
+
+    // RFC 8439 section 2.8.2
+    #[test]
+    fn seals_and_opens() {
+        let key: [u8; KEY_LEN] =
+            hex("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f")
+                .try_into()
+                .unwrap();
+        let nonce: [u8; 12] = hex("070000004041424344454647").try_into().unwrap();
+        let aad = hex("50515253c0c1c2c3c4c5c6c7");
+
+        let sealed = seal(&key, &nonce, &aad, PLAINTEXT);
+        assert_eq!(
+            sealed,
+            hex(
+                "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+                 3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+                 92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+                 3ff4def08e4b7a9de576d26586cec64b6116\
+                 1ae10b594f09e26a7e902ecbd0600691"
+            )
+        );
+        assert_eq!(open(&key, &nonce, &aad, &sealed).unwrap(), PLAINTEXT);
+
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert!(open(&key, &nonce, &aad, &tampered).is_none());
+        assert!(open(&key, &nonce, b"", &sealed).is_none());
+        assert!(open(&key, &nonce, &aad, &sealed[..15]).is_none());
+    }
+}
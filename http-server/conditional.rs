@@ -0,0 +1,261 @@
+use crate::date;
+use crate::response::Response;
+use crate::router::Context;
+use crate::statuscode::StatusCode;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: SystemTime,
+}
+
+impl Validators {
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?;
+        let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(Self {
+            etag: format!(
+                "\"{:x}-{:x}-{:x}\"",
+                metadata.len(),
+                since_epoch.as_secs(),
+                since_epoch.subsec_nanos()
+            ),
+            // Last-Modified only carries whole seconds
+            last_modified: UNIX_EPOCH + std::time::Duration::from_secs(since_epoch.as_secs()),
+        })
+    }
+
+    pub fn apply(&self, response: Response) -> Response {
+        response
+            .with_header("ETag", &self.etag)
+            .with_header("Last-Modified", &date::http_date(self.last_modified))
+    }
+}
+
+// evaluates the preconditions in the order of RFC 9110 section 13.2.2, current
+// is None when the target doesn't exist yet
+pub fn evaluate(ctx: &Context, current: Option<&Validators>) -> Option<StatusCode> {
+    let safe = ctx.method() == "GET" || ctx.method() == "HEAD";
+
+    if let Some(if_match) = ctx.header("If-Match") {
+        let matched = match current {
+            Some(validators) => etag_list_matches(&if_match, &validators.etag, true),
+            None => false,
+        };
+        if !matched {
+            return Some(StatusCode::PreconditionFailed);
+        }
+    } else if let Some(since) = ctx.header("If-Unmodified-Since") {
+        if let (Some(validators), Some(since)) = (current, date::parse_http_date(&since)) {
+            if validators.last_modified > since {
+                return Some(StatusCode::PreconditionFailed);
+            }
+        }
+    }
+
+    if let Some(if_none_match) = ctx.header("If-None-Match") {
+        let matched = match current {
+            Some(validators) => etag_list_matches(&if_none_match, &validators.etag, false),
+            None => false,
+        };
+        if matched {
+            return Some(if safe {
+                StatusCode::NotModified
+            } else {
+                StatusCode::PreconditionFailed
+            });
+        }
+    } else if let Some(since) = ctx.header("If-Modified-Since") {
+        if let (true, Some(validators), Some(since)) =
+            (safe, current, date::parse_http_date(&since))
+        {
+            if validators.last_modified <= since {
+                return Some(StatusCode::NotModified);
+            }
+        }
+    }
+
+    None
+}
+
+// "*" or a list of entity tags; the strong comparison never matches weak tags
+pub fn etag_list_matches(list: &str, etag: &str, strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+
+    list.split(',').map(str::trim).any(|candidate| {
+        if strong {
+            !candidate.starts_with("W/") && !etag.starts_with("W/") && candidate == etag
+        } else {
+            candidate.trim_start_matches("W/") == etag.trim_start_matches("W/")
+        }
+    })
+}
+
+// a Range is only honoured while the If-Range validator still matches exactly
+pub fn if_range_matches(ctx: &Context, current: Option<&Validators>) -> bool {
+    let if_range = match ctx.header("If-Range") {
+        Some(if_range) => if_range,
+        None => return true,
+    };
+    let validators = match current {
+        Some(validators) => validators,
+        None => return false,
+    };
+
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        etag_list_matches(&if_range, &validators.etag, true)
+    } else {
+        date::parse_http_date(&if_range) == Some(validators.last_modified)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+    use crate::request::{Request, Version};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    const LAST_MODIFIED: &str = "Sun, 09 Sep 2001 01:46:40 GMT";
+
+    fn validators() -> Validators {
+        Validators {
+            etag: "\"abc\"".to_string(),
+            last_modified: UNIX_EPOCH + Duration::from_secs(1_000_000_000),
+        }
+    }
+
+    fn request(method: &str, fields: &[(&str, &str)]) -> Request {
+        let mut headers = Headers::new();
+        for (name, value) in fields {
+            headers.append(name, value);
+        }
+        Request {
+            method: method.to_string(),
+            target: "/".to_string(),
+            path: "/".to_string(),
+            query: HashMap::new(),
+            version: Version::Http11,
+            headers,
+            body: Vec::new(),
+        }
+    }
+
+    fn context(request: &Request) -> Context<'_> {
+        Context {
+            request,
+            params: HashMap::new(),
+            directory: "",
+            client: "127.0.0.1",
+            secure: false,
+        }
+    }
+
+    fn evaluate_with(method: &str, fields: &[(&str, &str)], exists: bool) -> Option<u16> {
+        let request = request(method, fields);
+        let current = exists.then(validators);
+        evaluate(&context(&request), current.as_ref()).map(|status| status.code())
+    }
+
+    #[test]
+    fn compares_entity_tags() {
+        assert!(etag_list_matches("*", "\"abc\"", true));
+        assert!(etag_list_matches("\"x\", \"abc\"", "\"abc\"", true));
+        assert!(!etag_list_matches("W/\"abc\"", "\"abc\"", true));
+        assert!(!etag_list_matches("\"abc\"", "W/\"abc\"", true));
+        assert!(etag_list_matches("W/\"abc\"", "\"abc\"", false));
+        assert!(etag_list_matches("\"abc\"", "W/\"abc\"", false));
+        assert!(!etag_list_matches("\"abd\"", "\"abc\"", false));
+    }
+
+    #[test]
+    fn evaluates_preconditions_in_order() {
+        assert_eq!(evaluate_with("GET", &[], true), None);
+        assert_eq!(
+            evaluate_with("GET", &[("If-None-Match", "\"abc\"")], true),
+            Some(304)
+        );
+        assert_eq!(
+            evaluate_with("POST", &[("If-None-Match", "*")], true),
+            Some(412)
+        );
+        assert_eq!(
+            evaluate_with("POST", &[("If-None-Match", "*")], false),
+            None
+        );
+        assert_eq!(
+            evaluate_with("POST", &[("If-Match", "\"abc\"")], true),
+            None
+        );
+        assert_eq!(
+            evaluate_with("POST", &[("If-Match", "\"old\"")], true),
+            Some(412)
+        );
+        assert_eq!(
+            evaluate_with("POST", &[("If-Match", "*")], false),
+            Some(412)
+        );
+
+        assert_eq!(
+            evaluate_with("GET", &[("If-Modified-Since", LAST_MODIFIED)], true),
+            Some(304)
+        );
+        assert_eq!(
+            evaluate_with(
+                "GET",
+                &[("If-Modified-Since", "Sun, 09 Sep 2001 01:46:39 GMT")],
+                true
+            ),
+            None
+        );
+        assert_eq!(
+            evaluate_with("POST", &[("If-Modified-Since", LAST_MODIFIED)], true),
+            None
+        );
+        assert_eq!(
+            evaluate_with("GET", &[("If-Modified-Since", "yesterday")], true),
+            None
+        );
+        assert_eq!(
+            evaluate_with(
+                "POST",
+                &[("If-Unmodified-Since", "Sun, 09 Sep 2001 01:46:39 GMT")],
+                true
+            ),
+            Some(412)
+        );
+
+        // an entity tag takes precedence over the date it would be checked against
+        let fields = [
+            ("If-None-Match", "\"old\""),
+            ("If-Modified-Since", LAST_MODIFIED),
+        ];
+        assert_eq!(evaluate_with("GET", &fields, true), None);
+        let fields = [
+            ("If-Match", "\"abc\""),
+            ("If-Unmodified-Since", "Sat, 01 Jan 2000 00:00:00 GMT"),
+        ];
+        assert_eq!(evaluate_with("POST", &fields, true), None);
+    }
+
+    #[test]
+    fn matches_if_range_exactly() {
+        let current = validators();
+        let matches = |fields: &[(&str, &str)]| {
+            let request = request("GET", fields);
+            if_range_matches(&context(&request), Some(&current))
+        };
+        assert!(matches(&[]));
+        assert!(matches(&[("If-Range", "\"abc\"")]));
+        assert!(matches(&[("If-Range", LAST_MODIFIED)]));
+        assert!(!matches(&[("If-Range", "W/\"abc\"")]));
+        assert!(!matches(&[("If-Range", "Sun, 09 Sep 2001 01:46:41 GMT")]));
+    }
+}
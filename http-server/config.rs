@@ -0,0 +1,305 @@
+use crate::accesslog::LogFormat;
+use crate::proxy::{Balance, ProxyRoute};
+use crate::request::{KeepAlive, Limits, Timeouts};
+use std::fs;
+use std::time::Duration;
+
+pub const USAGE: &str = "usage: http-server [options]
+  --config <file>          read options from a file, one `name = value` per line
+  --bind <address>         address to listen on (default 127.0.0.1)
+  --port <port>            port to listen on (default 4221)
+  --workers <n>            worker threads (default 4)
+  --max-streams <n>        event streams, WebSockets and HTTP/2 connections open
+                           at once, each on a thread of its own outside the
+                           workers (default 256)
+  --directory <dir>        directory served under /files (default .)
+  --list-directories       list directories without an index.html
+  --max-body <bytes>       largest accepted request body (default 10485760)
+  --max-requests <n>       requests per keep-alive connection (default 100)
+  --idle-timeout <secs>    wait for the next request (default 5)
+  --header-timeout <secs>  receive a whole request head (default 10)
+  --read-timeout <secs>    wait on a single body read (default 30)
+  --write-timeout <secs>   wait on a single write (default 30)
+  --ws-idle-timeout <secs> silence before a WebSocket is pinged, and again before
+                           it is closed (default 30)
+  --access-log <file>      where requests are logged, - for stdout, off for nowhere
+                           (default -), reopened on SIGHUP
+  --log-format <format>    common, combined or json (default combined)
+  --tls-port <port>        port to listen on for HTTPS (default 4443)
+  --tls-cert <file>        PEM certificate chain, enables HTTPS together with --tls-key
+  --tls-key <file>         PEM private key, Ed25519 or RSA
+  --proxy <prefix=hosts>   forward requests under prefix to comma separated
+                           host:port upstreams, may be repeated
+  --balance <strategy>     round-robin or least-connections (default round-robin)
+  --proxy-timeout <secs>   connect to and wait on an upstream (default 30)
+  --help                   print this message";
+
+pub struct Config {
+    pub bind: String,
+    pub port: u16,
+    pub workers: usize,
+    pub max_streams: usize,
+    pub directory: String,
+    pub list_directories: bool,
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+    pub keep_alive: KeepAlive,
+    pub ws_idle_timeout: Duration,
+    pub access_log: Option<String>,
+    pub log_format: LogFormat,
+    pub tls_port: u16,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub proxies: Vec<ProxyRoute>,
+    pub balance: Balance,
+    pub proxy_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1".to_string(),
+            port: 4221,
+            workers: 4,
+            max_streams: 256,
+            directory: ".".to_string(),
+            list_directories: false,
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+            keep_alive: KeepAlive::default(),
+            ws_idle_timeout: Duration::from_secs(30),
+            access_log: Some("-".to_string()),
+            log_format: LogFormat::Combined,
+            tls_port: 4443,
+            tls_cert: None,
+            tls_key: None,
+            proxies: Vec::new(),
+            balance: Balance::RoundRobin,
+            proxy_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Config {
+    // options given on the command line win over the ones from --config
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let options = parse_args(args)?;
+        let mut config = Self::default();
+
+        for (name, value) in &options {
+            if name == "config" {
+                let path = value.as_deref().unwrap_or("");
+                let contents =
+                    fs::read_to_string(path).map_err(|e| format!("config file {path}: {e}"))?;
+                for (name, value) in parse_file(&contents)? {
+                    config.set(&name, value.as_deref())?;
+                }
+            }
+        }
+
+        for (name, value) in &options {
+            if name != "config" {
+                config.set(name, value.as_deref())?;
+            }
+        }
+
+        if config.workers == 0 {
+            return Err("--workers has to be at least 1".to_string());
+        }
+        if config.tls_cert.is_some() != config.tls_key.is_some() {
+            return Err("--tls-cert and --tls-key go together".to_string());
+        }
+        Ok(config)
+    }
+
+    pub fn address(&self) -> String {
+        self.address_with(self.port)
+    }
+
+    pub fn tls_address(&self) -> String {
+        self.address_with(self.tls_port)
+    }
+
+    fn address_with(&self, port: u16) -> String {
+        // IPv6 addresses need brackets in front of the port
+        if self.bind.contains(':') {
+            format!("[{}]:{}", self.bind, port)
+        } else {
+            format!("{}:{}", self.bind, port)
+        }
+    }
+
+    fn set(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        if name == "list-directories" {
+            self.list_directories = match value {
+                None | Some("true") => true,
+                Some("false") => false,
+                Some(value) => {
+                    return Err(format!("--{name}: expected true or false, got {value}"))
+                }
+            };
+            return Ok(());
+        }
+
+        let value = value.ok_or_else(|| format!("--{name} needs a value"))?;
+        match name {
+            "bind" => self.bind = value.to_string(),
+            "port" => self.port = number(name, value)?,
+            "workers" => self.workers = number(name, value)?,
+            "max-streams" => self.max_streams = number(name, value)?,
+            "directory" => self.directory = value.to_string(),
+            "max-body" => self.limits.max_body_bytes = number(name, value)?,
+            "max-requests" => self.keep_alive.max_requests = number(name, value)?,
+            "idle-timeout" => self.timeouts.idle = seconds(name, value)?,
+            "header-timeout" => self.timeouts.header = seconds(name, value)?,
+            "read-timeout" => self.timeouts.read = seconds(name, value)?,
+            "write-timeout" => self.timeouts.write = seconds(name, value)?,
+            "ws-idle-timeout" => self.ws_idle_timeout = seconds(name, value)?,
+            "access-log" if value == "off" => self.access_log = None,
+            "access-log" => self.access_log = Some(value.to_string()),
+            "log-format" => {
+                self.log_format = LogFormat::parse(value)
+                    .ok_or_else(|| format!("--{name}: unknown format {value}"))?
+            }
+            "tls-port" => self.tls_port = number(name, value)?,
+            "tls-cert" => self.tls_cert = Some(value.to_string()),
+            "tls-key" => self.tls_key = Some(value.to_string()),
+            "proxy" => self.proxies.push(ProxyRoute::parse(value).ok_or_else(|| {
+                format!("--{name}: expected <prefix>=<host:port>[,<host:port>...], got {value}")
+            })?),
+            "balance" => {
+                self.balance = Balance::parse(value)
+                    .ok_or_else(|| format!("--{name}: unknown strategy {value}"))?
+            }
+            "proxy-timeout" => self.proxy_timeout = seconds(name, value)?,
+            _ => return Err(format!("unknown option --{name}")),
+        }
+        Ok(())
+    }
+}
+
+// --name value, --name=value and bare flags
+fn parse_args(args: &[String]) -> Result<Vec<(String, Option<String>)>, String> {
+    let mut options = Vec::new();
+    let mut args = args.iter().peekable();
+
+    while let Some(arg) = args.next() {
+        let option = arg
+            .strip_prefix("--")
+            .ok_or_else(|| format!("unexpected argument {arg}"))?;
+
+        if let Some((name, value)) = option.split_once('=') {
+            options.push((name.to_string(), Some(value.to_string())));
+        } else if option == "list-directories" {
+            options.push((option.to_string(), None));
+        } else {
+            let value = args.next_if(|next| !next.starts_with("--")).cloned();
+            options.push((option.to_string(), value));
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_file(contents: &str) -> Result<Vec<(String, Option<String>)>, String> {
+    let mut options = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("config file line {}: expected name = value", i + 1))?;
+        options.push((name.trim().to_string(), Some(value.trim().to_string())));
+    }
+
+    Ok(options)
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("--{name}: invalid number {value}"))
+}
+
+fn seconds(name: &str, value: &str) -> Result<Duration, String> {
+    let secs: u64 = number(name, value)?;
+    if secs == 0 {
+        return Err(format!("--{name} has to be at least 1 second"));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Result<Config, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Config::from_args(&args)
+    }
+
+    #[test]
+    fn parses_command_line_options() {
+        let config = config(&[
+            "--port",
+            "8080",
+            "--bind=::1",
+            "--list-directories",
+            "--idle-timeout",
+            "7",
+            "--access-log",
+            "off",
+        ])
+        .unwrap();
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.address(), "[::1]:8080");
+        assert_eq!(config.tls_address(), "[::1]:4443");
+        assert!(config.list_directories);
+        assert_eq!(config.timeouts.idle, Duration::from_secs(7));
+        assert!(config.access_log.is_none());
+        assert_eq!(config.workers, 4);
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        for args in [
+            &["--port", "http"][..],
+            &["--port", "70000"],
+            &["--port"],
+            &["--workers", "0"],
+            &["--idle-timeout", "0"],
+            &["--list-directories=maybe"],
+            &["--log-format", "xml"],
+            &["--tls-cert", "cert.pem"],
+            &["--colour"],
+            &["port", "80"],
+        ] {
+            assert!(config(args).is_err(), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn command_line_wins_over_the_config_file() {
+        let path = std::env::temp_dir().join(format!("config-{}", std::process::id()));
+        fs::write(
+            &path,
+            "# comment\n\n port = 9000 \nworkers=2\nlist-directories = false\n",
+        )
+        .unwrap();
+        let file = path.to_str().unwrap();
+
+        let merged = config(&["--workers", "8", "--config", file]).unwrap();
+        assert_eq!(merged.port, 9000);
+        assert_eq!(merged.workers, 8);
+        assert!(!merged.list_directories);
+
+        fs::write(&path, "port 9000\n").unwrap();
+        let err = config(&["--config", file]).err().unwrap();
+        assert_eq!(err, "config file line 1: expected name = value");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,391 @@
+// X25519 key agreement (RFC 7748) and Ed25519 signing (RFC 8032), following
+// the field arithmetic of TweetNaCl: an element of GF(2^255 - 19) is sixteen
+// 16-bit limbs held in i64 so products can be summed before carrying
+use crate::sha2::sha512;
+
+type Gf = [i64; 16];
+
+const GF0: Gf = [0; 16];
+const GF1: Gf = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const A24: Gf = [0xdb41, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+// twice the Edwards curve constant d, and the base point coordinates
+const D2: Gf = [
+    0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0, 0xd130, 0xeef3, 0x80f2, 0x198e,
+    0xfce7, 0x56df, 0xd9dc, 0x2406,
+];
+const BASE_X: Gf = [
+    0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c, 0xdc5c, 0xfdd6, 0xe231, 0xc0a4,
+    0x53fe, 0xcd6e, 0x36d3, 0x2169,
+];
+const BASE_Y: Gf = [
+    0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+    0x6666, 0x6666, 0x6666, 0x6666,
+];
+
+// the order of the base point, little endian
+const L: [i64; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+fn carry(o: &mut Gf) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1;
+        } else {
+            o[0] += 38 * (c - 1);
+        }
+        o[i] -= c << 16;
+    }
+}
+
+// swaps p and q when b is 1, without branching on it
+fn select(p: &mut Gf, q: &mut Gf, b: i64) {
+    let mask = !(b - 1);
+    for i in 0..16 {
+        let t = mask & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn pack(n: &Gf) -> [u8; 32] {
+    let mut t = *n;
+    carry(&mut t);
+    carry(&mut t);
+    carry(&mut t);
+
+    let mut m = GF0;
+    for _ in 0..2 {
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let b = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        select(&mut t, &mut m, 1 - b);
+    }
+
+    let mut out = [0u8; 32];
+    for i in 0..16 {
+        out[2 * i] = (t[i] & 0xff) as u8;
+        out[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    out
+}
+
+fn unpack(n: &[u8; 32]) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = i64::from(n[2 * i]) + (i64::from(n[2 * i + 1]) << 8);
+    }
+    o[15] &= 0x7fff;
+    o
+}
+
+fn add(a: &Gf, b: &Gf) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = a[i] + b[i];
+    }
+    o
+}
+
+fn sub(a: &Gf, b: &Gf) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = a[i] - b[i];
+    }
+    o
+}
+
+fn mul(a: &Gf, b: &Gf) -> Gf {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    // 2^256 is 38 modulo p
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+
+    let mut o = GF0;
+    o.copy_from_slice(&t[..16]);
+    carry(&mut o);
+    carry(&mut o);
+    o
+}
+
+fn square(a: &Gf) -> Gf {
+    mul(a, a)
+}
+
+// a^(p - 2)
+fn invert(a: &Gf) -> Gf {
+    let mut c = *a;
+    for i in (0..254).rev() {
+        c = square(&c);
+        if i != 2 && i != 4 {
+            c = mul(&c, a);
+        }
+    }
+    c
+}
+
+pub fn x25519(scalar: &[u8; 32], point: &[u8; 32]) -> [u8; 32] {
+    let mut z = *scalar;
+    z[31] = (z[31] & 127) | 64;
+    z[0] &= 248;
+
+    let x = unpack(point);
+    let (mut a, mut b, mut c, mut d) = (GF1, x, GF0, GF1);
+
+    for i in (0..255).rev() {
+        let bit = i64::from((z[i >> 3] >> (i & 7)) & 1);
+        select(&mut a, &mut b, bit);
+        select(&mut c, &mut d, bit);
+
+        let e = add(&a, &c);
+        a = sub(&a, &c);
+        c = add(&b, &d);
+        b = sub(&b, &d);
+        d = square(&e);
+        let f = square(&a);
+        a = mul(&c, &a);
+        c = mul(&b, &e);
+        let e = add(&a, &c);
+        a = sub(&a, &c);
+        b = square(&a);
+        c = sub(&d, &f);
+        a = mul(&c, &A24);
+        a = add(&a, &d);
+        c = mul(&c, &a);
+        a = mul(&d, &f);
+        d = mul(&b, &x);
+        b = square(&e);
+
+        select(&mut a, &mut b, bit);
+        select(&mut c, &mut d, bit);
+    }
+
+    pack(&mul(&a, &invert(&c)))
+}
+
+pub fn x25519_base(scalar: &[u8; 32]) -> [u8; 32] {
+    let mut base = [0u8; 32];
+    base[0] = 9;
+    x25519(scalar, &base)
+}
+
+// a point in extended coordinates (X, Y, Z, T)
+type Point = [Gf; 4];
+
+fn point_add(p: &Point, q: &Point) -> Point {
+    let a = mul(&sub(&p[1], &p[0]), &sub(&q[1], &q[0]));
+    let b = mul(&add(&p[0], &p[1]), &add(&q[0], &q[1]));
+    let c = mul(&mul(&p[3], &q[3]), &D2);
+    let d = mul(&p[2], &q[2]);
+    let d = add(&d, &d);
+    let e = sub(&b, &a);
+    let f = sub(&d, &c);
+    let g = add(&d, &c);
+    let h = add(&b, &a);
+
+    [mul(&e, &f), mul(&h, &g), mul(&g, &f), mul(&e, &h)]
+}
+
+fn point_swap(p: &mut Point, q: &mut Point, b: i64) {
+    for i in 0..4 {
+        select(&mut p[i], &mut q[i], b);
+    }
+}
+
+fn point_pack(p: &Point) -> [u8; 32] {
+    let zi = invert(&p[2]);
+    let x = mul(&p[0], &zi);
+    let y = mul(&p[1], &zi);
+
+    let mut out = pack(&y);
+    out[31] ^= (pack(&x)[0] & 1) << 7;
+    out
+}
+
+// scalar times the base point
+fn scalar_base(s: &[u8; 32]) -> Point {
+    let mut p: Point = [GF0, GF1, GF1, GF0];
+    let mut q: Point = [BASE_X, BASE_Y, GF1, mul(&BASE_X, &BASE_Y)];
+
+    for i in (0..256).rev() {
+        let bit = i64::from((s[i / 8] >> (i & 7)) & 1);
+        point_swap(&mut p, &mut q, bit);
+        q = point_add(&q, &p);
+        p = point_add(&p, &p);
+        point_swap(&mut p, &mut q, bit);
+    }
+    p
+}
+
+fn mod_l(x: &mut [i64; 64]) -> [u8; 32] {
+    for i in (32..64).rev() {
+        let mut carry = 0;
+        let mut j = i - 32;
+        while j < i - 12 {
+            x[j] += carry - 16 * x[i] * L[j - (i - 32)];
+            carry = (x[j] + 128) >> 8;
+            x[j] -= carry << 8;
+            j += 1;
+        }
+        x[j] += carry;
+        x[i] = 0;
+    }
+
+    let mut carry = 0;
+    for j in 0..32 {
+        x[j] += carry - (x[31] >> 4) * L[j];
+        carry = x[j] >> 8;
+        x[j] &= 255;
+    }
+    for j in 0..32 {
+        x[j] -= carry * L[j];
+    }
+
+    let mut r = [0u8; 32];
+    for i in 0..32 {
+        x[i + 1] += x[i] >> 8;
+        r[i] = (x[i] & 255) as u8;
+    }
+    r
+}
+
+fn reduce(hash: &[u8; 64]) -> [u8; 32] {
+    let mut x = [0i64; 64];
+    for i in 0..64 {
+        x[i] = i64::from(hash[i]);
+    }
+    mod_l(&mut x)
+}
+
+fn clamp(hash: &[u8; 64]) -> [u8; 32] {
+    let mut a = [0u8; 32];
+    a.copy_from_slice(&hash[..32]);
+    a[0] &= 248;
+    a[31] &= 127;
+    a[31] |= 64;
+    a
+}
+
+pub struct Ed25519Key {
+    seed: [u8; 32],
+    public: [u8; 32],
+}
+
+impl Ed25519Key {
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        let public = point_pack(&scalar_base(&clamp(&sha512(&seed))));
+        Self { seed, public }
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        let hash = sha512(&self.seed);
+        let a = clamp(&hash);
+
+        let mut prefixed = hash[32..].to_vec();
+        prefixed.extend_from_slice(message);
+        let r = reduce(&sha512(&prefixed));
+        let big_r = point_pack(&scalar_base(&r));
+
+        let mut challenge = big_r.to_vec();
+        challenge.extend_from_slice(&self.public);
+        challenge.extend_from_slice(message);
+        let k = reduce(&sha512(&challenge));
+
+        // s = r + k * a modulo L
+        let mut x = [0i64; 64];
+        for i in 0..32 {
+            x[i] = i64::from(r[i]);
+        }
+        for i in 0..32 {
+            for j in 0..32 {
+                x[i + j] += i64::from(k[i]) * i64::from(a[j]);
+            }
+        }
+        let s = mod_l(&mut x);
+
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(&big_r);
+        signature[32..].copy_from_slice(&s);
+        signature
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex32(digits: &str) -> [u8; 32] {
+        let bytes: Vec<u8> = (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+            .collect();
+        bytes.try_into().unwrap()
+    }
+
+    // RFC 7748 section 5.2
+    #[test]
+    fn multiplies_points() {
+        let scalar = hex32("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4");
+        let point = hex32("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c");
+        assert_eq!(
+            x25519(&scalar, &point),
+            hex32("c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552")
+        );
+    }
+
+    // RFC 7748 section 6.1
+    #[test]
+    fn agrees_on_a_shared_secret() {
+        let alice = hex32("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob = hex32("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        let alice_public = x25519_base(&alice);
+        let bob_public = x25519_base(&bob);
+        assert_eq!(
+            alice_public,
+            hex32("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+        );
+        assert_eq!(
+            bob_public,
+            hex32("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+        );
+        let shared = hex32("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+        assert_eq!(x25519(&alice, &bob_public), shared);
+        assert_eq!(x25519(&bob, &alice_public), shared);
+    }
+
+    // RFC 8032 section 7.1, test 1
+    #[test]
+    fn signs_with_ed25519() {
+        let key = Ed25519Key::from_seed(hex32(
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+        ));
+        assert_eq!(
+            key.public,
+            hex32("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a")
+        );
+        let signature = key.sign(b"");
+        assert_eq!(
+            signature[..32],
+            hex32("e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155")
+        );
+        assert_eq!(
+            signature[32..],
+            hex32("5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b")
+        );
+    }
+}
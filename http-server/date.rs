@@ -0,0 +1,168 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// IMF-fixdate, e.g. Sun, 06 Nov 1994 08:49:37 GMT
+pub fn http_date(time: SystemTime) -> String {
+    let seconds = unix_seconds(time);
+    let (year, month, day, hour, minute, second) = civil(seconds);
+    format!(
+        "{}, {day:02} {} {year:04} {hour:02}:{minute:02}:{second:02} GMT",
+        WEEKDAYS[seconds.div_euclid(86400).rem_euclid(7) as usize],
+        MONTHS[month as usize - 1]
+    )
+}
+
+// accepts IMF-fixdate as well as the obsolete RFC 850 and asctime formats
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let tokens: Vec<&str> = value
+        .split([' ', ',', '-'])
+        .filter(|token| !token.is_empty())
+        .collect();
+
+    let month = tokens
+        .iter()
+        .find_map(|token| MONTHS.iter().position(|month| month == token))?;
+    let time: Vec<u32> = tokens
+        .iter()
+        .find(|token| token.contains(':'))?
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    let numbers: Vec<&str> = tokens
+        .iter()
+        .copied()
+        .filter(|token| token.bytes().all(|b| b.is_ascii_digit()))
+        .collect();
+
+    if time.len() != 3 || numbers.len() != 2 {
+        return None;
+    }
+
+    let day: u32 = numbers[0].parse().ok()?;
+    let mut year: i64 = numbers[1].parse().ok()?;
+    if numbers[1].len() == 2 {
+        year += if year < 70 { 2000 } else { 1900 };
+    }
+
+    // four digit years, anything past them would overflow the arithmetic below
+    if !(1..=9999).contains(&year) {
+        return None;
+    }
+    if !(1..=31).contains(&day) || time[0] > 23 || time[1] > 59 || time[2] > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month as u32 + 1, day);
+    let seconds = days * 86400 + (time[0] * 3600 + time[1] * 60 + time[2]) as i64;
+    if seconds < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(seconds as u64))
+}
+
+// 2024-05-01T12:30:00Z
+pub fn iso8601(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = civil(unix_seconds(time));
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z")
+}
+
+// Common Log Format, e.g. 10/Oct/2000:13:55:36 +0000
+pub fn clf_date(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = civil(unix_seconds(time));
+    format!(
+        "{day:02}/{}/{year:04}:{hour:02}:{minute:02}:{second:02} +0000",
+        MONTHS[month as usize - 1]
+    )
+}
+
+fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+// proleptic gregorian date and time of day for seconds since the epoch
+fn civil(seconds: i64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = seconds.div_euclid(86400);
+    let time_of_day = seconds.rem_euclid(86400);
+
+    // shift the epoch to 0000-03-01 so leap days fall at the end of a year
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (
+        year,
+        month as u32,
+        day as u32,
+        (time_of_day / 3600) as u32,
+        (time_of_day % 3600 / 60) as u32,
+        (time_of_day % 60) as u32,
+    )
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * mp + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1994-11-06T08:49:37Z
+    const EXAMPLE: u64 = 784111777;
+
+    #[test]
+    fn formats_imf_fixdate() {
+        let time = UNIX_EPOCH + Duration::from_secs(EXAMPLE);
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(iso8601(time), "1994-11-06T08:49:37Z");
+        assert_eq!(clf_date(time), "06/Nov/1994:08:49:37 +0000");
+    }
+
+    #[test]
+    fn parses_all_three_formats() {
+        let time = Some(UNIX_EPOCH + Duration::from_secs(EXAMPLE));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), time);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), time);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), time);
+    }
+
+    #[test]
+    fn round_trips_leap_days() {
+        let time = parse_http_date("Thu, 29 Feb 2024 23:59:59 GMT").unwrap();
+        assert_eq!(http_date(time), "Thu, 29 Feb 2024 23:59:59 GMT");
+    }
+
+    #[test]
+    fn rejects_out_of_range_dates() {
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 99999999999999 08:49:37 GMT"),
+            None
+        );
+        assert_eq!(parse_http_date("Sun, 06 Nov 10000 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1969 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 24:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49 GMT"), None);
+        assert!(parse_http_date("Fri, 31 Dec 9999 23:59:59 GMT").is_some());
+    }
+}
@@ -0,0 +1,981 @@
+// DEFLATE (RFC 1951) encoder and the gzip (RFC 1952) container
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Read};
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+const MAX_CHAIN: usize = 128;
+const NO_POS: usize = usize::MAX;
+
+const BLOCK_TOKENS: usize = 16 * 1024;
+const MAX_STORED: usize = 65535;
+const END_OF_BLOCK: usize = 256;
+const STREAM_CHUNK: usize = 64 * 1024;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    Encoder::new(Container::Gzip).encode_all(data)
+}
+
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    Encoder::new(Container::Zlib).encode_all(data)
+}
+
+pub enum Container {
+    Gzip,
+    Zlib,
+}
+
+// compresses input piece by piece, each piece becomes its own set of blocks
+pub struct Encoder {
+    container: Container,
+    writer: BitWriter,
+    crc: u32,
+    adler: u32,
+    len: u32,
+}
+
+impl Encoder {
+    pub fn new(container: Container) -> Self {
+        Self {
+            container,
+            writer: BitWriter::new(),
+            crc: 0,
+            adler: 1,
+            len: 0,
+        }
+    }
+
+    pub fn header(&self) -> Vec<u8> {
+        match self.container {
+            // magic, CM=deflate, no flags, no mtime, no extra flags, OS unknown
+            Container::Gzip => vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255],
+            // CM=deflate with a 32K window, default level, FCHECK so the header is a multiple of 31
+            Container::Zlib => vec![0x78, 0x9c],
+        }
+    }
+
+    // returns the bytes completed so far, a partial byte stays behind until the next call
+    pub fn write(&mut self, data: &[u8], last: bool) -> Vec<u8> {
+        self.crc = crc32_update(self.crc, data);
+        self.adler = adler32_update(self.adler, data);
+        self.len = self.len.wrapping_add(data.len() as u32);
+
+        deflate_into(&mut self.writer, data, last);
+        self.writer.take_bytes()
+    }
+
+    // flushes the final partial byte and appends the checksum trailer
+    pub fn trailer(mut self) -> Vec<u8> {
+        self.writer.align_to_byte();
+        let mut out = self.writer.take_bytes();
+        match self.container {
+            Container::Gzip => {
+                out.extend(self.crc.to_le_bytes());
+                out.extend(self.len.to_le_bytes());
+            }
+            Container::Zlib => out.extend(self.adler.to_be_bytes()),
+        }
+        out
+    }
+
+    fn encode_all(mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = self.header();
+        out.extend(self.write(data, true));
+        out.extend(self.trailer());
+        out
+    }
+}
+
+// streams a compressed copy of source, reading it STREAM_CHUNK bytes at a time
+pub struct EncoderReader<R: Read> {
+    source: R,
+    encoder: Option<Encoder>,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> EncoderReader<R> {
+    pub fn new(container: Container, source: R) -> Self {
+        let encoder = Encoder::new(container);
+        Self {
+            source,
+            buffer: encoder.header(),
+            encoder: Some(encoder),
+            pos: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for EncoderReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buffer.len() {
+            let mut encoder = match self.encoder.take() {
+                Some(encoder) => encoder,
+                None => return Ok(0),
+            };
+
+            let mut chunk = Vec::with_capacity(STREAM_CHUNK);
+            (&mut self.source)
+                .take(STREAM_CHUNK as u64)
+                .read_to_end(&mut chunk)?;
+
+            self.pos = 0;
+            if chunk.is_empty() {
+                self.buffer = encoder.write(&[], true);
+                self.buffer.extend(encoder.trailer());
+            } else {
+                self.buffer = encoder.write(&chunk, false);
+                self.encoder = Some(encoder);
+            }
+        }
+
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn adler32_update(adler: u32, data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let mut a = adler & 0xffff;
+    let mut b = adler >> 16;
+    // 5552 is the largest run that can't overflow b before taking the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = crc ^ 0xffff_ffff;
+    for &byte in data {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc ^ 0xffff_ffff
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+// last marks the final block of the stream
+fn deflate_into(writer: &mut BitWriter, data: &[u8], last: bool) {
+    let tokens = tokenize(data);
+
+    if tokens.is_empty() {
+        write_fixed_block(writer, &[], last);
+        return;
+    }
+
+    let mut start = 0;
+    let mut blocks = tokens.chunks(BLOCK_TOKENS).peekable();
+    while let Some(block) = blocks.next() {
+        let is_final = last && blocks.peek().is_none();
+        let raw_len: usize = block.iter().map(|token| token.raw_len()).sum();
+        let raw = &data[start..start + raw_len];
+        start += raw_len;
+
+        let (lit_freqs, dist_freqs) = frequencies(block);
+        let dynamic = DynamicCodes::new(&lit_freqs, &dist_freqs);
+
+        let stored_cost = stored_cost(raw.len());
+        let fixed_cost = fixed_cost(&lit_freqs, &dist_freqs);
+        let dynamic_cost = dynamic.cost(&lit_freqs, &dist_freqs);
+
+        if stored_cost <= fixed_cost && stored_cost <= dynamic_cost {
+            write_stored_blocks(writer, raw, is_final);
+        } else if fixed_cost <= dynamic_cost {
+            write_fixed_block(writer, block, is_final);
+        } else {
+            dynamic.write_block(writer, block, is_final);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { len: u16, dist: u16 },
+}
+
+impl Token {
+    fn raw_len(&self) -> usize {
+        match *self {
+            Token::Literal(_) => 1,
+            Token::Match { len, .. } => len as usize,
+        }
+    }
+}
+
+fn length_code(len: u16) -> usize {
+    LENGTH_BASE.partition_point(|&base| base <= len) - 1
+}
+
+fn dist_code(dist: u16) -> usize {
+    DIST_BASE.partition_point(|&base| base <= dist) - 1
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let h = ((data[pos] as usize) << 10) ^ ((data[pos + 1] as usize) << 5) ^ data[pos + 2] as usize;
+    h & ((1 << HASH_BITS) - 1)
+}
+
+// greedy LZ77 with hash chains over the last WINDOW_SIZE bytes
+fn tokenize(data: &[u8]) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut head = vec![NO_POS; 1 << HASH_BITS];
+    let mut prev = vec![NO_POS; WINDOW_SIZE];
+
+    let insert = |pos: usize, head: &mut Vec<usize>, prev: &mut Vec<usize>| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(data, pos);
+            prev[pos & WINDOW_MASK] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+
+        if pos + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(data, pos)];
+            let mut chain = 0;
+
+            while candidate != NO_POS
+                && candidate < pos
+                && pos - candidate <= WINDOW_SIZE
+                && chain < MAX_CHAIN
+            {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+
+                let next = prev[candidate & WINDOW_MASK];
+                if next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            tokens.push(Token::Match {
+                len: best_len as u16,
+                dist: best_dist as u16,
+            });
+            for p in pos..pos + best_len {
+                insert(p, &mut head, &mut prev);
+            }
+            pos += best_len;
+        } else {
+            tokens.push(Token::Literal(data[pos]));
+            insert(pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+
+    tokens
+}
+
+fn frequencies(tokens: &[Token]) -> ([u32; 286], [u32; 30]) {
+    let mut lit_freqs = [0u32; 286];
+    let mut dist_freqs = [0u32; 30];
+
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => lit_freqs[byte as usize] += 1,
+            Token::Match { len, dist } => {
+                lit_freqs[257 + length_code(len)] += 1;
+                dist_freqs[dist_code(dist)] += 1;
+            }
+        }
+    }
+    lit_freqs[END_OF_BLOCK] += 1;
+
+    (lit_freqs, dist_freqs)
+}
+
+fn extra_bits_cost(lit_freqs: &[u32], dist_freqs: &[u32]) -> usize {
+    let len_extra: usize = (0..29)
+        .map(|i| lit_freqs[257 + i] as usize * LENGTH_EXTRA[i] as usize)
+        .sum();
+    let dist_extra: usize = (0..30)
+        .map(|i| dist_freqs[i] as usize * DIST_EXTRA[i] as usize)
+        .sum();
+    len_extra + dist_extra
+}
+
+fn symbols_cost(freqs: &[u32], lengths: &[u8]) -> usize {
+    freqs
+        .iter()
+        .zip(lengths)
+        .map(|(&freq, &len)| freq as usize * len as usize)
+        .sum()
+}
+
+fn stored_cost(len: usize) -> usize {
+    let blocks = len.div_ceil(MAX_STORED).max(1);
+    // header bits plus worst case alignment, LEN and NLEN per block
+    blocks * (3 + 7 + 32) + len * 8
+}
+
+fn fixed_cost(lit_freqs: &[u32], dist_freqs: &[u32]) -> usize {
+    let (lit_lengths, dist_lengths) = fixed_lengths();
+    3 + symbols_cost(lit_freqs, &lit_lengths)
+        + symbols_cost(dist_freqs, &dist_lengths)
+        + extra_bits_cost(lit_freqs, dist_freqs)
+}
+
+fn fixed_lengths() -> ([u8; 288], [u8; 30]) {
+    let mut lit_lengths = [0u8; 288];
+    for (i, len) in lit_lengths.iter_mut().enumerate() {
+        *len = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (lit_lengths, [5u8; 30])
+}
+
+fn write_stored_blocks(writer: &mut BitWriter, raw: &[u8], is_final: bool) {
+    if raw.is_empty() {
+        writer.write_bits(is_final as u32, 1);
+        writer.write_bits(0b00, 2);
+        writer.align_to_byte();
+        writer.write_bytes(&[0x00, 0x00, 0xff, 0xff]);
+        return;
+    }
+
+    let mut parts = raw.chunks(MAX_STORED).peekable();
+    while let Some(part) = parts.next() {
+        let last = is_final && parts.peek().is_none();
+        writer.write_bits(last as u32, 1);
+        writer.write_bits(0b00, 2);
+        writer.align_to_byte();
+
+        let len = part.len() as u16;
+        writer.write_bytes(&len.to_le_bytes());
+        writer.write_bytes(&(!len).to_le_bytes());
+        writer.write_bytes(part);
+    }
+}
+
+fn write_fixed_block(writer: &mut BitWriter, tokens: &[Token], is_final: bool) {
+    let (lit_lengths, dist_lengths) = fixed_lengths();
+    let lit_codes = canonical_codes(&lit_lengths);
+    let dist_codes = canonical_codes(&dist_lengths);
+
+    writer.write_bits(is_final as u32, 1);
+    writer.write_bits(0b01, 2);
+    write_tokens(
+        writer,
+        tokens,
+        (&lit_codes, &lit_lengths),
+        (&dist_codes, &dist_lengths),
+    );
+}
+
+fn write_tokens(
+    writer: &mut BitWriter,
+    tokens: &[Token],
+    lit: (&[u16], &[u8]),
+    dist: (&[u16], &[u8]),
+) {
+    let (lit_codes, lit_lengths) = lit;
+    let (dist_codes, dist_lengths) = dist;
+
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => {
+                let sym = byte as usize;
+                writer.write_bits(lit_codes[sym] as u32, lit_lengths[sym] as u32);
+            }
+            Token::Match { len, dist } => {
+                let len_idx = length_code(len);
+                let sym = 257 + len_idx;
+                writer.write_bits(lit_codes[sym] as u32, lit_lengths[sym] as u32);
+                writer.write_bits(
+                    (len - LENGTH_BASE[len_idx]) as u32,
+                    LENGTH_EXTRA[len_idx] as u32,
+                );
+
+                let dist_idx = dist_code(dist);
+                writer.write_bits(dist_codes[dist_idx] as u32, dist_lengths[dist_idx] as u32);
+                writer.write_bits(
+                    (dist - DIST_BASE[dist_idx]) as u32,
+                    DIST_EXTRA[dist_idx] as u32,
+                );
+            }
+        }
+    }
+
+    writer.write_bits(
+        lit_codes[END_OF_BLOCK] as u32,
+        lit_lengths[END_OF_BLOCK] as u32,
+    );
+}
+
+struct DynamicCodes {
+    lit_lengths: Vec<u8>,
+    dist_lengths: Vec<u8>,
+    // code length alphabet symbol with its extra bits value
+    cl_symbols: Vec<(u8, u8)>,
+    cl_lengths: Vec<u8>,
+    hclen: usize,
+}
+
+impl DynamicCodes {
+    fn new(lit_freqs: &[u32], dist_freqs: &[u32]) -> Self {
+        let lit_lengths = limited_code_lengths(lit_freqs, 15);
+        let dist_lengths = limited_code_lengths(dist_freqs, 15);
+
+        let hlit = last_used(&lit_lengths).max(257);
+        let hdist = last_used(&dist_lengths).max(1);
+
+        let mut all_lengths = lit_lengths[..hlit].to_vec();
+        all_lengths.extend_from_slice(&dist_lengths[..hdist]);
+        let cl_symbols = rle_code_lengths(&all_lengths);
+
+        let mut cl_freqs = [0u32; 19];
+        for &(sym, _) in &cl_symbols {
+            cl_freqs[sym as usize] += 1;
+        }
+        let cl_lengths = limited_code_lengths(&cl_freqs, 7);
+
+        let hclen = CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&sym| cl_lengths[sym] != 0)
+            .map_or(4, |i| (i + 1).max(4));
+
+        Self {
+            lit_lengths: lit_lengths[..hlit].to_vec(),
+            dist_lengths: dist_lengths[..hdist].to_vec(),
+            cl_symbols,
+            cl_lengths,
+            hclen,
+        }
+    }
+
+    fn cost(&self, lit_freqs: &[u32], dist_freqs: &[u32]) -> usize {
+        let header = 3 + 5 + 5 + 4 + 3 * self.hclen;
+        let code_lengths: usize = self
+            .cl_symbols
+            .iter()
+            .map(|&(sym, _)| self.cl_lengths[sym as usize] as usize + cl_extra_bits(sym) as usize)
+            .sum();
+
+        header
+            + code_lengths
+            + symbols_cost(lit_freqs, &self.lit_lengths)
+            + symbols_cost(dist_freqs, &self.dist_lengths)
+            + extra_bits_cost(lit_freqs, dist_freqs)
+    }
+
+    fn write_block(&self, writer: &mut BitWriter, tokens: &[Token], is_final: bool) {
+        writer.write_bits(is_final as u32, 1);
+        writer.write_bits(0b10, 2);
+        writer.write_bits((self.lit_lengths.len() - 257) as u32, 5);
+        writer.write_bits((self.dist_lengths.len() - 1) as u32, 5);
+        writer.write_bits((self.hclen - 4) as u32, 4);
+
+        for &sym in &CODE_LENGTH_ORDER[..self.hclen] {
+            writer.write_bits(self.cl_lengths[sym] as u32, 3);
+        }
+
+        let cl_codes = canonical_codes(&self.cl_lengths);
+        for &(sym, extra) in &self.cl_symbols {
+            let sym = sym as usize;
+            writer.write_bits(cl_codes[sym] as u32, self.cl_lengths[sym] as u32);
+            writer.write_bits(extra as u32, cl_extra_bits(sym as u8));
+        }
+
+        let lit_codes = canonical_codes(&self.lit_lengths);
+        let dist_codes = canonical_codes(&self.dist_lengths);
+        write_tokens(
+            writer,
+            tokens,
+            (&lit_codes, &self.lit_lengths),
+            (&dist_codes, &self.dist_lengths),
+        );
+    }
+}
+
+fn last_used(lengths: &[u8]) -> usize {
+    lengths
+        .iter()
+        .rposition(|&len| len != 0)
+        .map_or(0, |i| i + 1)
+}
+
+fn cl_extra_bits(sym: u8) -> u32 {
+    match sym {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+// run-length encodes code lengths with the repeat symbols 16, 17 and 18
+fn rle_code_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut symbols: Vec<(u8, u8)> = Vec::new();
+    let mut i = 0;
+
+    while i < lengths.len() {
+        let len = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&l| l == len).count();
+        i += run;
+
+        if len == 0 {
+            while run >= 11 {
+                let take = run.min(138);
+                symbols.push((18, (take - 11) as u8));
+                run -= take;
+            }
+            if run >= 3 {
+                symbols.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            symbols.push((len, 0));
+            run -= 1;
+            while run >= 3 {
+                let take = run.min(6);
+                symbols.push((16, (take - 3) as u8));
+                run -= take;
+            }
+        }
+
+        for _ in 0..run {
+            symbols.push((len, 0));
+        }
+    }
+
+    symbols
+}
+
+// huffman code lengths, with frequencies flattened until no code exceeds max_bits
+fn limited_code_lengths(freqs: &[u32], max_bits: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+
+    // decoders expect at least two codes in a tree
+    let mut used = freqs.iter().filter(|&&f| f > 0).count();
+    for f in freqs.iter_mut() {
+        if used >= 2 {
+            break;
+        }
+        if *f == 0 {
+            *f = 1;
+            used += 1;
+        }
+    }
+
+    loop {
+        let lengths = code_lengths(&freqs);
+        if lengths.iter().all(|&len| len <= max_bits) {
+            return lengths;
+        }
+        for f in freqs.iter_mut().filter(|f| **f > 0) {
+            *f = f.div_ceil(2);
+        }
+    }
+}
+
+fn code_lengths(freqs: &[u32]) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+    let mut parents: Vec<usize> = Vec::new();
+    let mut heap = BinaryHeap::new();
+
+    // leaves come first, node i of parents belongs to leaf symbols[i]
+    let symbols: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] > 0).collect();
+    for (node, &sym) in symbols.iter().enumerate() {
+        heap.push(Reverse((freqs[sym] as u64, node)));
+        parents.push(NO_POS);
+    }
+
+    while heap.len() > 1 {
+        let Reverse((weight_a, a)) = heap.pop().unwrap();
+        let Reverse((weight_b, b)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(NO_POS);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(Reverse((weight_a + weight_b, node)));
+    }
+
+    for (node, &sym) in symbols.iter().enumerate() {
+        let mut depth = 0;
+        let mut current = node;
+        while parents[current] != NO_POS {
+            current = parents[current];
+            depth += 1;
+        }
+        lengths[sym] = depth.min(u8::MAX as usize) as u8;
+    }
+
+    lengths
+}
+
+// canonical huffman codes, bit-reversed since deflate packs codes LSB first
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut bl_count = [0u16; 16];
+    for &len in lengths {
+        if len > 0 {
+            bl_count[len as usize] += 1;
+        }
+    }
+
+    let mut next_code = [0u16; 16];
+    let mut code = 0u16;
+    for bits in 1..16 {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                return 0;
+            }
+            let code = next_code[len as usize];
+            next_code[len as usize] += 1;
+            code.reverse_bits() >> (16 - len)
+        })
+        .collect()
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bit_buf: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            out: Vec::new(),
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bit_buf |= (value as u64) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.out.push(self.bit_buf as u8);
+            self.bit_buf >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn align_to_byte(&mut self) {
+        if self.bit_count > 0 {
+            self.out.push(self.bit_buf as u8);
+            self.bit_buf = 0;
+            self.bit_count = 0;
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // minimal inflater, just enough to read back what the encoder writes
+    struct Bits<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl Bits<'_> {
+        fn bits(&mut self, count: u8) -> usize {
+            (0..count).fold(0, |value, i| {
+                let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+                self.pos += 1;
+                value | (bit as usize) << i
+            })
+        }
+
+        fn symbol(&mut self, code: &Code) -> usize {
+            let (mut value, mut first, mut index) = (0, 0, 0);
+            for &count in &code.counts[1..] {
+                value |= self.bits(1);
+                if value - first < count {
+                    return code.symbols[index + value - first];
+                }
+                index += count;
+                first = (first + count) << 1;
+                value <<= 1;
+            }
+            panic!("invalid huffman code");
+        }
+    }
+
+    struct Code {
+        counts: [usize; 16],
+        symbols: Vec<usize>,
+    }
+
+    impl Code {
+        fn new(lengths: &[u8]) -> Self {
+            let mut counts = [0; 16];
+            for &len in lengths {
+                counts[len as usize] += 1;
+            }
+            counts[0] = 0;
+            let symbols = (1..16)
+                .flat_map(|len| (0..lengths.len()).filter(move |&s| lengths[s] == len))
+                .collect();
+            Self { counts, symbols }
+        }
+    }
+
+    fn inflate(data: &[u8]) -> (Vec<u8>, usize) {
+        let mut input = Bits { data, pos: 0 };
+        let mut out = Vec::new();
+        loop {
+            let last = input.bits(1) == 1;
+            let (literals, distances) = match input.bits(2) {
+                0 => {
+                    let start = input.pos.div_ceil(8);
+                    let len = u16::from_le_bytes([data[start], data[start + 1]]) as usize;
+                    let nlen = u16::from_le_bytes([data[start + 2], data[start + 3]]) as usize;
+                    assert!(len == !nlen & 0xffff);
+                    out.extend(&data[start + 4..start + 4 + len]);
+                    input.pos = (start + 4 + len) * 8;
+                    if last {
+                        break;
+                    }
+                    continue;
+                }
+                1 => {
+                    let mut lengths = [8; 288];
+                    lengths[144..256].fill(9);
+                    lengths[256..280].fill(7);
+                    (Code::new(&lengths), Code::new(&[5; 30]))
+                }
+                2 => {
+                    let literal_count = input.bits(5) + 257;
+                    let distance_count = input.bits(5) + 1;
+                    let code_length_count = input.bits(4) + 4;
+                    let mut code_lengths = [0; 19];
+                    for &i in &CODE_LENGTH_ORDER[..code_length_count] {
+                        code_lengths[i] = input.bits(3) as u8;
+                    }
+                    let code_lengths = Code::new(&code_lengths);
+
+                    let mut lengths = Vec::new();
+                    while lengths.len() < literal_count + distance_count {
+                        match input.symbol(&code_lengths) {
+                            len @ 0..=15 => lengths.push(len as u8),
+                            16 => {
+                                let previous = *lengths.last().unwrap();
+                                let repeat = 3 + input.bits(2);
+                                lengths.extend(std::iter::repeat_n(previous, repeat));
+                            }
+                            17 => {
+                                let repeat = 3 + input.bits(3);
+                                lengths.extend(std::iter::repeat_n(0, repeat));
+                            }
+                            _ => {
+                                let repeat = 11 + input.bits(7);
+                                lengths.extend(std::iter::repeat_n(0, repeat));
+                            }
+                        }
+                    }
+                    assert!(lengths.len() == literal_count + distance_count);
+                    (
+                        Code::new(&lengths[..literal_count]),
+                        Code::new(&lengths[literal_count..]),
+                    )
+                }
+                _ => panic!("reserved block type"),
+            };
+
+            loop {
+                let symbol = input.symbol(&literals);
+                if symbol < END_OF_BLOCK {
+                    out.push(symbol as u8);
+                    continue;
+                }
+                if symbol == END_OF_BLOCK {
+                    break;
+                }
+                let i = symbol - 257;
+                let len = LENGTH_BASE[i] as usize + input.bits(LENGTH_EXTRA[i]);
+                let d = input.symbol(&distances);
+                let distance = DIST_BASE[d] as usize + input.bits(DIST_EXTRA[d]);
+                assert!(distance <= out.len() && distance <= WINDOW_SIZE);
+                for _ in 0..len {
+                    out.push(out[out.len() - distance]);
+                }
+            }
+            if last {
+                break;
+            }
+        }
+        (out, input.pos.div_ceil(8))
+    }
+
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        assert!(data[..4] == [0x1f, 0x8b, 8, 0]);
+        let (out, used) = inflate(&data[10..]);
+        let trailer = &data[10 + used..];
+        assert!(trailer.len() == 8);
+        assert!(trailer[..4] == crc32_update(0, &out).to_le_bytes());
+        assert!(trailer[4..] == (out.len() as u32).to_le_bytes());
+        out
+    }
+
+    fn unzlib(data: &[u8]) -> Vec<u8> {
+        assert!(u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31));
+        let (out, used) = inflate(&data[2..]);
+        assert!(data[2 + used..] == adler32_update(1, &out).to_be_bytes());
+        out
+    }
+
+    fn samples() -> Vec<Vec<u8>> {
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let text = b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(3000);
+        let mixed: Vec<u8> = noise[..40_000]
+            .chunks(400)
+            .zip(text.chunks(300))
+            .flat_map(|(a, b)| [a, b].concat())
+            .collect();
+        vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"hello, hello, hello world".to_vec(),
+            vec![0; 70_000],
+            text,
+            noise,
+            mixed,
+        ]
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert!(crc32_update(0, b"123456789") == 0xcbf4_3926);
+        assert!(adler32_update(1, b"Wikipedia") == 0x11e6_0398);
+        // checksums can be fed in pieces
+        assert!(crc32_update(crc32_update(0, b"1234"), b"56789") == 0xcbf4_3926);
+    }
+
+    #[test]
+    fn gzip_and_zlib_round_trip() {
+        for sample in samples() {
+            assert!(gunzip(&gzip(&sample)) == sample);
+            assert!(unzlib(&zlib(&sample)) == sample);
+        }
+    }
+
+    #[test]
+    fn compresses_repetitive_input() {
+        let text = b"abcabcabcabc".repeat(1000);
+        assert!(gzip(&text).len() < text.len() / 20);
+        // incompressible input only grows by the stored block overhead
+        let noise = &samples()[5];
+        assert!(gzip(noise).len() < noise.len() + 64);
+    }
+
+    #[test]
+    fn streams_in_pieces() {
+        for sample in samples() {
+            let mut encoder = Encoder::new(Container::Gzip);
+            let mut out = encoder.header();
+            for piece in sample.chunks(7000) {
+                out.extend(encoder.write(piece, false));
+            }
+            out.extend(encoder.write(&[], true));
+            out.extend(encoder.trailer());
+            assert!(gunzip(&out) == sample);
+
+            let mut out = Vec::new();
+            EncoderReader::new(Container::Zlib, &sample[..])
+                .read_to_end(&mut out)
+                .unwrap();
+            assert!(unzlib(&out) == sample);
+        }
+    }
+}
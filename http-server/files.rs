@@ -0,0 +1,378 @@
+use crate::conditional::{self, Validators};
+use crate::date;
+use crate::mime::{self, MimeRegistry};
+use crate::range::{self, Ranges};
+use crate::response::{ContentType, Response};
+use crate::router::Context;
+use crate::statuscode::StatusCode;
+use crate::url;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+pub struct FileServer {
+    mime_types: MimeRegistry,
+    list_directories: bool,
+}
+
+impl FileServer {
+    pub fn new(list_directories: bool) -> Self {
+        Self {
+            mime_types: MimeRegistry::new(),
+            list_directories,
+        }
+    }
+
+    pub fn get(&self, ctx: &Context) -> Response {
+        let name = ctx.param("*");
+        let mut file_path = match sandboxed_path(ctx.directory, name, true) {
+            Ok(path) => path,
+            Err(status) => return Response::new(status, ContentType::TextPlain, ""),
+        };
+
+        if file_path.is_dir() {
+            let index = match name.trim_end_matches('/') {
+                "" => "index.html".to_string(),
+                dir => format!("{dir}/index.html"),
+            };
+            match sandboxed_path(ctx.directory, &index, true) {
+                Ok(path) if path.is_file() => file_path = path,
+                Ok(_) | Err(StatusCode::NotFound) if self.list_directories => {
+                    return list_directory(ctx, &file_path, name);
+                }
+                Ok(_) => return Response::new(StatusCode::NotFound, ContentType::TextPlain, ""),
+                Err(status) => return Response::new(status, ContentType::TextPlain, ""),
+            }
+        }
+
+        let validators = Validators::of(&file_path);
+        if let Some(status) = conditional::evaluate(ctx, validators.as_ref()) {
+            let not_modified = matches!(status, StatusCode::NotModified);
+            let mut response = Response::new(status, ContentType::TextPlain, "");
+            // a 304 carries the Vary the full response would have
+            if not_modified
+                && File::open(&file_path)
+                    .and_then(|mut file| self.content_type(&mut file, &file_path))
+                    .is_ok_and(|content_type| content_type.is_compressible())
+            {
+                response = response.with_header("Vary", "Accept-Encoding");
+            }
+            return with_validators(response, validators.as_ref());
+        }
+
+        match self.open(ctx, &file_path, validators.as_ref()) {
+            Ok(response) => {
+                with_validators(response, validators.as_ref()).with_header("Accept-Ranges", "bytes")
+            }
+            Err(e) => {
+                println!("err: {e}");
+                Response::new(StatusCode::NotFound, ContentType::TextPlain, "")
+            }
+        }
+    }
+
+    // the file is streamed from disk, never held in memory as a whole
+    fn open(
+        &self,
+        ctx: &Context,
+        path: &Path,
+        validators: Option<&Validators>,
+    ) -> io::Result<Response> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let content_type = self.content_type(&mut file, path)?;
+
+        let ranges = match ctx.header("Range") {
+            Some(header) if conditional::if_range_matches(ctx, validators) => {
+                range::parse(&header, len)
+            }
+            _ => None,
+        };
+
+        match ranges {
+            Some(Ranges::Satisfiable(ranges)) => range::partial(&ranges, path, len, content_type),
+            Some(Ranges::Unsatisfiable) => Ok(range::unsatisfiable(len)),
+            None => Ok(Response::stream(
+                StatusCode::Ok,
+                content_type,
+                Box::new(file),
+                len,
+            )),
+        }
+    }
+
+    // by the name, or by the first bytes when the name says nothing
+    fn content_type(&self, file: &mut File, path: &Path) -> io::Result<ContentType> {
+        let mut head: Vec<u8> = Vec::new();
+        file.take(mime::SNIFF_LEN).read_to_end(&mut head)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(self.mime_types.content_type(path, &head))
+    }
+}
+
+fn with_validators(response: Response, validators: Option<&Validators>) -> Response {
+    match validators {
+        Some(validators) => validators.apply(response),
+        None => response,
+    }
+}
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: String,
+}
+
+fn list_directory(ctx: &Context, dir: &Path, name: &str) -> Response {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) => {
+            println!("err: {e}");
+            return Response::new(StatusCode::InternalServerError, ContentType::TextPlain, "");
+        }
+    };
+
+    let mut entries: Vec<Entry> = read_dir
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some(Entry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().map(date::iso8601).unwrap_or_default(),
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    let base = name.trim_matches('/');
+    let href = |entry: &str| {
+        let path = if base.is_empty() {
+            entry.to_string()
+        } else {
+            format!("{base}/{entry}")
+        };
+        format!("/files/{}", url::percent_encode(&path))
+    };
+
+    let json = ctx.query("format") == Some("json")
+        || ctx
+            .header("Accept")
+            .is_some_and(|accept| accept.contains("application/json"));
+    if json {
+        let items: Vec<String> = entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{{\"name\":\"{}\",\"type\":\"{}\",\"size\":{},\"modified\":\"{}\"}}",
+                    json_escape(&entry.name),
+                    if entry.is_dir { "directory" } else { "file" },
+                    entry.size,
+                    entry.modified
+                )
+            })
+            .collect();
+        let body = format!("[{}]", items.join(","));
+        return Response::new(
+            StatusCode::Ok,
+            ContentType::Mime("application/json".to_string()),
+            body,
+        );
+    }
+
+    let title = html_escape(&format!("/{base}"));
+    let mut body = format!(
+        "<!doctype html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n"
+    );
+    if !base.is_empty() {
+        let parent = match base.rfind('/') {
+            Some(i) => format!("/files/{}", url::percent_encode(&base[..i])),
+            None => "/files/".to_string(),
+        };
+        body.push_str(&format!(
+            "<tr><td><a href=\"{parent}\">../</a></td><td></td><td></td></tr>\n"
+        ));
+    }
+    for entry in &entries {
+        let (suffix, size) = if entry.is_dir {
+            ("/", "-".to_string())
+        } else {
+            ("", entry.size.to_string())
+        };
+        body.push_str(&format!(
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            html_escape(&href(&entry.name)),
+            suffix,
+            html_escape(&entry.name),
+            suffix,
+            size,
+            entry.modified
+        ));
+    }
+    body.push_str("</table>\n</body>\n</html>\n");
+
+    Response::new(StatusCode::Ok, ContentType::TextHtml, body)
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+pub fn json_escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn post_file(ctx: &Context) -> Response {
+    // the name has to be a file's, POST /files/ would name the directory itself
+    let name = ctx.param("*");
+    if name.is_empty() || name.ends_with('/') {
+        return Response::new(StatusCode::BadRequest, ContentType::TextPlain, "");
+    }
+
+    let file_path = match sandboxed_path(ctx.directory, name, false) {
+        Ok(path) => path,
+        Err(status) => return Response::new(status, ContentType::TextPlain, ""),
+    };
+    if file_path.is_dir() {
+        return Response::new(StatusCode::Conflict, ContentType::TextPlain, "");
+    }
+
+    if let Some(status) = conditional::evaluate(ctx, Validators::of(&file_path).as_ref()) {
+        return Response::new(status, ContentType::TextPlain, "");
+    }
+
+    let result = fs::write(&file_path, ctx.body());
+    match result {
+        Ok(_result) => {
+            let response = Response::new(StatusCode::Created, ContentType::TextPlain, "");
+            with_validators(response, Validators::of(&file_path).as_ref())
+        }
+        Err(e) => {
+            println!("err: {e}");
+            Response::new(StatusCode::InternalServerError, ContentType::TextPlain, "")
+        }
+    }
+}
+
+// resolves name below root, following symlinks, and refuses anything that ends
+// up outside of it; must_exist is false for files that are about to be created
+fn sandboxed_path(root: &str, name: &str, must_exist: bool) -> Result<PathBuf, StatusCode> {
+    let root = fs::canonicalize(root).map_err(|e| {
+        println!("err: served directory {root}: {e}");
+        StatusCode::InternalServerError
+    })?;
+
+    let relative = Path::new(name);
+    let plain = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if !plain || name.contains('\0') {
+        return Err(StatusCode::Forbidden);
+    }
+
+    let candidate = root.join(relative);
+    let resolved = match fs::canonicalize(&candidate) {
+        Ok(path) => path,
+        Err(_) if must_exist => return Err(StatusCode::NotFound),
+        Err(_) => {
+            // a dangling symlink would let the write land wherever it points to
+            if fs::symlink_metadata(&candidate).is_ok() {
+                return Err(StatusCode::Forbidden);
+            }
+
+            let parent = candidate.parent().ok_or(StatusCode::Forbidden)?;
+            let file_name = candidate.file_name().ok_or(StatusCode::Forbidden)?;
+            fs::canonicalize(parent)
+                .map_err(|_| StatusCode::NotFound)?
+                .join(file_name)
+        }
+    };
+
+    if resolved.starts_with(&root) {
+        Ok(resolved)
+    } else {
+        Err(StatusCode::Forbidden)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    // a fresh directory tree: root/a.txt, root/sub/b.txt, a link out of root
+    // and one that stays inside
+    fn tree(name: &str) -> (PathBuf, String) {
+        let base = std::env::temp_dir().join(format!("files-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let root = base.join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("sub/b.txt"), "b").unwrap();
+        fs::write(base.join("secret"), "s").unwrap();
+        symlink(base.join("secret"), root.join("out")).unwrap();
+        symlink(root.join("sub/b.txt"), root.join("in")).unwrap();
+        symlink(base.join("missing"), root.join("dangling")).unwrap();
+        let root_str = root.to_str().unwrap().to_string();
+        (base, root_str)
+    }
+
+    fn resolve(root: &str, name: &str, must_exist: bool) -> Result<PathBuf, u16> {
+        sandboxed_path(root, name, must_exist).map_err(|status| status.code())
+    }
+
+    #[test]
+    fn resolves_names_below_the_root() {
+        let (base, root) = tree("below");
+        let root_path = fs::canonicalize(&root).unwrap();
+        assert_eq!(resolve(&root, "a.txt", true), Ok(root_path.join("a.txt")));
+        assert_eq!(
+            resolve(&root, "./sub/b.txt", true),
+            Ok(root_path.join("sub/b.txt"))
+        );
+        assert_eq!(resolve(&root, "in", true), Ok(root_path.join("sub/b.txt")));
+        assert_eq!(
+            resolve(&root, "new.txt", false),
+            Ok(root_path.join("new.txt"))
+        );
+        assert_eq!(resolve(&root, "missing.txt", true), Err(404));
+        assert_eq!(resolve(&root, "nodir/new.txt", false), Err(404));
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn refuses_names_that_leave_the_root() {
+        let (base, root) = tree("escape");
+        assert_eq!(resolve(&root, "../secret", true), Err(403));
+        assert_eq!(resolve(&root, "sub/../../secret", false), Err(403));
+        assert_eq!(resolve(&root, "/etc/passwd", true), Err(403));
+        assert_eq!(resolve(&root, "a\0.txt", true), Err(403));
+        assert_eq!(resolve(&root, "out", true), Err(403));
+        assert_eq!(resolve(&root, "dangling", false), Err(403));
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn escapes_listing_names() {
+        assert_eq!(
+            html_escape("<a href=\"x\">&'"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;"
+        );
+        assert_eq!(json_escape("a\"b\\c\n"), "a\\\"b\\\\c\\u000a");
+    }
+}
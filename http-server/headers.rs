@@ -0,0 +1,127 @@
+// header fields in the order they were received or added, names compare
+// case-insensitively and a name may occur more than once
+#[derive(Clone, Default)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self { fields: Vec::new() }
+    }
+
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+
+    // replaces every field of that name
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.fields
+            .retain(|(field, _)| !field.eq_ignore_ascii_case(name));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // repeated fields are combined into one comma separated value
+    pub fn combined(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self.get_all(name).collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(", "))
+        }
+    }
+
+    // the comma separated elements of every field of that name
+    pub fn list<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .map(|element| element.trim())
+            .filter(|element| !element.is_empty())
+    }
+
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.list(name)
+            .any(|element| element.eq_ignore_ascii_case(token))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+impl From<Vec<(String, String)>> for Headers {
+    fn from(fields: Vec<(String, String)>) -> Self {
+        Self { fields }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> Headers {
+        let mut headers = Headers::new();
+        headers.append("Accept", "text/html");
+        headers.append("connection", "keep-alive, Upgrade");
+        headers.append("ACCEPT", "text/plain, ,");
+        headers
+    }
+
+    #[test]
+    fn looks_up_names_case_insensitively() {
+        let headers = headers();
+        assert_eq!(headers.get("accept"), Some("text/html"));
+        assert_eq!(
+            headers.get_all("Accept").collect::<Vec<_>>(),
+            ["text/html", "text/plain, ,"]
+        );
+        assert_eq!(
+            headers.combined("accept").as_deref(),
+            Some("text/html, text/plain, ,")
+        );
+        assert_eq!(headers.get("Host"), None);
+        assert_eq!(headers.combined("Host"), None);
+    }
+
+    #[test]
+    fn splits_lists_into_tokens() {
+        let headers = headers();
+        assert_eq!(
+            headers.list("Accept").collect::<Vec<_>>(),
+            ["text/html", "text/plain"]
+        );
+        assert!(headers.has_token("Connection", "upgrade"));
+        assert!(headers.has_token("Connection", "Keep-Alive"));
+        assert!(!headers.has_token("Connection", "close"));
+        assert!(!headers.has_token("Accept", "text"));
+    }
+
+    #[test]
+    fn sets_and_removes_every_field_of_a_name() {
+        let mut headers = headers();
+        headers.set("accept", "*/*");
+        assert_eq!(headers.get_all("Accept").collect::<Vec<_>>(), ["*/*"]);
+        headers.remove("CONNECTION");
+        assert_eq!(headers.iter().collect::<Vec<_>>(), [("accept", "*/*")]);
+    }
+}
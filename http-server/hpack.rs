@@ -0,0 +1,449 @@
+// HPACK header compression (RFC 7541). The decoder follows the dynamic table
+// the client's encoder builds up; the encoder only refers to the static table
+// and never asks the client to remember anything
+use crate::headers::Headers;
+use std::collections::VecDeque;
+
+// what an entry costs in the dynamic table on top of its name and value
+const ENTRY_OVERHEAD: usize = 32;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// code length of every symbol, 256 being EOS; the code is canonical, so
+// the codes themselves follow from the lengths
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+const EOS: usize = 256;
+
+pub struct DecodeError;
+
+pub struct Decoder {
+    // newest entry first, it has index 62
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+    // the most the client may grow the table to, as we announced it
+    limit: usize,
+    huffman: Huffman,
+}
+
+impl Decoder {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: limit,
+            limit,
+            huffman: Huffman::new(),
+        }
+    }
+
+    // the fields of a whole header block in order, names as sent, or None
+    // once they add up to more than max_list_size, counted the way
+    // SETTINGS_MAX_HEADER_LIST_SIZE counts. The rest of the block is still
+    // decoded to keep the table in step, without collecting its fields
+    pub fn decode(
+        &mut self,
+        block: &[u8],
+        max_list_size: usize,
+    ) -> Result<Option<Vec<(String, String)>>, DecodeError> {
+        let mut input = Input { data: block };
+        let mut fields: Vec<(String, String)> = Vec::new();
+        let mut list_size = 0;
+
+        while let Some(&first) = input.data.first() {
+            if first & 0x80 != 0 {
+                // indexed field, copied only while it fits
+                let index = input.integer(7)?;
+                let (name, value) = self.entry(index)?;
+                list_size += name.len() + value.len() + ENTRY_OVERHEAD;
+                if list_size <= max_list_size {
+                    fields.push((name.to_string(), value.to_string()));
+                }
+                continue;
+            }
+
+            let field = if first & 0x40 != 0 {
+                // literal added to the table
+                let field = self.literal(&mut input, 6)?;
+                self.insert(field.clone());
+                field
+            } else if first & 0x20 != 0 {
+                // table size update, only allowed ahead of the first field
+                let max_size = input.integer(5)?;
+                if list_size > 0 || max_size > self.limit {
+                    return Err(DecodeError);
+                }
+                self.max_size = max_size;
+                self.evict(0);
+                continue;
+            } else {
+                // literal not indexed, or never to be indexed
+                self.literal(&mut input, 4)?
+            };
+            list_size += field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+            if list_size <= max_list_size {
+                fields.push(field);
+            }
+        }
+
+        Ok((list_size <= max_list_size).then_some(fields))
+    }
+
+    fn literal(&self, input: &mut Input, prefix: u8) -> Result<(String, String), DecodeError> {
+        let index = input.integer(prefix)?;
+        let name = if index == 0 {
+            input.string(&self.huffman)?
+        } else {
+            self.entry(index)?.0.to_string()
+        };
+        let value = input.string(&self.huffman)?;
+        Ok((name, value))
+    }
+
+    fn entry(&self, index: usize) -> Result<(&str, &str), DecodeError> {
+        if index == 0 {
+            return Err(DecodeError);
+        }
+        if let Some(&(name, value)) = STATIC_TABLE.get(index - 1) {
+            return Ok((name, value));
+        }
+        self.table
+            .get(index - 1 - STATIC_TABLE.len())
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .ok_or(DecodeError)
+    }
+
+    // an entry larger than the whole table just empties it
+    fn insert(&mut self, field: (String, String)) {
+        let size = field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front(field);
+        }
+    }
+
+    // drops the oldest entries until room bytes more would fit
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
+
+struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl Input<'_> {
+    // an integer in the low prefix bits of the first byte, continued in
+    // 7 bit groups once those are all set
+    fn integer(&mut self, prefix: u8) -> Result<usize, DecodeError> {
+        let mask = (1usize << prefix) - 1;
+        let mut value = self.byte()? as usize & mask;
+        if value < mask {
+            return Ok(value);
+        }
+
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            // anything past 2^28 is no size or index worth decoding
+            if shift > 21 {
+                return Err(DecodeError);
+            }
+            value += ((b & 0x7f) as usize) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn string(&mut self, huffman: &Huffman) -> Result<String, DecodeError> {
+        let encoded = self.data.first().ok_or(DecodeError)? & 0x80 != 0;
+        let len = self.integer(7)?;
+        if len > self.data.len() {
+            return Err(DecodeError);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+
+        let bytes = if encoded {
+            huffman.decode(bytes)?
+        } else {
+            bytes.to_vec()
+        };
+        Ok(String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let (&b, rest) = self.data.split_first().ok_or(DecodeError)?;
+        self.data = rest;
+        Ok(b)
+    }
+}
+
+// canonical decoding: how many codes each length has and the symbols
+// ordered by length, then value
+struct Huffman {
+    counts: [usize; 31],
+    symbols: Vec<usize>,
+}
+
+impl Huffman {
+    fn new() -> Self {
+        let mut counts = [0; 31];
+        for &len in HUFFMAN_LENGTHS.iter() {
+            counts[len as usize] += 1;
+        }
+        let mut symbols: Vec<usize> = (0..HUFFMAN_LENGTHS.len()).collect();
+        symbols.sort_by_key(|&symbol| HUFFMAN_LENGTHS[symbol]);
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        let mut decoded = Vec::with_capacity(data.len() * 8 / 5);
+        // the bits of the symbol read so far, and where its length's codes
+        // start in value and in symbols
+        let (mut code, mut len, mut first, mut index) = (0usize, 0usize, 0usize, 0usize);
+
+        for &b in data {
+            for shift in (0..8).rev() {
+                code = (code << 1) | ((b >> shift) & 1) as usize;
+                len += 1;
+                let count = self.counts[len];
+                if code < first + count {
+                    let symbol = self.symbols[index + code - first];
+                    if symbol == EOS {
+                        return Err(DecodeError);
+                    }
+                    decoded.push(symbol as u8);
+                    (code, len, first, index) = (0, 0, 0, 0);
+                } else {
+                    index += count;
+                    first = (first + count) << 1;
+                    if len == 30 {
+                        return Err(DecodeError);
+                    }
+                }
+            }
+        }
+
+        // padding is at most 7 bits of the EOS code, all ones
+        if len > 7 || code != (1 << len) - 1 {
+            return Err(DecodeError);
+        }
+        Ok(decoded)
+    }
+}
+
+// a header block of literals that stay out of the dynamic table, fields
+// found in the static table are referred to by index
+pub fn encode(fields: &Headers) -> Vec<u8> {
+    let mut block = Vec::new();
+
+    for (name, value) in fields.iter() {
+        let name = name.to_ascii_lowercase();
+        let exact = STATIC_TABLE
+            .iter()
+            .position(|&(n, v)| n == name && v == value && !v.is_empty());
+        if let Some(i) = exact {
+            integer(&mut block, 0x80, 7, i + 1);
+            continue;
+        }
+
+        match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+            Some(i) => integer(&mut block, 0x00, 4, i + 1),
+            None => {
+                block.push(0x00);
+                string(&mut block, name.as_bytes());
+            }
+        }
+        string(&mut block, value.as_bytes());
+    }
+
+    block
+}
+
+fn integer(block: &mut Vec<u8>, flags: u8, prefix: u8, mut value: usize) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        block.push(flags | value as u8);
+        return;
+    }
+
+    block.push(flags | mask as u8);
+    value -= mask;
+    while value >= 0x80 {
+        block.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+// strings go out raw, Huffman coding them is optional
+fn string(block: &mut Vec<u8>, bytes: &[u8]) {
+    integer(block, 0x00, 7, bytes.len());
+    block.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digits: &str) -> Vec<u8> {
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    const FIRST_REQUEST: [(&str, &str); 4] = [
+        (":method", "GET"),
+        (":scheme", "http"),
+        (":path", "/"),
+        (":authority", "www.example.com"),
+    ];
+
+    // RFC 7541 C.3, the second request refers to the table the first filled
+    #[test]
+    fn decodes_requests_through_the_dynamic_table() {
+        let mut decoder = Decoder::new(4096);
+        let first = decoder.decode(&hex("828684410f7777772e6578616d706c652e636f6d"), 4096);
+        assert!(first.ok().unwrap() == Some(fields(&FIRST_REQUEST)));
+
+        let second = decoder.decode(&hex("828684be58086e6f2d6361636865"), 4096);
+        let mut expected = fields(&FIRST_REQUEST);
+        expected.push(("cache-control".to_string(), "no-cache".to_string()));
+        assert!(second.ok().unwrap() == Some(expected));
+    }
+
+    // RFC 7541 C.4.1
+    #[test]
+    fn decodes_huffman_strings() {
+        let mut decoder = Decoder::new(4096);
+        let decoded = decoder.decode(&hex("828684418cf1e3c2e5f23a6ba0ab90f4ff"), 4096);
+        assert!(decoded.ok().unwrap() == Some(fields(&FIRST_REQUEST)));
+    }
+
+    #[test]
+    fn stops_collecting_past_the_list_size_but_keeps_the_table() {
+        let mut decoder = Decoder::new(4096);
+        let first = decoder.decode(&hex("828684410f7777772e6578616d706c652e636f6d"), 100);
+        assert!(first.ok().unwrap().is_none());
+
+        let second = decoder.decode(&hex("be"), 4096);
+        assert!(second.ok().unwrap() == Some(fields(&[(":authority", "www.example.com")])));
+    }
+
+    #[test]
+    fn round_trips_encoded_fields() {
+        let mut headers = Headers::new();
+        headers.append(":status", "200");
+        headers.append("Content-Type", "text/plain");
+        headers.append("X-Custom", "value");
+
+        let decoded = Decoder::new(4096).decode(&encode(&headers), 4096);
+        let expected = [
+            (":status", "200"),
+            ("content-type", "text/plain"),
+            ("x-custom", "value"),
+        ];
+        assert!(decoded.ok().unwrap() == Some(fields(&expected)));
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        let mut decoder = Decoder::new(4096);
+        // index 0, an index past both tables, a size update after a field,
+        // a size update past the limit, a string longer than the block
+        for block in ["80", "ff00", "823f01", "3fe21f", "400a61"] {
+            assert!(decoder.decode(&hex(block), 4096).is_err());
+        }
+    }
+}
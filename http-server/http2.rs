@@ -0,0 +1,1439 @@
+// HTTP/2 over cleartext (RFC 9113), entered with the client preface right
+// away or through an "Upgrade: h2c" request. Frames are read on a thread of
+// their own and written on the connection's thread, which waits for either
+// a frame or output of a request. Requests run on the workers of the pool
+// and hand their responses over in pieces, so one slow response doesn't
+// hold up the others
+use crate::base64;
+use crate::headers::Headers;
+use crate::hpack::{self, Decoder};
+use crate::request::{self, Head, Limits, Request, Timeouts, Version};
+use crate::response::{self, ContentType, Response};
+use crate::statuscode::StatusCode;
+use crate::threadpool::{Detached, ThreadPool};
+use crate::url;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+// what read_head leaves of the preface when it takes the start for a head
+const PREFACE_REST: &[u8] = b"SM\r\n\r\n";
+
+// frame types
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// flags
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+// settings
+const ENABLE_PUSH: u16 = 0x2;
+const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const INITIAL_WINDOW_SIZE: u16 = 0x4;
+const MAX_FRAME_SIZE: u16 = 0x5;
+const MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+// error codes
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const INTERNAL_ERROR: u32 = 0x2;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+
+// the defaults every connection starts with, ours are never changed
+const HEADER_TABLE_SIZE: usize = 4096;
+const DEFAULT_WINDOW: i64 = 65_535;
+const DEFAULT_FRAME_SIZE: usize = 16_384;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+const MAX_STREAMS: usize = 64;
+
+// body pieces a request may get ahead of the connection, and frames the
+// reading thread may get ahead of it
+const BACKLOG: usize = 16;
+const READ_CHUNK: usize = 16 * 1024;
+// more than this goes out before anything else is looked at
+const FLUSH_AT: usize = 64 * 1024;
+
+// what every stream's request is answered with, on a worker of the pool
+pub type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+// writes the access log entry of a finished response
+pub type Log<'a> = dyn Fn(Option<&Request>, u16, u64, Instant) + 'a;
+
+// the start of the preface looks like a request head to HTTP/1 parsing
+pub fn is_preface(head: &str) -> bool {
+    head == "PRI * HTTP/2.0"
+}
+
+// an HTTP/1.1 request asking to go on in HTTP/2, carrying the settings the
+// client would otherwise send first
+pub fn is_upgrade(request: &Request) -> bool {
+    let headers = &request.headers;
+    request.version == Version::Http11
+        && headers.has_token("Upgrade", "h2c")
+        && headers.has_token("Connection", "upgrade")
+        && headers.has_token("Connection", "http2-settings")
+        && upgrade_settings(request).is_some()
+}
+
+// turns away a client that started with the preface when there's no thread
+// for its connection, before any of its streams was looked at
+pub fn refuse<W: Write>(stream: &mut W) {
+    let mut goaway = 0u32.to_be_bytes().to_vec();
+    goaway.extend_from_slice(&REFUSED_STREAM.to_be_bytes());
+    let mut frames = Vec::new();
+    encode_frame(&mut frames, SETTINGS, 0, 0, &[]);
+    encode_frame(&mut frames, GOAWAY, 0, 0, &goaway);
+    if let Err(e) = stream.write_all(&frames).and_then(|()| stream.flush()) {
+        println!("err: http2: {}", e);
+    }
+}
+
+pub fn switching_protocols() -> Response {
+    Response::new(StatusCode::SwitchingProtocols, ContentType::TextPlain, "")
+        .with_header("Connection", "Upgrade")
+        .with_header("Upgrade", "h2c")
+}
+
+// the HTTP2-Settings field is a SETTINGS payload in unpadded base64url
+fn upgrade_settings(request: &Request) -> Option<Vec<u8>> {
+    let mut values = request.headers.get_all("HTTP2-Settings");
+    let value = values.next()?;
+    if values.next().is_some() {
+        return None;
+    }
+
+    let mut standard: String = value
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '-' => '+',
+            '_' => '/',
+            c => c,
+        })
+        .collect();
+    while !standard.len().is_multiple_of(4) {
+        standard.push('=');
+    }
+    base64::decode(&standard).filter(|settings| settings.len().is_multiple_of(6))
+}
+
+// how requests are answered: handler runs on the workers of pool, and an
+// open-ended body is sent from a thread reserved in detached
+pub struct Requests {
+    pub handler: Arc<Handler>,
+    pub pool: Arc<ThreadPool>,
+    pub detached: Detached,
+}
+
+// runs the connection until either side ends it; upgraded is the request
+// that asked for h2c, answered on stream 1, and without it the connection
+// began with the preface. log is told about every finished response. h2c
+// is cleartext only, so the socket is all there is to the connection
+pub fn serve(
+    stream: &TcpStream,
+    buffered: Vec<u8>,
+    upgraded: Option<Request>,
+    requests: &Requests,
+    log: &Log,
+    limits: &Limits,
+    timeouts: &Timeouts,
+) {
+    // frames are gathered and flushed on purpose, the response to a request
+    // shouldn't wait on the ack of the last flush
+    let mut reader = match stream.set_nodelay(true).and_then(|()| stream.try_clone()) {
+        Ok(clone) => Reader {
+            stream: clone,
+            buffer: buffered,
+        },
+        Err(e) => {
+            println!("err: http2: {}", e);
+            return;
+        }
+    };
+
+    thread::scope(|scope| {
+        let (events, inbox) = mpsc::sync_channel(BACKLOG);
+        let mut session = Session {
+            stream,
+            out: Vec::new(),
+            requests,
+            log,
+            limits,
+            timeouts,
+            events,
+            inbox,
+            decoder: Decoder::new(HEADER_TABLE_SIZE),
+            streams: HashMap::new(),
+            ready: Vec::new(),
+            last_stream_id: 0,
+            send_window: DEFAULT_WINDOW,
+            recv_window: connection_window(limits),
+            initial_window: DEFAULT_WINDOW,
+            max_frame_size: DEFAULT_FRAME_SIZE,
+            continuing: None,
+            draining: false,
+        };
+
+        let result = match session.start(upgraded, &mut reader) {
+            Ok(true) => {
+                let events = session.events.clone();
+                scope.spawn(move || read_frames(reader, events));
+                session.run()
+            }
+            Ok(false) => Ok(()),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {}
+            Err(Failure::Io(e)) => println!("err: http2: {}", e),
+            Err(Failure::Connection(code, reason)) => {
+                session.goaway(code);
+                let _ = session.flush();
+                println!("err: http2: {}", reason);
+            }
+        }
+
+        // streams cut off with the connection are logged as far as they got,
+        // requests still running find them gone when they next send
+        let ids: Vec<u32> = session.streams.keys().copied().collect();
+        for id in ids {
+            session.close(id);
+        }
+        // wakes the reading thread, which finds nobody listening
+        let _ = stream.shutdown(Shutdown::Both);
+    });
+}
+
+// the window announced for the whole connection: request bodies are held
+// against it until their streams close, so together they never buffer more
+// than one body at its limit
+fn connection_window(limits: &Limits) -> i64 {
+    (limits.max_body_bytes as i64).clamp(DEFAULT_WINDOW, MAX_WINDOW)
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+enum Failure {
+    Io(io::Error),
+    // ends the connection with GOAWAY and this code
+    Connection(u32, &'static str),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::Io(e)
+    }
+}
+
+// why a request wasn't handed to the handler
+enum Rejection {
+    // the stream is reset
+    Malformed,
+    // answered with this status instead
+    Status(StatusCode),
+}
+
+// what a request's thread passes on: the response head, whether a body
+// follows, then the body in pieces
+enum Output {
+    Head(u16, Headers, bool),
+    Data(Vec<u8>),
+    End,
+    // the body source failed halfway, the stream is reset
+    Abort,
+}
+
+// what the connection's thread waits for
+enum Event {
+    Frame(Frame),
+    // a request handed over more of its response
+    Output,
+    // the client closed the connection
+    Closed,
+    Failed(Failure),
+}
+
+// a request's end of its stream
+struct Handover {
+    output: SyncSender<Output>,
+    events: SyncSender<Event>,
+}
+
+impl Handover {
+    // false once the stream is gone
+    fn send(&self, message: Output) -> bool {
+        if self.output.send(message).is_err() {
+            return false;
+        }
+        // with the queue full the connection's thread is busy anyway, and
+        // it looks at every stream before it waits again
+        let _ = self.events.try_send(Event::Output);
+        true
+    }
+}
+
+struct Stream {
+    // the request until all of it arrived
+    head: Option<Head>,
+    body: Vec<u8>,
+    // the client is still sending a body that was refused
+    discarding: bool,
+    send_window: i64,
+    recv_window: i64,
+    // body bytes taken from the connection's window, given back on close
+    held: usize,
+    output: Option<Receiver<Output>>,
+    // body bytes waiting for flow control to let them out
+    pending: Vec<u8>,
+    // the whole body has been handed over
+    finished: bool,
+    // kept for the access log
+    request: Option<Arc<Request>>,
+    started: Instant,
+    // 0 until the response head went out
+    status: u16,
+    bytes: u64,
+}
+
+impl Stream {
+    fn new(send_window: i64) -> Self {
+        Self {
+            head: None,
+            body: Vec::new(),
+            discarding: false,
+            send_window,
+            recv_window: DEFAULT_WINDOW,
+            held: 0,
+            output: None,
+            pending: Vec::new(),
+            finished: false,
+            request: None,
+            started: Instant::now(),
+            status: 0,
+            bytes: 0,
+        }
+    }
+
+    // the response is being produced and nothing of it is ready
+    fn waiting(&self) -> bool {
+        self.output.is_some() && self.pending.is_empty() && !self.finished
+    }
+}
+
+struct Session<'a> {
+    stream: &'a TcpStream,
+    // frames not yet written
+    out: Vec<u8>,
+    requests: &'a Requests,
+    log: &'a Log<'a>,
+    limits: &'a Limits,
+    timeouts: &'a Timeouts,
+    // frames from the reading thread and word of output from requests
+    events: SyncSender<Event>,
+    inbox: Receiver<Event>,
+    decoder: Decoder,
+    streams: HashMap<u32, Stream>,
+    // requests that arrived whole and wait for a worker
+    ready: Vec<(u32, Result<Arc<Request>, StatusCode>)>,
+    last_stream_id: u32,
+    send_window: i64,
+    recv_window: i64,
+    // the client's settings
+    initial_window: i64,
+    max_frame_size: usize,
+    // a header block still missing CONTINUATION frames: stream, flags, block
+    continuing: Option<(u32, u8, Vec<u8>)>,
+    // the client sent GOAWAY, the connection ends with its last stream
+    draining: bool,
+}
+
+impl<'a> Session<'a> {
+    // exchanges prefaces, false when the client left before sending its own
+    fn start(&mut self, upgraded: Option<Request>, reader: &mut Reader) -> Result<bool, Failure> {
+        // our settings go first, there's no need to wait for the client's
+        let settings = [
+            (MAX_CONCURRENT_STREAMS, MAX_STREAMS as u32),
+            (MAX_HEADER_LIST_SIZE, self.limits.max_header_bytes as u32),
+        ];
+        let payload: Vec<u8> = settings
+            .iter()
+            .flat_map(|&(id, value)| {
+                let mut setting = id.to_be_bytes().to_vec();
+                setting.extend_from_slice(&value.to_be_bytes());
+                setting
+            })
+            .collect();
+        self.frame(SETTINGS, 0, 0, &payload);
+        let increment = (self.recv_window - DEFAULT_WINDOW) as u32;
+        if increment > 0 {
+            self.frame(WINDOW_UPDATE, 0, 0, &increment.to_be_bytes());
+        }
+        self.flush()?;
+
+        let preface = if upgraded.is_some() {
+            PREFACE
+        } else {
+            PREFACE_REST
+        };
+        reader.stream.set_read_timeout(Some(self.timeouts.header))?;
+        if !reader.fill(preface.len())? {
+            return Ok(false);
+        }
+        if &reader.buffer[..preface.len()] != preface {
+            return Err(Failure::Connection(
+                PROTOCOL_ERROR,
+                "bad connection preface",
+            ));
+        }
+        reader.buffer.drain(..preface.len());
+        // from here on the connection's thread keeps time
+        reader.stream.set_read_timeout(None)?;
+
+        if let Some(mut request) = upgraded {
+            if let Some(settings) = upgrade_settings(&request) {
+                self.apply_settings(&settings)?;
+            }
+            // the rest of the exchange happens in HTTP/2
+            request.version = Version::Http2;
+            let request = Arc::new(request);
+            let mut stream = Stream::new(self.initial_window);
+            stream.request = Some(Arc::clone(&request));
+            self.streams.insert(1, stream);
+            self.ready.push((1, Ok(request)));
+            self.last_stream_id = 1;
+        }
+        Ok(true)
+    }
+
+    fn run(&mut self) -> Result<(), Failure> {
+        let mut settings_seen = false;
+        loop {
+            for (id, job) in self.ready.drain(..) {
+                let (output, receiver) = mpsc::sync_channel(BACKLOG);
+                let handover = Handover {
+                    output,
+                    events: self.events.clone(),
+                };
+                let handler = Arc::clone(&self.requests.handler);
+                let detached = self.requests.detached.clone();
+                self.requests
+                    .pool
+                    .execute(move || respond(&*handler, job, handover, &detached));
+                if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.output = Some(receiver);
+                }
+            }
+
+            self.pump()?;
+            self.flush()?;
+            if self.draining && self.streams.is_empty() {
+                return Ok(());
+            }
+
+            // a running request takes as long as it takes, the client owes
+            // nothing meanwhile
+            let event = if self.streams.values().any(Stream::waiting) {
+                self.inbox
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected)
+            } else if self.streams.is_empty() {
+                self.inbox.recv_timeout(self.timeouts.idle)
+            } else {
+                self.inbox.recv_timeout(self.timeouts.read)
+            };
+            let frame = match event {
+                Ok(Event::Frame(frame)) => frame,
+                Ok(Event::Output) => continue,
+                Ok(Event::Closed) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                Ok(Event::Failed(failure)) => return Err(failure),
+                Err(RecvTimeoutError::Timeout) => {
+                    // idle, or a client that stopped reading or sending
+                    self.goaway(NO_ERROR);
+                    self.flush()?;
+                    return Ok(());
+                }
+            };
+
+            // the client's preface ends with its settings
+            if !settings_seen && (frame.kind != SETTINGS || frame.flags & ACK != 0) {
+                return Err(Failure::Connection(PROTOCOL_ERROR, "expected SETTINGS"));
+            }
+            settings_seen = true;
+            self.handle(frame)?;
+        }
+    }
+
+    fn handle(&mut self, frame: Frame) -> Result<(), Failure> {
+        if let Some((id, _, _)) = self.continuing {
+            if frame.kind != CONTINUATION || frame.stream_id != id {
+                return Err(Failure::Connection(PROTOCOL_ERROR, "expected CONTINUATION"));
+            }
+        }
+
+        let id = frame.stream_id;
+        let connection_frame = matches!(frame.kind, SETTINGS | PING | GOAWAY);
+        if connection_frame != (id == 0)
+            && frame.kind != WINDOW_UPDATE
+            && frame.kind <= CONTINUATION
+        {
+            return Err(Failure::Connection(
+                PROTOCOL_ERROR,
+                "frame on the wrong stream",
+            ));
+        }
+
+        match frame.kind {
+            DATA => self.on_data(frame),
+            HEADERS => self.on_headers(frame),
+            CONTINUATION => {
+                let (id, flags, mut block) = self.continuing.take().ok_or(Failure::Connection(
+                    PROTOCOL_ERROR,
+                    "unexpected CONTINUATION",
+                ))?;
+                block.extend_from_slice(&frame.payload);
+                self.check_block_size(&block)?;
+                if frame.flags & END_HEADERS != 0 {
+                    self.on_header_block(id, flags, &block)
+                } else {
+                    self.continuing = Some((id, flags, block));
+                    Ok(())
+                }
+            }
+            PRIORITY => {
+                if frame.payload.len() != 5 {
+                    self.reset(id, FRAME_SIZE_ERROR);
+                }
+                Ok(())
+            }
+            RST_STREAM => {
+                if frame.payload.len() != 4 {
+                    return Err(Failure::Connection(FRAME_SIZE_ERROR, "bad RST_STREAM"));
+                }
+                if id > self.last_stream_id {
+                    return Err(Failure::Connection(
+                        PROTOCOL_ERROR,
+                        "RST_STREAM on an idle stream",
+                    ));
+                }
+                self.close(id);
+                Ok(())
+            }
+            SETTINGS => {
+                if frame.flags & ACK != 0 {
+                    if !frame.payload.is_empty() {
+                        return Err(Failure::Connection(
+                            FRAME_SIZE_ERROR,
+                            "SETTINGS ack with payload",
+                        ));
+                    }
+                    return Ok(());
+                }
+                if !frame.payload.len().is_multiple_of(6) {
+                    return Err(Failure::Connection(FRAME_SIZE_ERROR, "bad SETTINGS"));
+                }
+                self.apply_settings(&frame.payload)?;
+                self.frame(SETTINGS, ACK, 0, &[]);
+                Ok(())
+            }
+            PUSH_PROMISE => Err(Failure::Connection(
+                PROTOCOL_ERROR,
+                "client sent PUSH_PROMISE",
+            )),
+            PING => {
+                if frame.payload.len() != 8 {
+                    return Err(Failure::Connection(FRAME_SIZE_ERROR, "bad PING"));
+                }
+                if frame.flags & ACK == 0 {
+                    self.frame(PING, ACK, 0, &frame.payload);
+                }
+                Ok(())
+            }
+            GOAWAY => {
+                self.draining = true;
+                Ok(())
+            }
+            WINDOW_UPDATE => self.on_window_update(frame),
+            // unknown frame types are ignored
+            _ => Ok(()),
+        }
+    }
+
+    fn on_headers(&mut self, frame: Frame) -> Result<(), Failure> {
+        let id = frame.stream_id;
+        let mut block = unpad(&frame)?;
+        if frame.flags & PRIORITY_FLAG != 0 {
+            if block.len() < 5 {
+                return Err(Failure::Connection(PROTOCOL_ERROR, "truncated priority"));
+            }
+            block = &block[5..];
+        }
+
+        if frame.flags & END_HEADERS != 0 {
+            self.on_header_block(id, frame.flags, block)
+        } else {
+            let block = block.to_vec();
+            self.check_block_size(&block)?;
+            self.continuing = Some((id, frame.flags, block));
+            Ok(())
+        }
+    }
+
+    // decoding has to happen even for a stream about to be refused, so the
+    // dynamic table stays the same on both sides; fields past the header
+    // limit are decoded but not kept, and the stream is answered with 431
+    fn on_header_block(&mut self, id: u32, flags: u8, block: &[u8]) -> Result<(), Failure> {
+        let fields = self
+            .decoder
+            .decode(block, self.limits.max_header_bytes)
+            .map_err(|_| Failure::Connection(COMPRESSION_ERROR, "cannot decode header block"))?;
+        let end_stream = flags & END_STREAM != 0;
+
+        // trailers, which end the request and are otherwise ignored
+        if let Some(stream) = self.streams.get(&id) {
+            if stream.head.is_none() {
+                self.reset(id, STREAM_CLOSED);
+            } else if !end_stream {
+                self.reset(id, PROTOCOL_ERROR);
+            } else {
+                self.complete(id);
+            }
+            return Ok(());
+        }
+
+        if id <= self.last_stream_id {
+            return Err(Failure::Connection(
+                STREAM_CLOSED,
+                "HEADERS on a closed stream",
+            ));
+        }
+        if id.is_multiple_of(2) {
+            return Err(Failure::Connection(
+                PROTOCOL_ERROR,
+                "even stream id from client",
+            ));
+        }
+        self.last_stream_id = id;
+
+        if self.streams.len() >= MAX_STREAMS {
+            self.frame(RST_STREAM, 0, id, &REFUSED_STREAM.to_be_bytes());
+            return Ok(());
+        }
+
+        let mut stream = Stream::new(self.initial_window);
+        let head = fields
+            .ok_or(Rejection::Status(StatusCode::RequestHeaderFieldsTooLarge))
+            .and_then(|fields| request_head(fields, self.limits));
+        match head {
+            Ok(head) => {
+                stream.head = Some(head);
+                self.streams.insert(id, stream);
+                if end_stream {
+                    self.complete(id);
+                }
+            }
+            Err(Rejection::Malformed) => {
+                self.frame(RST_STREAM, 0, id, &PROTOCOL_ERROR.to_be_bytes());
+            }
+            Err(Rejection::Status(status)) => {
+                stream.discarding = !end_stream;
+                self.streams.insert(id, stream);
+                self.ready.push((id, Err(status)));
+            }
+        }
+        Ok(())
+    }
+
+    fn on_data(&mut self, frame: Frame) -> Result<(), Failure> {
+        let id = frame.stream_id;
+        let data = unpad(&frame)?;
+        let end_stream = frame.flags & END_STREAM != 0;
+        // padding counts against the windows too. The stream's window is given
+        // back as data arrives, the body limit is what bounds a request; the
+        // connection's only for bytes that aren't kept
+        let len = frame.payload.len();
+        self.recv_window -= len as i64;
+        if self.recv_window < 0 {
+            return Err(Failure::Connection(
+                FLOW_CONTROL_ERROR,
+                "DATA beyond the connection window",
+            ));
+        }
+
+        let max_body_bytes = self.limits.max_body_bytes;
+        let stream = match self.streams.get_mut(&id) {
+            Some(stream) => stream,
+            None if id > self.last_stream_id => {
+                return Err(Failure::Connection(
+                    PROTOCOL_ERROR,
+                    "DATA on an idle stream",
+                ));
+            }
+            // reset by one side, frames already on their way are dropped
+            None => {
+                self.release(len);
+                return Ok(());
+            }
+        };
+
+        stream.recv_window -= len as i64;
+        if stream.recv_window < 0 {
+            self.release(len);
+            self.reset(id, FLOW_CONTROL_ERROR);
+            return Ok(());
+        }
+        if stream.discarding {
+            stream.discarding = !end_stream;
+            self.release(len);
+            return Ok(());
+        }
+        if stream.head.is_none() {
+            self.release(len);
+            self.reset(id, STREAM_CLOSED);
+            return Ok(());
+        }
+
+        stream.body.extend_from_slice(data);
+        stream.held += data.len();
+        let padding = len - data.len();
+        if stream.body.len() > max_body_bytes {
+            stream.head = None;
+            stream.body = Vec::new();
+            stream.discarding = !end_stream;
+            self.ready.push((id, Err(StatusCode::PayloadTooLarge)));
+        } else if end_stream {
+            self.complete(id);
+        } else if len > 0 {
+            stream.recv_window += len as i64;
+            self.frame(WINDOW_UPDATE, 0, id, &(len as u32).to_be_bytes());
+        }
+        self.release(padding);
+        Ok(())
+    }
+
+    // gives the client back room for len bytes on the connection
+    fn release(&mut self, len: usize) {
+        if len > 0 {
+            self.recv_window += len as i64;
+            self.frame(WINDOW_UPDATE, 0, 0, &(len as u32).to_be_bytes());
+        }
+    }
+
+    fn on_window_update(&mut self, frame: Frame) -> Result<(), Failure> {
+        if frame.payload.len() != 4 {
+            return Err(Failure::Connection(FRAME_SIZE_ERROR, "bad WINDOW_UPDATE"));
+        }
+        let increment = (u32_at(&frame.payload, 0) & 0x7fff_ffff) as i64;
+
+        let id = frame.stream_id;
+        if id == 0 {
+            if increment == 0 {
+                return Err(Failure::Connection(PROTOCOL_ERROR, "zero window increment"));
+            }
+            self.send_window += increment;
+            if self.send_window > MAX_WINDOW {
+                return Err(Failure::Connection(FLOW_CONTROL_ERROR, "window overflow"));
+            }
+            return Ok(());
+        }
+
+        let stream = match self.streams.get_mut(&id) {
+            Some(stream) => stream,
+            None if id > self.last_stream_id => {
+                return Err(Failure::Connection(
+                    PROTOCOL_ERROR,
+                    "WINDOW_UPDATE on an idle stream",
+                ));
+            }
+            None => return Ok(()),
+        };
+        if increment == 0 {
+            self.reset(id, PROTOCOL_ERROR);
+            return Ok(());
+        }
+        stream.send_window += increment;
+        if stream.send_window > MAX_WINDOW {
+            self.reset(id, FLOW_CONTROL_ERROR);
+        }
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), Failure> {
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32_at(setting, 2);
+            match id {
+                ENABLE_PUSH if value > 1 => {
+                    return Err(Failure::Connection(PROTOCOL_ERROR, "bad ENABLE_PUSH"));
+                }
+                INITIAL_WINDOW_SIZE => {
+                    let value = value as i64;
+                    if value > MAX_WINDOW {
+                        return Err(Failure::Connection(
+                            FLOW_CONTROL_ERROR,
+                            "bad INITIAL_WINDOW_SIZE",
+                        ));
+                    }
+                    // applies to the streams already open as well
+                    let delta = value - self.initial_window;
+                    self.initial_window = value;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > MAX_WINDOW {
+                            return Err(Failure::Connection(FLOW_CONTROL_ERROR, "window overflow"));
+                        }
+                    }
+                }
+                MAX_FRAME_SIZE => {
+                    if !(DEFAULT_FRAME_SIZE as u32..=0xff_ffff).contains(&value) {
+                        return Err(Failure::Connection(PROTOCOL_ERROR, "bad MAX_FRAME_SIZE"));
+                    }
+                    self.max_frame_size = value as usize;
+                }
+                // the encoder never uses the dynamic table, so its size
+                // doesn't matter; the rest is advice
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // the request arrived whole and can be handed to a thread
+    fn complete(&mut self, id: u32) {
+        let stream = match self.streams.get_mut(&id) {
+            Some(stream) => stream,
+            None => return,
+        };
+        let head = match stream.head.take() {
+            Some(head) => head,
+            None => return,
+        };
+        let body = std::mem::take(&mut stream.body);
+
+        let declared = head.headers.get("Content-Length").is_some();
+        if declared && body.len() != head.content_length {
+            self.reset(id, PROTOCOL_ERROR);
+            return;
+        }
+
+        let request = Arc::new(head.into_request(body));
+        stream.request = Some(Arc::clone(&request));
+        self.ready.push((id, Ok(request)));
+    }
+
+    // sends what running requests have produced, as far as flow control allows
+    fn pump(&mut self) -> io::Result<()> {
+        let mut ids: Vec<u32> = self.streams.keys().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            self.pump_stream(id)?;
+        }
+        Ok(())
+    }
+
+    fn pump_stream(&mut self, id: u32) -> io::Result<()> {
+        loop {
+            if self.out.len() >= FLUSH_AT {
+                self.flush()?;
+            }
+            let max_frame_size = self.max_frame_size;
+            let connection_window = self.send_window;
+            let stream = match self.streams.get_mut(&id) {
+                Some(stream) => stream,
+                None => return Ok(()),
+            };
+
+            // takes in what's ready until there's a full frame, so the end of
+            // the body can ride on the last DATA frame
+            if !stream.finished && stream.pending.len() < max_frame_size {
+                let output = match stream.output {
+                    Some(ref output) => output.try_recv(),
+                    None => return Ok(()),
+                };
+                match output {
+                    Ok(Output::Head(status, fields, end_stream)) => {
+                        stream.status = status;
+                        self.write_headers(id, status, fields, end_stream);
+                        if end_stream {
+                            self.finish(id);
+                            return Ok(());
+                        }
+                        continue;
+                    }
+                    Ok(Output::Data(data)) => {
+                        stream.pending.extend_from_slice(&data);
+                        continue;
+                    }
+                    Ok(Output::End) => stream.finished = true,
+                    Ok(Output::Abort) | Err(TryRecvError::Disconnected) => {
+                        self.reset(id, INTERNAL_ERROR);
+                        return Ok(());
+                    }
+                    Err(TryRecvError::Empty) if stream.pending.is_empty() => return Ok(()),
+                    Err(TryRecvError::Empty) => {}
+                }
+            }
+
+            let window = connection_window.min(stream.send_window).max(0) as usize;
+            let len = stream.pending.len().min(max_frame_size).min(window);
+            // an empty body still has to be ended
+            if len == 0 && !(stream.finished && stream.pending.is_empty()) {
+                return Ok(());
+            }
+            let data: Vec<u8> = stream.pending.drain(..len).collect();
+            let last = stream.finished && stream.pending.is_empty();
+            stream.send_window -= len as i64;
+            stream.bytes += len as u64;
+            self.send_window -= len as i64;
+            self.frame(DATA, if last { END_STREAM } else { 0 }, id, &data);
+            if last {
+                self.finish(id);
+                return Ok(());
+            }
+        }
+    }
+
+    fn write_headers(&mut self, id: u32, status: u16, fields: Headers, end_stream: bool) {
+        let mut all = Headers::new();
+        all.append(":status", &status.to_string());
+        for (name, value) in fields.iter() {
+            all.append(name, value);
+        }
+        let block = hpack::encode(&all);
+
+        // what doesn't fit one frame continues in CONTINUATION frames
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+        let mut rest = &block[..];
+        loop {
+            let (part, tail) = rest.split_at(rest.len().min(self.max_frame_size));
+            if tail.is_empty() {
+                flags |= END_HEADERS;
+            }
+            self.frame(kind, flags, id, part);
+            if tail.is_empty() {
+                break;
+            }
+            rest = tail;
+            kind = CONTINUATION;
+            flags = 0;
+        }
+    }
+
+    // the response went out whole
+    fn finish(&mut self, id: u32) {
+        if let Some(stream) = self.streams.get(&id) {
+            // the client may stop sending a body nobody reads
+            if stream.discarding {
+                self.frame(RST_STREAM, 0, id, &NO_ERROR.to_be_bytes());
+            }
+        }
+        self.close(id);
+    }
+
+    fn reset(&mut self, id: u32, code: u32) {
+        self.frame(RST_STREAM, 0, id, &code.to_be_bytes());
+        self.close(id);
+    }
+
+    // dropping the stream tells a running request to stop
+    fn close(&mut self, id: u32) {
+        if let Some(stream) = self.streams.remove(&id) {
+            self.release(stream.held);
+            if stream.status != 0 {
+                (self.log)(
+                    stream.request.as_deref(),
+                    stream.status,
+                    stream.bytes,
+                    stream.started,
+                );
+            }
+        }
+    }
+
+    fn goaway(&mut self, code: u32) {
+        let mut payload = self.last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        self.frame(GOAWAY, 0, 0, &payload);
+    }
+
+    fn check_block_size(&self, block: &[u8]) -> Result<(), Failure> {
+        // compressed, a block this large can't decode to an acceptable one
+        if block.len() > 4 * self.limits.max_header_bytes {
+            return Err(Failure::Connection(
+                PROTOCOL_ERROR,
+                "header block too large",
+            ));
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+        encode_frame(&mut self.out, kind, flags, stream_id, payload);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.out.is_empty() {
+            return Ok(());
+        }
+        let mut stream = self.stream;
+        stream.write_all(&self.out)?;
+        self.out.clear();
+        stream.flush()
+    }
+}
+
+// the reading half of the connection
+struct Reader {
+    stream: TcpStream,
+    // bytes read but not yet a whole frame
+    buffer: Vec<u8>,
+}
+
+impl Reader {
+    // None once the client closed the connection
+    fn read_frame(&mut self) -> Result<Option<Frame>, Failure> {
+        if !self.fill(9)? {
+            return Ok(None);
+        }
+        let len = u32_at(&[0, self.buffer[0], self.buffer[1], self.buffer[2]], 0) as usize;
+        if len > DEFAULT_FRAME_SIZE {
+            return Err(Failure::Connection(FRAME_SIZE_ERROR, "frame too large"));
+        }
+        if !self.fill(9 + len)? {
+            return Ok(None);
+        }
+
+        let frame = Frame {
+            kind: self.buffer[3],
+            flags: self.buffer[4],
+            stream_id: u32_at(&self.buffer, 5) & 0x7fff_ffff,
+            payload: self.buffer[9..9 + len].to_vec(),
+        };
+        self.buffer.drain(..9 + len);
+        Ok(Some(frame))
+    }
+
+    fn fill(&mut self, len: usize) -> io::Result<bool> {
+        let mut chunk = [0u8; READ_CHUNK];
+        while self.buffer.len() < len {
+            let bytes = self.stream.read(&mut chunk)?;
+            if bytes == 0 {
+                return Ok(false);
+            }
+            self.buffer.extend_from_slice(&chunk[..bytes]);
+        }
+        Ok(true)
+    }
+}
+
+fn encode_frame(out: &mut Vec<u8>, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&stream_id.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+// runs on a worker of the pool; a failed send means the stream is gone. An
+// open-ended body such as an event stream would hold the worker for as long
+// as the client listens, it goes on on a thread of its own instead
+fn respond(
+    handler: &Handler,
+    job: Result<Arc<Request>, StatusCode>,
+    output: Handover,
+    detached: &Detached,
+) {
+    let mut response = match job {
+        Ok(request) => handler(&request),
+        Err(status) => Response::new(status, ContentType::TextPlain, ""),
+    };
+    let mut slot = None;
+    if response.is_open_ended() {
+        slot = detached.reserve();
+        if slot.is_none() {
+            response = Response::new(StatusCode::ServiceUnavailable, ContentType::TextPlain, "");
+        }
+    }
+
+    let (status, fields, body) = response.into_http2();
+    if !output.send(Output::Head(status, fields, body.is_none())) {
+        return;
+    }
+    if let Some(source) = body {
+        match slot {
+            Some(slot) => slot.spawn(move || send_body(source, &output)),
+            None => send_body(source, &output),
+        }
+    }
+}
+
+fn send_body(mut source: Box<dyn Read + Send>, output: &Handover) {
+    loop {
+        let mut chunk = vec![0; READ_CHUNK];
+        let message = match source.read(&mut chunk) {
+            Ok(0) => Output::End,
+            Ok(bytes) => {
+                chunk.truncate(bytes);
+                Output::Data(chunk)
+            }
+            Err(e) => {
+                println!("err while writing response: {}", e);
+                Output::Abort
+            }
+        };
+        let last = !matches!(message, Output::Data(_));
+        if !output.send(message) || last {
+            return;
+        }
+    }
+}
+
+// reads frames until the client closes the connection or the connection's
+// thread stops listening
+fn read_frames(mut reader: Reader, events: SyncSender<Event>) {
+    loop {
+        let event = match reader.read_frame() {
+            Ok(Some(frame)) => Event::Frame(frame),
+            Ok(None) => Event::Closed,
+            Err(failure) => Event::Failed(failure),
+        };
+        let last = !matches!(event, Event::Frame(_));
+        if events.send(event).is_err() || last {
+            return;
+        }
+    }
+}
+
+// the pseudo-header fields become the request line, the rest are checked
+// the way HTTP/2 wants: lowercase and nothing connection specific
+fn request_head(fields: Vec<(String, String)>, limits: &Limits) -> Result<Head, Rejection> {
+    // their size was bounded while decoding
+    if fields.len() > limits.max_headers {
+        return Err(Rejection::Status(StatusCode::RequestHeaderFieldsTooLarge));
+    }
+
+    let (mut method, mut scheme, mut path, mut authority) = (None, None, None, None);
+    let mut regular: Vec<(String, String)> = Vec::new();
+    for (name, value) in fields {
+        if let Some(pseudo) = name.strip_prefix(':') {
+            let slot = match pseudo {
+                "method" => &mut method,
+                "scheme" => &mut scheme,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                _ => return Err(Rejection::Malformed),
+            };
+            // pseudo-header fields come first, each at most once
+            if !regular.is_empty() || slot.is_some() {
+                return Err(Rejection::Malformed);
+            }
+            *slot = Some(value);
+            continue;
+        }
+
+        let valid = !name.is_empty()
+            && name
+                .bytes()
+                .all(|b| request::is_tchar(b) && !b.is_ascii_uppercase())
+            && request::valid_value(&value)
+            && !response::is_connection_specific(&name)
+            && (name != "te" || value == "trailers");
+        if !valid {
+            return Err(Rejection::Malformed);
+        }
+        regular.push((name, value));
+    }
+
+    let (method, target) = match (method, scheme, path) {
+        (Some(method), Some(_), Some(path)) if !path.is_empty() => (method, path),
+        _ => return Err(Rejection::Malformed),
+    };
+    if method.is_empty() || !method.bytes().all(request::is_tchar) {
+        return Err(Rejection::Malformed);
+    }
+    if target.len() > limits.max_uri_len {
+        return Err(Rejection::Status(StatusCode::UriTooLong));
+    }
+    if !(target.starts_with('/') || (target == "*" && method == "OPTIONS")) {
+        return Err(Rejection::Malformed);
+    }
+    if url::percent_decode(url::split_target(&target).0).is_none() {
+        return Err(Rejection::Status(StatusCode::BadRequest));
+    }
+
+    // the authority takes the place of Host, which clients may leave out
+    if let Some(authority) = authority {
+        if !regular.iter().any(|(name, _)| name == "host") {
+            regular.insert(0, ("host".to_string(), authority));
+        }
+    }
+    let headers = Headers::from(regular);
+    let content_lengths: Vec<&str> = headers.get_all("Content-Length").collect();
+    let content_length =
+        request::parse_content_length(&content_lengths).map_err(|_| Rejection::Malformed)?;
+    if content_length > limits.max_body_bytes {
+        return Err(Rejection::Status(StatusCode::PayloadTooLarge));
+    }
+
+    Ok(Head {
+        method,
+        target,
+        version: Version::Http2,
+        headers,
+        content_length,
+        chunked: false,
+    })
+}
+
+// the payload of a DATA or HEADERS frame without its padding
+fn unpad(frame: &Frame) -> Result<&[u8], Failure> {
+    if frame.flags & PADDED == 0 {
+        return Ok(&frame.payload);
+    }
+    let (&pad, rest) = frame
+        .payload
+        .split_first()
+        .ok_or(Failure::Connection(PROTOCOL_ERROR, "missing pad length"))?;
+    if pad as usize > rest.len() {
+        return Err(Failure::Connection(PROTOCOL_ERROR, "padding too long"));
+    }
+    Ok(&rest[..rest.len() - pad as usize])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    const GET: [(&str, &str); 4] = [
+        (":method", "GET"),
+        (":scheme", "http"),
+        (":path", "/a?b=1"),
+        (":authority", "example.com"),
+    ];
+
+    // the status a request head is answered with instead of being handled,
+    // 0 when its stream is reset
+    fn rejected(pairs: &[(&str, &str)]) -> Option<u16> {
+        match request_head(fields(pairs), &Limits::default()) {
+            Ok(_) => None,
+            Err(Rejection::Malformed) => Some(0),
+            Err(Rejection::Status(status)) => Some(status.code()),
+        }
+    }
+
+    fn with(extra: &[(&'static str, &'static str)]) -> Vec<(&'static str, &'static str)> {
+        GET.iter().chain(extra).copied().collect()
+    }
+
+    #[test]
+    fn turns_pseudo_headers_into_a_request_line() {
+        let head = request_head(fields(&with(&[("accept", "*/*")])), &Limits::default())
+            .ok()
+            .unwrap();
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.target, "/a?b=1");
+        assert!(head.version == Version::Http2);
+        assert_eq!(head.headers.get("Host"), Some("example.com"));
+        assert_eq!(head.headers.get("Accept"), Some("*/*"));
+
+        let head = request_head(fields(&with(&[("host", "other")])), &Limits::default())
+            .ok()
+            .unwrap();
+        assert_eq!(head.headers.get_all("host").collect::<Vec<_>>(), ["other"]);
+
+        let options = [(":method", "OPTIONS"), (":scheme", "http"), (":path", "*")];
+        assert!(rejected(&options).is_none());
+        assert!(rejected(&with(&[("te", "trailers")])).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        for pairs in [
+            &GET[1..],
+            &[GET[0], GET[2]][..],
+            &[GET[0], GET[1], (":path", "")],
+            &[GET[0], GET[1], (":path", "a")],
+            &[GET[0], GET[1], (":path", "*")],
+            &[(":method", "G T"), GET[1], GET[2]],
+            &[GET[0], GET[0], GET[1], GET[2]],
+            &[GET[0], GET[1], GET[2], (":status", "200")],
+            &[GET[0], GET[1], ("accept", "*/*"), GET[2]],
+            &with(&[("Accept", "*/*")]),
+            &with(&[("connection", "keep-alive")]),
+            &with(&[("transfer-encoding", "chunked")]),
+            &with(&[("te", "gzip")]),
+            &with(&[("x", "a\nb")]),
+            &with(&[("content-length", "1, 2")]),
+        ] {
+            assert_eq!(rejected(pairs), Some(0), "{pairs:?}");
+        }
+    }
+
+    #[test]
+    fn answers_oversized_heads_with_a_status() {
+        let long = format!("/{}", "a".repeat(Limits::default().max_uri_len));
+        let pairs = [GET[0], GET[1], (":path", long.as_str())];
+        assert_eq!(rejected(&pairs), Some(414));
+        assert_eq!(rejected(&[GET[0], GET[1], (":path", "/%zz")]), Some(400));
+        assert_eq!(
+            rejected(&with(&[("content-length", "99999999999")])),
+            Some(413)
+        );
+
+        let many: Vec<(&str, &str)> = GET
+            .iter()
+            .copied()
+            .chain(std::iter::repeat_n(
+                ("x", "y"),
+                Limits::default().max_headers,
+            ))
+            .collect();
+        assert_eq!(rejected(&many), Some(431));
+    }
+
+    #[test]
+    fn recognises_the_h2c_upgrade() {
+        let upgrade = |pairs: &[(&str, &str)]| {
+            let mut headers = Headers::new();
+            for (name, value) in pairs {
+                headers.append(name, value);
+            }
+            is_upgrade(&Request {
+                method: "GET".to_string(),
+                target: "/".to_string(),
+                path: "/".to_string(),
+                query: HashMap::new(),
+                version: Version::Http11,
+                headers,
+                body: Vec::new(),
+            })
+        };
+        // SETTINGS_MAX_CONCURRENT_STREAMS = 100 in base64url without padding
+        let fields = [
+            ("Upgrade", "h2c"),
+            ("Connection", "Upgrade, HTTP2-Settings"),
+            ("HTTP2-Settings", "AAMAAABk"),
+        ];
+        assert!(upgrade(&fields));
+        assert!(upgrade(&[fields[0], fields[1], ("HTTP2-Settings", "")]));
+        assert!(!upgrade(&fields[..2]));
+        assert!(!upgrade(&[fields[0], ("Connection", "Upgrade"), fields[2]]));
+        assert!(!upgrade(&[
+            fields[0],
+            fields[1],
+            ("HTTP2-Settings", "AAMAAA")
+        ]));
+        assert!(!upgrade(&[fields[0], fields[1], fields[2], fields[2]]));
+    }
+
+    #[test]
+    fn strips_padding() {
+        let frame = |flags, payload: &[u8]| Frame {
+            kind: DATA,
+            flags,
+            stream_id: 1,
+            payload: payload.to_vec(),
+        };
+        assert_eq!(unpad(&frame(0, b"\x02abc")).ok(), Some(&b"\x02abc"[..]));
+        assert_eq!(unpad(&frame(PADDED, b"\x02abc")).ok(), Some(&b"a"[..]));
+        assert_eq!(unpad(&frame(PADDED, b"\x03abc")).ok(), Some(&b""[..]));
+        assert!(unpad(&frame(PADDED, b"\x04abc")).is_err());
+        assert!(unpad(&frame(PADDED, b"")).is_err());
+    }
+
+    #[test]
+    fn reads_back_encoded_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let mut reader = Reader {
+            stream: server,
+            buffer: Vec::new(),
+        };
+
+        let mut out = Vec::new();
+        encode_frame(&mut out, HEADERS, END_HEADERS, 3, b"block");
+        encode_frame(
+            &mut out,
+            DATA,
+            END_STREAM,
+            0x8000_0005,
+            &[9; DEFAULT_FRAME_SIZE],
+        );
+        // a frame longer than SETTINGS_MAX_FRAME_SIZE
+        out.extend_from_slice(&[0, 0x40, 0x01, DATA, 0, 0, 0, 0, 1]);
+        client.write_all(&out).unwrap();
+
+        let frame = reader.read_frame().ok().flatten().unwrap();
+        assert_eq!(
+            (frame.kind, frame.flags, frame.stream_id),
+            (HEADERS, END_HEADERS, 3)
+        );
+        assert_eq!(frame.payload, b"block");
+        // the reserved bit of the stream identifier is ignored
+        let frame = reader.read_frame().ok().flatten().unwrap();
+        assert_eq!((frame.kind, frame.stream_id), (DATA, 5));
+        assert_eq!(frame.payload.len(), DEFAULT_FRAME_SIZE);
+        assert!(matches!(
+            reader.read_frame(),
+            Err(Failure::Connection(FRAME_SIZE_ERROR, _))
+        ));
+
+        drop(client);
+        reader.buffer.clear();
+        assert!(matches!(reader.read_frame(), Ok(None)));
+    }
+
+    #[test]
+    fn refuses_with_goaway() {
+        let mut out = Vec::new();
+        refuse(&mut out);
+        assert_eq!(
+            out,
+            [
+                &[0, 0, 0, SETTINGS, 0, 0, 0, 0, 0][..],
+                &[0, 0, 8, GOAWAY, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7],
+            ]
+            .concat()
+        );
+    }
+}
@@ -1,27 +1,198 @@
+mod accesslog;
+mod aesgcm;
+mod base64;
+mod chacha20poly1305;
+mod conditional;
+mod config;
+mod curve25519;
+mod date;
+mod deflate;
+mod files;
+mod headers;
+mod hpack;
+mod http2;
+mod mime;
+mod pem;
+mod proxy;
+mod range;
+mod request;
 mod response;
+mod router;
+mod rsa;
+mod sha1;
+mod sha2;
+mod sse;
 mod statuscode;
 mod threadpool;
+mod tls;
+mod url;
+mod websocket;
 
-use crate::response::{AcceptEncoding, ContentType, Response};
+use crate::accesslog::{AccessLog, Entry};
+use crate::config::Config;
+use crate::files::FileServer;
+use crate::proxy::Proxy;
+use crate::request::{Connection, ReadError, Request, Version};
+use crate::response::{AcceptEncoding, ContentType, NotAcceptable, Response};
+use crate::router::{Context, Dispatch, Router};
+use crate::sse::Event;
 use crate::statuscode::StatusCode;
-use crate::threadpool::ThreadPool;
-use std::fs;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use crate::threadpool::{Detached, ThreadPool};
+use crate::tls::TlsConfig;
+use crate::websocket::{Message, Socket, Upgrade, WebSocket};
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::TcpListener;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 fn main() {
-    let port = 4221;
-    let address = format!("127.0.0.1:{port}");
-    let listener = TcpListener::bind(&address).unwrap();
-    println!("Server listening on port {port}...");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", config::USAGE);
+        return;
+    }
+
+    let config = match Config::from_args(&args) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            println!("err: {}\n{}", e, config::USAGE);
+            process::exit(2);
+        }
+    };
+
+    let address = config.address();
+    let listener = bind(&address);
+    println!("Server listening on {address}...");
+
+    // HTTPS gets its own port once a certificate and key are given
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let tls_config = match TlsConfig::load(cert, key) {
+                Ok(tls_config) => Arc::new(tls_config),
+                Err(e) => {
+                    println!("err: cannot load certificate: {}", e);
+                    process::exit(1);
+                }
+            };
+            let address = config.tls_address();
+            let listener = bind(&address);
+            println!("TLS listening on {address}...");
+            Some((listener, tls_config))
+        }
+        _ => None,
+    };
+
+    let access_log = match config.access_log {
+        Some(ref destination) => match AccessLog::open(destination, config.log_format) {
+            Ok(access_log) => Some(Arc::new(access_log)),
+            Err(e) => {
+                println!("err: cannot open access log {}: {}", destination, e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let threads = Threads {
+        pool: Arc::new(ThreadPool::new(config.workers)),
+        detached: Detached::new(config.max_streams),
+    };
+    let router = Arc::new(routes(&config));
+
+    thread::scope(|scope| {
+        if let Some((tls_listener, tls_config)) = tls {
+            let (threads, router, config, access_log) = (&threads, &router, &config, &access_log);
+            scope.spawn(move || {
+                serve(
+                    tls_listener,
+                    Some(tls_config),
+                    threads,
+                    router,
+                    config,
+                    access_log,
+                )
+            });
+        }
+        serve(listener, None, &threads, &router, &config, &access_log);
+    });
+}
 
-    let pool = ThreadPool::new(4);
+fn bind(address: &str) -> TcpListener {
+    match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("err: cannot listen on {}: {}", address, e);
+            process::exit(1);
+        }
+    }
+}
 
+// where work runs: connections and requests on the workers of the pool,
+// whatever stays open indefinitely on a detached thread of its own
+#[derive(Clone)]
+struct Threads {
+    pool: Arc<ThreadPool>,
+    detached: Detached,
+}
+
+// hands every accepted connection to the pool, behind TLS when configured
+fn serve(
+    listener: TcpListener,
+    tls: Option<Arc<TlsConfig>>,
+    threads: &Threads,
+    router: &Arc<Router>,
+    config: &Arc<Config>,
+    access_log: &Option<Arc<AccessLog>>,
+) {
     for stream in listener.incoming() {
         match stream {
-            Ok(mut _stream) => {
-                pool.execute(|| {
-                    handle_connection(_stream);
+            Ok(stream) => {
+                let tls = tls.clone();
+                let threads = threads.clone();
+                let router = Arc::clone(router);
+                let config = Arc::clone(config);
+                let access_log = access_log.clone();
+                threads.pool.clone().execute(move || {
+                    // set on the socket so TLS records are covered too, the
+                    // read timeout bounds the handshake until requests are read
+                    let timeouts = &config.timeouts;
+                    if let Err(e) = stream
+                        .set_write_timeout(Some(timeouts.write))
+                        .and_then(|()| stream.set_read_timeout(Some(timeouts.header)))
+                    {
+                        println!("err: {}", e);
+                        return;
+                    }
+                    let client = stream
+                        .peer_addr()
+                        .map_or("-".to_string(), |addr| addr.ip().to_string());
+
+                    match tls {
+                        Some(tls) => match tls::accept(stream, &tls) {
+                            Ok(stream) => handle_connection(
+                                stream,
+                                &client,
+                                true,
+                                &threads,
+                                &router,
+                                &config,
+                                access_log.as_ref(),
+                            ),
+                            Err(e) => println!("err: tls handshake with {}: {}", client, e),
+                        },
+                        None => handle_connection(
+                            stream,
+                            &client,
+                            false,
+                            &threads,
+                            &router,
+                            &config,
+                            access_log.as_ref(),
+                        ),
+                    }
                 });
             }
             Err(e) => {
@@ -31,257 +202,424 @@ fn main() {
     }
 }
 
-fn handle_connection(mut _stream: TcpStream) {
-    let args: Vec<String> = std::env::args().collect();
-    let directory = if args.len() >= 3 && args[1] == "--directory" {
-        &args[2]
-    } else {
-        "."
+fn routes(config: &Config) -> Router {
+    let mut router = Router::new();
+
+    // proxied prefixes come first, they take precedence over the routes below
+    for route in &config.proxies {
+        let proxy = Proxy::new(
+            route,
+            config.balance,
+            config.proxy_timeout,
+            config.limits.clone(),
+        );
+        router.any(&route.pattern(), move |ctx| proxy.forward(ctx));
+    }
+
+    router.get("/", |_| {
+        Response::new(StatusCode::Ok, ContentType::TextPlain, "")
+    });
+    router.get("/echo/:text", |ctx| {
+        Response::new(StatusCode::Ok, ContentType::TextPlain, ctx.param("text"))
+    });
+    router.get("/user-agent", |ctx| {
+        Response::new(
+            StatusCode::Ok,
+            ContentType::TextPlain,
+            ctx.header("User-Agent").unwrap_or_default(),
+        )
+    });
+
+    // counts up once a second, carrying on after the last id a client saw
+    router.get("/events", |ctx| {
+        let start = sse::last_event_id(ctx)
+            .and_then(|id| id.parse::<u64>().ok()?.checked_add(1))
+            .unwrap_or(0);
+        let (sender, response) = sse::stream();
+
+        thread::spawn(move || {
+            for n in start.. {
+                let mut event = Event::new(&n.to_string())
+                    .with_id(&n.to_string())
+                    .with_event("tick");
+                if n == start {
+                    event = event.with_retry(Duration::from_secs(3));
+                }
+                // fails once the connection is gone and the stream dropped
+                if sender.send(event).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+        });
+        response
+    });
+    router.websocket("/ws/echo", |_, socket| {
+        while let Ok(Some(message)) = socket.recv() {
+            if socket.send(&message).is_err() {
+                break;
+            }
+        }
+    });
+    // counts up once a second from another thread while echoing what arrives
+    router.websocket("/ws/ticks", |_, socket| {
+        let sender = socket.sender();
+        thread::spawn(move || {
+            for n in 0u64.. {
+                // fails once the socket is closed
+                if sender.send(&Message::Text(n.to_string())).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+        });
+        while let Ok(Some(message)) = socket.recv() {
+            if socket.send(&message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let file_server = FileServer::new(config.list_directories);
+    router.get("/files/*", move |ctx| file_server.get(ctx));
+    router.post("/files/*", files::post_file);
+    router
+}
+
+fn handle_connection<S: Socket + 'static>(
+    stream: S,
+    client: &str,
+    secure: bool,
+    threads: &Threads,
+    router: &Arc<Router>,
+    config: &Arc<Config>,
+    access_log: Option<&Arc<AccessLog>>,
+) {
+    let limits = &config.limits;
+    let timeouts = &config.timeouts;
+    let keep_alive = &config.keep_alive;
+
+    // owns what it needs, a connection moved to a thread of its own takes a copy
+    let log = {
+        let client = client.to_string();
+        let access_log = access_log.cloned();
+        move |request: Option<&Request>, status: u16, bytes: u64, started: Instant| {
+            if let Some(ref access_log) = access_log {
+                access_log.log(&Entry {
+                    client: &client,
+                    request,
+                    status,
+                    bytes,
+                    duration: started.elapsed(),
+                });
+            }
+        }
     };
 
+    let mut conn = Connection::new(stream, *timeouts);
+    let mut served = 0;
+
     loop {
-        let mut buffer = [0; 256];
-        let bytes = match _stream.read(&mut buffer) {
-            Ok(0) => break,
-            Ok(n) => n,
+        let head = conn.read_head(limits);
+        let started = Instant::now();
+        let head = match head {
+            Ok(head) => head,
             Err(e) => {
-                println!("err while reading bytes: {}", e);
+                if let Some(status) = reject(conn.get_mut(), e) {
+                    log(None, status, 0, started);
+                }
                 break;
             }
         };
 
-        let request = String::from_utf8_lossy(&buffer[..bytes]);
-        // println!("{}", request);
-
-        let mut main_parts = request.splitn(2, "\r\n\r\n");
-        let headers = main_parts.next().unwrap_or("");
-        let req_body = main_parts.next().unwrap_or("");
-
-        let header_parts = headers.split("\r\n").collect::<Vec<&str>>();
-        let req_line = header_parts[0].split(" ").collect::<Vec<&str>>();
-
-        let method = req_line[0];
-        let req_path = req_line[1];
-        let req_path_edited = &req_path[1..];
-
-        let mut user_agent_kind = String::new();
-        let mut host = String::new();
-        let mut accept = String::new();
-        let mut req_content_type = String::new();
-        let mut req_content_len: usize = 0;
-        let mut accept_encoding: Vec<String> = Vec::new();
-        let mut connection = String::new();
-
-        parse_headers(
-            &header_parts,
-            &mut user_agent_kind,
-            &mut host,
-            &mut accept,
-            &mut req_content_type,
-            &mut req_content_len,
-            &mut accept_encoding,
-            &mut connection,
-        );
+        // a client that knows the server speaks HTTP/2 starts with its preface,
+        // only without TLS as h2c is cleartext only (RFC 9113 section 3.2)
+        if !secure && served == 0 && http2::is_preface(&head) {
+            match threads.detached.reserve() {
+                Some(slot) => {
+                    let requests = http2_requests(threads, router, config, client);
+                    let config = Arc::clone(config);
+                    slot.spawn(move || run_http2(conn, None, &requests, &log, &config));
+                }
+                None => http2::refuse(conn.get_mut()),
+            }
+            break;
+        }
 
-        let connection_close: bool = connection.to_lowercase() == "close";
+        let head = match request::parse_head(&head, limits) {
+            Ok(head) => head,
+            Err(e) => {
+                if let Some(status) = reject(conn.get_mut(), e) {
+                    log(None, status, 0, started);
+                }
+                break;
+            }
+        };
 
-        let response = process_request(
-            method,
-            req_path_edited,
-            &user_agent_kind,
-            req_body,
-            directory,
-            &accept_encoding,
-            connection_close,
-        );
+        let read_result = if head.chunked {
+            conn.read_chunked_body(limits)
+        } else {
+            conn.read_body(head.content_length, limits)
+                .map(|body| (body, Vec::new()))
+        };
 
-        let response_bytes = response.format_bytes();
-        let _ = _stream.write(&response_bytes);
+        let (req_body, _trailers) = match read_result {
+            Ok(body) => body,
+            Err(e) => {
+                if let Some(status) = reject(conn.get_mut(), e) {
+                    log(None, status, 0, started);
+                }
+                break;
+            }
+        };
 
-        if connection_close {
+        let request = head.into_request(req_body);
+
+        // the response goes out on stream 1 once the connection switched, the
+        // upgrade is ignored over TLS like the preface, and while no thread is
+        // free for the connection
+        let http2_slot = if !secure && http2::is_upgrade(&request) {
+            threads.detached.reserve()
+        } else {
+            None
+        };
+        if let Some(slot) = http2_slot {
+            if let Err(e) = http2::switching_protocols().write_to(conn.get_mut()) {
+                println!("err while writing response: {}", e);
+                break;
+            }
+            let requests = http2_requests(threads, router, config, client);
+            let config = Arc::clone(config);
+            slot.spawn(move || run_http2(conn, Some(request), &requests, &log, &config));
             break;
         }
-    }
-}
 
-fn parse_headers(
-    header_parts: &[&str],
-    user_agent_kind: &mut String,
-    host: &mut String,
-    accept: &mut String,
-    req_content_type: &mut String,
-    req_content_len: &mut usize,
-    accept_encoding: &mut Vec<String>,
-    connection: &mut String,
-) {
-    for part in header_parts {
-        let headers = part.split(": ").collect::<Vec<&str>>();
-        match headers[0].to_lowercase().as_str() {
-            "host" => {
-                *host = headers[1].to_string();
-            }
-            "user-agent" => {
-                *user_agent_kind = headers[1].to_string();
-            }
-            "accept" => {
-                *accept = headers[1].to_string();
-            }
-            "content-type" => {
-                *req_content_type = headers[1].to_string();
-            }
-            "content-length" => {
-                *req_content_len = headers[1].parse::<usize>().unwrap();
+        let mut ctx = Context {
+            request: &request,
+            params: HashMap::new(),
+            directory: &config.directory,
+            client,
+            secure,
+        };
+
+        served += 1;
+        let mut response = process_request(router, &mut ctx).for_version(request.version);
+        let mut upgrade = response.take_upgrade();
+
+        // event streams and WebSockets last as long as their clients want, they
+        // move to a thread of their own so the worker is free for other connections
+        let mut slot = None;
+        if upgrade.is_some() || response.is_open_ended() {
+            slot = threads.detached.reserve();
+            if slot.is_none() {
+                upgrade = None;
+                response =
+                    Response::new(StatusCode::ServiceUnavailable, ContentType::TextPlain, "");
             }
-            "accept-encoding" => {
-                *accept_encoding = headers[1]
-                    .split(", ")
-                    .map(|s| s.to_string())
-                    .collect::<Vec<String>>();
+        }
+
+        let persistent = slot.is_none()
+            && request.persistent()
+            && served < keep_alive.max_requests
+            && !response.closes_connection();
+
+        let mut response = if upgrade.is_some() {
+            response
+        } else if persistent {
+            let response = response.with_header(
+                "Keep-Alive",
+                &format!(
+                    "timeout={}, max={}",
+                    timeouts.idle.as_secs(),
+                    keep_alive.max_requests - served
+                ),
+            );
+            // persistence is the default in HTTP/1.1 only
+            if request.version == Version::Http10 {
+                response.with_header("Connection", "keep-alive")
+            } else {
+                response
             }
-            "connection" => {
-                *connection = headers[1].to_string();
+        } else {
+            response.close_connection(true)
+        };
+
+        if let Some(slot) = slot {
+            let log = log.clone();
+            let params = std::mem::take(&mut ctx.params);
+            let directory = config.directory.clone();
+            let client = client.to_string();
+            let (ws_idle_timeout, max_message) = (config.ws_idle_timeout, limits.max_body_bytes);
+            slot.spawn(move || {
+                match response.write_to(conn.get_mut()) {
+                    Ok(bytes) => log(Some(&request), response.status_code(), bytes, started),
+                    Err(e) => {
+                        println!("err while writing response: {}", e);
+                        return;
+                    }
+                }
+                if let Some(upgrade) = upgrade {
+                    let ctx = Context {
+                        request: &request,
+                        params,
+                        directory: &directory,
+                        client: &client,
+                        secure,
+                    };
+                    run_websocket(conn, &ctx, upgrade, ws_idle_timeout, max_message);
+                }
+            });
+            break;
+        }
+
+        match response.write_to(conn.get_mut()) {
+            Ok(bytes) => log(Some(&request), response.status_code(), bytes, started),
+            Err(e) => {
+                println!("err while writing response: {}", e);
+                break;
             }
-            _ => {}
+        }
+
+        if !persistent {
+            break;
         }
     }
 }
 
-fn process_request(
-    method: &str,
-    path: &str,
-    user_agent: &str,
-    body: &str,
-    directory: &str,
-    accept_encoding: &Vec<String>,
-    connection_close: bool,
-) -> Response {
-    let req_path_parts = path.split("/").collect::<Vec<&str>>();
-
-    let mut parsed_encoding = None;
-    for encoding in accept_encoding {
-        if encoding == "gzip" {
-            parsed_encoding = Some(AcceptEncoding::Gzip);
-            break;
+// HTTP/2 streams are answered on the workers, the connection itself stays
+// open indefinitely and runs on a detached thread so it never waits for a
+// worker while holding one
+fn http2_requests(
+    threads: &Threads,
+    router: &Arc<Router>,
+    config: &Arc<Config>,
+    client: &str,
+) -> http2::Requests {
+    let (router, config, client) = (Arc::clone(router), Arc::clone(config), client.to_string());
+    http2::Requests {
+        handler: Arc::new(move |request: &Request| {
+            let mut ctx = Context {
+                request,
+                params: HashMap::new(),
+                directory: &config.directory,
+                client: &client,
+                // h2c never runs over TLS
+                secure: false,
+            };
+            process_request(&router, &mut ctx)
+        }),
+        pool: Arc::clone(&threads.pool),
+        detached: threads.detached.clone(),
+    }
+}
+
+fn run_http2<S: Socket>(
+    mut conn: Connection<S>,
+    upgraded: Option<Request>,
+    requests: &http2::Requests,
+    log: &http2::Log,
+    config: &Config,
+) {
+    let buffered = conn.take_buffered();
+    let stream = conn.into_inner();
+    http2::serve(
+        stream.tcp(),
+        buffered,
+        upgraded,
+        requests,
+        log,
+        &config.limits,
+        &config.timeouts,
+    );
+}
+
+// the connection belongs to the WebSocket from here on, the client may stay
+// silent for idle_timeout before it's pinged
+fn run_websocket<S: Socket + 'static>(
+    mut conn: Connection<S>,
+    ctx: &Context,
+    upgrade: Upgrade,
+    idle_timeout: Duration,
+    max_message: usize,
+) {
+    let buffered = conn.take_buffered();
+    let stream = conn.into_inner();
+    let socket = stream
+        .set_read_timeout(Some(idle_timeout))
+        .and_then(|()| WebSocket::new(Box::new(stream), buffered, max_message));
+    let mut socket = match socket {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("err: {}", e);
+            return;
         }
+    };
+    upgrade(ctx, &mut socket);
+    if let Err(e) = socket.close(websocket::NORMAL_CLOSURE) {
+        println!("err while closing websocket: {}", e);
     }
+}
 
-    if req_path_parts.is_empty() {
-        return Response::new(
-            StatusCode::Ok,
-            parsed_encoding,
-            ContentType::TextPlain,
-            "",
-            connection_close,
-        );
+// answers a request that couldn't be read, returns the status code it got
+fn reject<W: Write>(stream: &mut W, e: ReadError) -> Option<u16> {
+    let status = match e {
+        ReadError::Closed => return None,
+        ReadError::Io(e) => {
+            println!("err while reading request: {}", e);
+            return None;
+        }
+        ReadError::HeadersTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+        ReadError::UriTooLong => StatusCode::UriTooLong,
+        ReadError::BodyTooLarge => StatusCode::PayloadTooLarge,
+        ReadError::Malformed => StatusCode::BadRequest,
+        ReadError::UnsupportedVersion => StatusCode::HttpVersionNotSupported,
+        ReadError::UnsupportedTransferCoding => StatusCode::NotImplemented,
+        ReadError::TimedOut => StatusCode::RequestTimeout,
+    };
+
+    let mut response = Response::new(status, ContentType::TextPlain, "").close_connection(true);
+    let _ = response.write_to(stream);
+    Some(response.status_code())
+}
+
+fn process_request(router: &Router, ctx: &mut Context) -> Response {
+    if ctx.method() == "OPTIONS" && ctx.path() == "*" {
+        return Response::new(StatusCode::NoContent, ContentType::TextPlain, "")
+            .with_allow(&router.methods());
     }
 
-    match req_path_parts[0] {
-        "echo" => {
-            if req_path_parts.len() > 1 {
-                Response::new(
-                    StatusCode::Ok,
-                    parsed_encoding,
-                    ContentType::TextPlain,
-                    req_path_parts[1],
-                    connection_close,
-                )
-            } else {
-                Response::new(
-                    StatusCode::NotFound,
-                    parsed_encoding,
-                    ContentType::TextPlain,
-                    "",
-                    connection_close,
-                )
+    let accept_encoding: Vec<String> = ctx
+        .request
+        .headers
+        .list("Accept-Encoding")
+        .map(|coding| coding.to_string())
+        .collect();
+
+    // a content coding only matters once there's a body to encode
+    let encoding = AcceptEncoding::negotiate(&accept_encoding);
+    let mut response = match router.dispatch(ctx) {
+        Dispatch::Handled(response) => match encoding {
+            Ok(encoding) => response.compress(encoding),
+            Err(NotAcceptable) if response.has_compressible_body() => {
+                Response::new(StatusCode::NotAcceptable, ContentType::TextPlain, "")
+                    .with_header("Vary", "Accept-Encoding")
             }
+            Err(NotAcceptable) => response,
+        },
+        Dispatch::Options(allowed) => {
+            Response::new(StatusCode::NoContent, ContentType::TextPlain, "").with_allow(&allowed)
         }
-        "" => Response::new(
-            StatusCode::Ok,
-            parsed_encoding,
-            ContentType::TextPlain,
-            "",
-            connection_close,
-        ),
-        "user-agent" => Response::new(
-            StatusCode::Ok,
-            parsed_encoding,
-            ContentType::TextPlain,
-            user_agent,
-            connection_close,
-        ),
-        "files" => {
-            if req_path_parts.len() < 2 {
-                Response::new(
-                    StatusCode::NotFound,
-                    parsed_encoding,
-                    ContentType::TextPlain,
-                    "",
-                    connection_close,
-                )
-            } else {
-                let file_path = format!("{}/{}", directory, req_path_parts[1]);
-                if method == "POST" {
-                    let result = fs::write(&file_path, body);
-                    match result {
-                        Ok(_result) => Response::new(
-                            StatusCode::Created,
-                            parsed_encoding,
-                            ContentType::TextPlain,
-                            "",
-                            connection_close,
-                        ),
-                        Err(e) => {
-                            println!("err: {e}");
-                            Response::new(
-                                StatusCode::InternalServerError,
-                                parsed_encoding,
-                                ContentType::TextPlain,
-                                "",
-                                connection_close,
-                            )
-                        }
-                    }
-                } else {
-                    let contents = fs::read(&file_path);
-                    match contents {
-                        Ok(bytes) => {
-                            let content_type = if req_path_parts[1].ends_with(".html") {
-                                ContentType::TextHtml
-                            } else {
-                                ContentType::ApplicationOctetStream
-                            };
-
-                            let body = String::from_utf8_lossy(&bytes)
-                                .trim_end_matches('\n')
-                                .to_string();
-                            Response::new(
-                                StatusCode::Ok,
-                                parsed_encoding,
-                                content_type,
-                                &body,
-                                connection_close,
-                            )
-                        }
-                        Err(e) => {
-                            println!("err: {e}");
-                            Response::new(
-                                StatusCode::NotFound,
-                                parsed_encoding,
-                                ContentType::TextPlain,
-                                "",
-                                connection_close,
-                            )
-                        }
-                    }
-                }
-            }
+        Dispatch::MethodNotAllowed(allowed) => {
+            Response::new(StatusCode::MethodNotAllowed, ContentType::TextPlain, "")
+                .with_allow(&allowed)
         }
-        _ => Response::new(
-            StatusCode::NotFound,
-            parsed_encoding,
-            ContentType::TextPlain,
-            "",
-            connection_close,
-        ),
+        Dispatch::NotFound => Response::new(StatusCode::NotFound, ContentType::TextPlain, ""),
+    };
+
+    // lets clients correlate responses with their requests
+    if let Some(request_id) = ctx.request.headers.get("X-Request-Id") {
+        response.headers_mut().set("X-Request-Id", request_id);
     }
+
+    response
 }
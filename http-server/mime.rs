@@ -0,0 +1,183 @@
+use crate::response::ContentType;
+use std::collections::HashMap;
+use std::path::Path;
+
+const DEFAULT_TYPES: [(&str, &str); 38] = [
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("bin", "application/octet-stream"),
+    ("exe", "application/octet-stream"),
+    ("iso", "application/octet-stream"),
+];
+
+// how much of the content sniffing looks at
+pub const SNIFF_LEN: u64 = 512;
+
+// leading bytes of formats that can be recognised without an extension
+const MAGIC_BYTES: [(&[u8], &str); 9] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"\0asm", "application/wasm"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"OggS", "audio/ogg"),
+];
+
+pub struct MimeRegistry {
+    types: HashMap<String, String>,
+}
+
+impl MimeRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self {
+            types: HashMap::new(),
+        };
+        for (extension, mime) in DEFAULT_TYPES {
+            registry.register(extension, mime);
+        }
+        registry
+    }
+
+    pub fn register(&mut self, extension: &str, mime: &str) {
+        self.types
+            .insert(extension.to_ascii_lowercase(), mime.to_string());
+    }
+
+    // by extension first, falls back to sniffing the content
+    pub fn content_type(&self, path: &Path, contents: &[u8]) -> ContentType {
+        let by_extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| self.types.get(&extension.to_ascii_lowercase()));
+
+        match by_extension {
+            Some(mime) => ContentType::Mime(mime.clone()),
+            None => sniff(contents),
+        }
+    }
+}
+
+fn sniff(contents: &[u8]) -> ContentType {
+    for (magic, mime) in MAGIC_BYTES {
+        if contents.starts_with(magic) {
+            return ContentType::Mime(mime.to_string());
+        }
+    }
+
+    if contents.len() >= 12 && &contents[..4] == b"RIFF" && &contents[8..12] == b"WEBP" {
+        return ContentType::Mime("image/webp".to_string());
+    }
+
+    let head = &contents[..contents.len().min(SNIFF_LEN as usize)];
+    let text = String::from_utf8_lossy(head);
+    let start = text.trim_start().to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        return ContentType::TextHtml;
+    }
+
+    let binary = head
+        .iter()
+        .any(|&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c));
+    // a cut off multi-byte character at the end of the sample is fine
+    let utf8 = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+
+    if !binary && utf8 {
+        ContentType::Mime("text/plain; charset=utf-8".to_string())
+    } else {
+        ContentType::ApplicationOctetStream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content_type(name: &str, contents: &[u8]) -> String {
+        MimeRegistry::new()
+            .content_type(Path::new(name), contents)
+            .str()
+            .to_string()
+    }
+
+    #[test]
+    fn detects_by_extension() {
+        assert_eq!(content_type("index.html", b""), "text/html; charset=utf-8");
+        assert_eq!(content_type("photo.JPG", b""), "image/jpeg");
+        assert_eq!(content_type("dir/app.wasm", b""), "application/wasm");
+        // the extension wins over the content
+        assert_eq!(
+            content_type("notes.txt", b"\x89PNG\r\n\x1a\n"),
+            "text/plain; charset=utf-8"
+        );
+
+        let mut registry = MimeRegistry::new();
+        registry.register("Rs", "text/rust");
+        assert_eq!(
+            registry.content_type(Path::new("main.rs"), b"").str(),
+            "text/rust"
+        );
+    }
+
+    #[test]
+    fn sniffs_without_a_known_extension() {
+        assert_eq!(content_type("image", b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(content_type("image.unknown", b"GIF89a...."), "image/gif");
+        assert_eq!(content_type("anim", b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(content_type("page", b"  <!DOCTYPE html><p>"), "text/html");
+        assert_eq!(
+            content_type("README", b"plain text\n"),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            content_type("data", b"\0\x01\x02"),
+            "application/octet-stream"
+        );
+        assert_eq!(
+            content_type("latin1", b"caf\xe9 au lait"),
+            "application/octet-stream"
+        );
+        // a multi-byte character cut off at the end of the sample is still text
+        let mut text = vec![b'a'; SNIFF_LEN as usize - 1];
+        text.extend("é".as_bytes());
+        assert_eq!(content_type("cut", &text), "text/plain; charset=utf-8");
+    }
+}
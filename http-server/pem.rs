@@ -0,0 +1,143 @@
+// certificate chains and private keys from PEM files, with just enough DER
+// to find the key material inside PKCS#8 and PKCS#1 structures
+use crate::base64;
+use crate::curve25519::Ed25519Key;
+use crate::rsa::RsaKey;
+
+const SEQUENCE: u8 = 0x30;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+
+// 1.3.101.112 and 1.2.840.113549.1.1.1
+const ED25519_OID: &[u8] = &[0x2b, 0x65, 0x70];
+const RSA_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+pub enum PrivateKey {
+    Ed25519(Ed25519Key),
+    Rsa(RsaKey),
+}
+
+// the DER of every CERTIFICATE block, leaf first as in the file
+pub fn certificates(text: &str) -> Result<Vec<Vec<u8>>, String> {
+    let chain: Vec<Vec<u8>> = blocks(text)?
+        .into_iter()
+        .filter(|(label, _)| label == "CERTIFICATE")
+        .map(|(_, der)| der)
+        .collect();
+
+    if chain.is_empty() {
+        return Err("no CERTIFICATE block found".to_string());
+    }
+    Ok(chain)
+}
+
+pub fn private_key(text: &str) -> Result<PrivateKey, String> {
+    for (label, der) in blocks(text)? {
+        let key = match label.as_str() {
+            "PRIVATE KEY" => pkcs8(&der),
+            "RSA PRIVATE KEY" => pkcs1(&der),
+            _ => continue,
+        };
+        return key.ok_or_else(|| format!("unsupported or malformed {label}"));
+    }
+    Err("no PRIVATE KEY or RSA PRIVATE KEY block found".to_string())
+}
+
+fn blocks(text: &str) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut blocks = Vec::new();
+    let mut lines = text.lines();
+
+    while let Some(line) = lines.next() {
+        let label = match line
+            .trim()
+            .strip_prefix("-----BEGIN ")
+            .and_then(|rest| rest.strip_suffix("-----"))
+        {
+            Some(label) => label,
+            None => continue,
+        };
+
+        let end = format!("-----END {label}-----");
+        let mut body = String::new();
+        loop {
+            let line = lines.next().ok_or_else(|| format!("missing {end}"))?.trim();
+            if line == end {
+                break;
+            }
+            body.push_str(line);
+        }
+
+        let der = base64::decode(&body).ok_or_else(|| format!("invalid base64 in {label}"))?;
+        blocks.push((label.to_string(), der));
+    }
+
+    Ok(blocks)
+}
+
+// PrivateKeyInfo: version, algorithm identifier, key in an octet string
+fn pkcs8(der: &[u8]) -> Option<PrivateKey> {
+    let mut info = Der::new(Der::new(der).read(SEQUENCE)?);
+    info.read(INTEGER)?;
+    let algorithm = Der::new(info.read(SEQUENCE)?).read(OBJECT_IDENTIFIER)?;
+    let key = info.read(OCTET_STRING)?;
+
+    if algorithm == ED25519_OID {
+        let seed = Der::new(key).read(OCTET_STRING)?;
+        if seed.len() != 32 {
+            return None;
+        }
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(seed);
+        Some(PrivateKey::Ed25519(Ed25519Key::from_seed(bytes)))
+    } else if algorithm == RSA_OID {
+        pkcs1(key)
+    } else {
+        None
+    }
+}
+
+// RSAPrivateKey: version, modulus, public exponent, private exponent, ...
+fn pkcs1(der: &[u8]) -> Option<PrivateKey> {
+    let mut key = Der::new(Der::new(der).read(SEQUENCE)?);
+    key.read(INTEGER)?;
+    let modulus = key.read(INTEGER)?;
+    let public_exponent = key.read(INTEGER)?;
+    let private_exponent = key.read(INTEGER)?;
+
+    RsaKey::new(modulus, public_exponent, private_exponent).map(PrivateKey::Rsa)
+}
+
+struct Der<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    // the contents of the next element, which has to carry the given tag
+    fn read(&mut self, tag: u8) -> Option<&'a [u8]> {
+        if *self.data.first()? != tag {
+            return None;
+        }
+
+        let first = *self.data.get(1)? as usize;
+        let (len, header) = if first < 0x80 {
+            (first, 2)
+        } else {
+            let count = first & 0x7f;
+            if count == 0 || count > 4 {
+                return None;
+            }
+            let bytes = self.data.get(2..2 + count)?;
+            let len = bytes.iter().fold(0, |len, &b| (len << 8) | b as usize);
+            (len, 2 + count)
+        };
+
+        let contents = self.data.get(header..header + len)?;
+        self.data = &self.data[header + len..];
+        Some(contents)
+    }
+}
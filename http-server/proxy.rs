@@ -0,0 +1,792 @@
+// reverse proxy routes: requests under a path prefix are forwarded to one of
+// several upstream HTTP/1.1 servers over pooled keep-alive connections;
+// response bodies are relayed as they're read, so an upstream failing before
+// its head is through turns into a 502 and one failing halfway through the
+// body cuts the client's connection short
+use crate::headers::Headers;
+use crate::request::{self, Connection, Limits, ReadError, Timeouts, Version};
+use crate::response::{self, ContentType, Response};
+use crate::router::Context;
+use crate::statuscode::StatusCode;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// idle connections kept per upstream
+const MAX_IDLE: usize = 8;
+// below the keep-alive timeout most servers use, so a pooled connection isn't
+// closed by the upstream just as a request goes out on it
+const MAX_IDLE_TIME: Duration = Duration::from_secs(4);
+
+// fields that describe a single connection, never forwarded (RFC 9110 7.6.1)
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+// a path prefix and the upstreams that serve it, as given to --proxy
+pub struct ProxyRoute {
+    pub prefix: String,
+    pub upstreams: Vec<String>,
+}
+
+impl ProxyRoute {
+    // "/api=127.0.0.1:8001,127.0.0.1:8002"
+    pub fn parse(value: &str) -> Option<ProxyRoute> {
+        let (prefix, upstreams) = value.split_once('=')?;
+        if !prefix.starts_with('/') {
+            return None;
+        }
+
+        let upstreams: Vec<String> = upstreams
+            .split(',')
+            .map(|upstream| upstream.trim().to_string())
+            .collect();
+        let valid = upstreams.iter().all(|upstream| {
+            upstream
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+        });
+        if !valid {
+            return None;
+        }
+
+        Some(ProxyRoute {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            upstreams,
+        })
+    }
+
+    // the prefix itself and everything below it
+    pub fn pattern(&self) -> String {
+        format!("{}/*", self.prefix)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Balance {
+    RoundRobin,
+    // the upstream with the fewest requests in flight, ties taken in turn
+    LeastConnections,
+}
+
+impl Balance {
+    pub fn parse(value: &str) -> Option<Balance> {
+        match value {
+            "round-robin" => Some(Balance::RoundRobin),
+            "least-connections" => Some(Balance::LeastConnections),
+            _ => None,
+        }
+    }
+}
+
+struct Upstream {
+    address: String,
+    // connections with nothing in flight and when they were last used
+    idle: Mutex<Vec<(Connection<TcpStream>, Instant)>>,
+    // requests in flight
+    active: AtomicUsize,
+}
+
+impl Upstream {
+    fn idle(&self) -> MutexGuard<'_, Vec<(Connection<TcpStream>, Instant)>> {
+        match self.idle.lock() {
+            Ok(idle) => idle,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    // the most recently used connection that isn't too old, stale ones are dropped
+    fn take_idle(&self) -> Option<Connection<TcpStream>> {
+        let mut idle = self.idle();
+        idle.retain(|(_, since)| since.elapsed() < MAX_IDLE_TIME);
+        idle.pop().map(|(conn, _)| conn)
+    }
+
+    fn put_idle(&self, conn: Connection<TcpStream>) {
+        let mut idle = self.idle();
+        if idle.len() < MAX_IDLE {
+            idle.push((conn, Instant::now()));
+        }
+    }
+}
+
+// counts a request as in flight for as long as it lives
+struct Active(Arc<Upstream>);
+
+impl Active {
+    fn new(upstream: &Arc<Upstream>) -> Self {
+        upstream.active.fetch_add(1, Ordering::SeqCst);
+        Self(Arc::clone(upstream))
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+enum Failure {
+    // the upstream closed the connection before it answered
+    Closed,
+    TimedOut,
+    Io(io::Error),
+    // whatever came back isn't a response that can be relayed
+    Invalid(&'static str),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Closed => write!(f, "connection closed"),
+            Failure::TimedOut => write!(f, "timed out"),
+            Failure::Io(e) => write!(f, "{}", e),
+            Failure::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+            Failure::TimedOut
+        } else if response::is_disconnect(&e) {
+            Failure::Closed
+        } else {
+            Failure::Io(e)
+        }
+    }
+}
+
+// how an upstream body is delimited (RFC 9112 6.3)
+enum Framing {
+    // bytes still to come
+    Length(u64),
+    // bytes left of the current chunk, 0 when a size line comes next
+    Chunked(usize),
+    Close,
+}
+
+// an upstream response head as read by round_trip
+struct Head {
+    status: u16,
+    headers: Headers,
+    framing: Framing,
+    // the connection can carry the next request once the body is through
+    reusable: bool,
+}
+
+// an upstream response with its body still on the connection
+struct Answer {
+    status: u16,
+    headers: Headers,
+    body: UpstreamBody,
+}
+
+// the body of an upstream response, read off the connection as the client
+// takes it; the request stays in flight until the body is through, and the
+// connection then goes back to the pool
+struct UpstreamBody {
+    // gone once the body is through or broken off
+    conn: Option<Connection<TcpStream>>,
+    framing: Framing,
+    reusable: bool,
+    limits: Limits,
+    active: Active,
+}
+
+impl UpstreamBody {
+    fn next(&mut self, buf: &mut [u8]) -> Result<usize, ReadError> {
+        let Some(conn) = self.conn.as_mut() else {
+            return Ok(0);
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let bytes = match &mut self.framing {
+            Framing::Length(left) => {
+                let want = (*left).min(buf.len() as u64) as usize;
+                let bytes = if want == 0 {
+                    0
+                } else {
+                    conn.read_some(&mut buf[..want])?
+                };
+                *left -= bytes as u64;
+                bytes
+            }
+            Framing::Chunked(left) => {
+                if *left == 0 {
+                    *left = request::parse_chunk_size(&conn.read_line(&self.limits)?)?;
+                }
+                if *left == 0 {
+                    // the last chunk, trailers aren't relayed
+                    while !conn.read_line(&self.limits)?.is_empty() {}
+                    0
+                } else {
+                    let want = (*left).min(buf.len());
+                    let bytes = conn.read_some(&mut buf[..want])?;
+                    *left -= bytes;
+                    if *left == 0 && !conn.read_line(&self.limits)?.is_empty() {
+                        return Err(ReadError::Malformed);
+                    }
+                    bytes
+                }
+            }
+            Framing::Close => match conn.read_some(buf) {
+                Err(ReadError::Closed) => 0,
+                result => result?,
+            },
+        };
+
+        // a body of known length is through with its last byte, without
+        // waiting for a read that comes back empty
+        if bytes == 0 || matches!(self.framing, Framing::Length(0)) {
+            self.finish();
+        }
+        Ok(bytes)
+    }
+
+    fn finish(&mut self) {
+        if let Some(mut conn) = self.conn.take() {
+            // anything past the body means the framing went wrong somewhere
+            if self.reusable && conn.take_buffered().is_empty() {
+                self.active.0.put_idle(conn);
+            }
+        }
+    }
+}
+
+impl Read for UpstreamBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.next(buf).map_err(|e| {
+            self.conn = None;
+            body_error(&self.active.0.address, e)
+        })
+    }
+}
+
+pub struct Proxy {
+    upstreams: Vec<Arc<Upstream>>,
+    balance: Balance,
+    // where the next pick starts
+    next: AtomicUsize,
+    // bounds connecting and every single read or write
+    timeout: Duration,
+    limits: Limits,
+}
+
+impl Proxy {
+    pub fn new(route: &ProxyRoute, balance: Balance, timeout: Duration, limits: Limits) -> Self {
+        let upstreams = route
+            .upstreams
+            .iter()
+            .map(|address| {
+                Arc::new(Upstream {
+                    address: address.clone(),
+                    idle: Mutex::new(Vec::new()),
+                    active: AtomicUsize::new(0),
+                })
+            })
+            .collect();
+        Self {
+            upstreams,
+            balance,
+            next: AtomicUsize::new(0),
+            timeout,
+            limits,
+        }
+    }
+
+    // 504 when the upstream took too long, 502 for any other failure
+    pub fn forward(&self, ctx: &Context) -> Response {
+        let upstream = self.pick();
+
+        match self.exchange(Active::new(upstream), ctx) {
+            Ok(answer) => relay(ctx, answer),
+            Err(failure) => {
+                println!("err: upstream {}: {}", upstream.address, failure);
+                let status = match failure {
+                    Failure::TimedOut => StatusCode::GatewayTimeout,
+                    _ => StatusCode::BadGateway,
+                };
+                Response::new(status, ContentType::TextPlain, "")
+            }
+        }
+    }
+
+    fn pick(&self) -> &Arc<Upstream> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let n = self.upstreams.len();
+        match self.balance {
+            Balance::RoundRobin => &self.upstreams[start % n],
+            Balance::LeastConnections => {
+                let mut best = &self.upstreams[start % n];
+                for i in 1..n {
+                    let upstream = &self.upstreams[(start + i) % n];
+                    if upstream.active.load(Ordering::SeqCst) < best.active.load(Ordering::SeqCst) {
+                        best = upstream;
+                    }
+                }
+                best
+            }
+        }
+    }
+
+    // a pooled connection may have been closed by the upstream in the meantime,
+    // the request is then sent again on a new one when that's safe to do
+    fn exchange(&self, active: Active, ctx: &Context) -> Result<Answer, Failure> {
+        let upstream = Arc::clone(&active.0);
+        let message = request_message(ctx, &upstream.address);
+
+        if let Some(mut conn) = upstream.take_idle() {
+            match self.round_trip(&mut conn, &message, ctx.method()) {
+                Ok(head) => return Ok(self.answer(head, conn, active)),
+                Err(Failure::Closed) if is_idempotent(ctx.method()) => {}
+                Err(failure) => return Err(failure),
+            }
+        }
+
+        let mut conn = self.connect(&upstream.address)?;
+        let head = self.round_trip(&mut conn, &message, ctx.method())?;
+        Ok(self.answer(head, conn, active))
+    }
+
+    fn answer(&self, head: Head, conn: Connection<TcpStream>, active: Active) -> Answer {
+        let mut body = UpstreamBody {
+            conn: Some(conn),
+            framing: head.framing,
+            reusable: head.reusable,
+            limits: self.limits.clone(),
+            active,
+        };
+        // nothing to read, the connection is free right away
+        if matches!(body.framing, Framing::Length(0)) {
+            body.finish();
+        }
+        Answer {
+            status: head.status,
+            headers: head.headers,
+            body,
+        }
+    }
+
+    fn connect(&self, address: &str) -> Result<Connection<TcpStream>, Failure> {
+        let mut last_error = Failure::Invalid("address resolves to nothing");
+        for addr in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    stream.set_nodelay(true)?;
+                    let timeouts = Timeouts {
+                        idle: self.timeout,
+                        header: self.timeout,
+                        read: self.timeout,
+                        write: self.timeout,
+                    };
+                    return Ok(Connection::new(stream, timeouts));
+                }
+                Err(e) => last_error = e.into(),
+            }
+        }
+        Err(last_error)
+    }
+
+    fn round_trip(
+        &self,
+        conn: &mut Connection<TcpStream>,
+        message: &[u8],
+        method: &str,
+    ) -> Result<Head, Failure> {
+        conn.get_mut().write_all(message)?;
+        conn.get_mut().flush()?;
+
+        // interim responses are skipped, the final one follows them
+        let sent = Instant::now();
+        let (version, status, headers) = loop {
+            let head = match conn.read_head(&self.limits) {
+                Ok(head) => head,
+                // no byte arrived within the idle timeout
+                Err(ReadError::Closed) if sent.elapsed() >= self.timeout => {
+                    return Err(Failure::TimedOut)
+                }
+                Err(e) => return Err(head_failure(e)),
+            };
+            let mut lines = head.split("\r\n");
+            let (version, status) = parse_status_line(lines.next().unwrap_or(""))
+                .ok_or(Failure::Invalid("malformed status line"))?;
+            let headers = request::parse_fields(lines, &self.limits).map_err(head_failure)?;
+            match status {
+                101 => return Err(Failure::Invalid("upstream switched protocols")),
+                100..=199 => continue,
+                _ => break (version, status, headers),
+            }
+        };
+
+        let codings: Vec<&str> = headers.list("Transfer-Encoding").collect();
+        let content_lengths: Vec<&str> = headers.get_all("Content-Length").collect();
+        let framing = if method == "HEAD" || status == 204 || status == 304 {
+            Framing::Length(0)
+        } else if codings
+            .last()
+            .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"))
+        {
+            Framing::Chunked(0)
+        } else if codings.is_empty() && !content_lengths.is_empty() {
+            let len = request::parse_content_length(&content_lengths)
+                .map_err(|_| Failure::Invalid("invalid Content-Length"))?;
+            Framing::Length(len as u64)
+        } else {
+            Framing::Close
+        };
+
+        let reusable = !matches!(framing, Framing::Close)
+            && version == Version::Http11
+            && !headers.has_token("Connection", "close");
+
+        // the head's deadline is over, each read of the body gets the full timeout
+        conn.get_mut().set_read_timeout(Some(self.timeout))?;
+
+        Ok(Head {
+            status,
+            headers,
+            framing,
+            reusable,
+        })
+    }
+}
+
+// the request as it goes upstream: Host names the upstream, the fields the
+// client's connection needed are dropped and the X-Forwarded ones tell where
+// the request came from
+fn request_message(ctx: &Context, upstream: &str) -> Vec<u8> {
+    let request = ctx.request;
+    let mut head = format!("{} {} HTTP/1.1\r\n", request.method, request.target);
+    head.push_str(&format!("Host: {}\r\n", upstream));
+
+    let listed: Vec<&str> = request.headers.list("Connection").collect();
+    let replaced = [
+        "Host",
+        "Content-Length",
+        "Expect",
+        "HTTP2-Settings",
+        "X-Forwarded-For",
+        "X-Forwarded-Proto",
+        "X-Forwarded-Host",
+    ];
+    for (name, value) in request.headers.iter() {
+        let skip = HOP_BY_HOP
+            .iter()
+            .chain(replaced.iter())
+            .chain(listed.iter())
+            .any(|skipped| skipped.eq_ignore_ascii_case(name));
+        if !skip {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+
+    let forwarded_for = match request.headers.combined("X-Forwarded-For") {
+        Some(earlier) => format!("{}, {}", earlier, ctx.client),
+        None => ctx.client.to_string(),
+    };
+    head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded_for));
+    let proto = if ctx.secure { "https" } else { "http" };
+    head.push_str(&format!("X-Forwarded-Proto: {}\r\n", proto));
+    if let Some(host) = request.headers.get("Host") {
+        head.push_str(&format!("X-Forwarded-Host: {}\r\n", host));
+    }
+
+    // the body was read in full, chunked or not, so its length is known
+    if !request.body.is_empty() || matches!(request.method.as_str(), "POST" | "PUT" | "PATCH") {
+        head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+    }
+    head.push_str("\r\n");
+
+    let mut message = head.into_bytes();
+    message.extend_from_slice(&request.body);
+    message
+}
+
+// the upstream's response as this server's own, minus its connection's fields
+fn relay(ctx: &Context, answer: Answer) -> Response {
+    let Answer {
+        status,
+        headers,
+        body,
+    } = answer;
+    let status = StatusCode::from_code(status);
+    let content_type = match headers.get("Content-Type") {
+        Some(content_type) => ContentType::Mime(content_type.to_string()),
+        None => ContentType::ApplicationOctetStream,
+    };
+
+    let mut response = if ctx.method() == "HEAD" {
+        // the length the upstream announced for the body it left out
+        let content_lengths: Vec<&str> = headers.get_all("Content-Length").collect();
+        match request::parse_content_length(&content_lengths) {
+            Ok(len) if !content_lengths.is_empty() => {
+                Response::stream(status, content_type, Box::new(io::empty()), len as u64)
+            }
+            _ => Response::chunked(status, content_type, Box::new(io::empty())),
+        }
+        .without_body()
+    } else {
+        match body.framing {
+            Framing::Length(len) => Response::stream(status, content_type, Box::new(body), len),
+            _ => Response::chunked(status, content_type, Box::new(body)),
+        }
+    };
+    // the upstream chose the encoding, and a HEAD left uncompressed this way
+    // gets the same fields as its GET
+    response = response.relayed();
+
+    let listed: Vec<&str> = headers.list("Connection").collect();
+    for (name, value) in headers.iter() {
+        let skip = HOP_BY_HOP
+            .iter()
+            .chain(["Content-Length", "Content-Type"].iter())
+            .chain(listed.iter())
+            .any(|skipped| skipped.eq_ignore_ascii_case(name));
+        if !skip {
+            response.headers_mut().append(name, value);
+        }
+    }
+    response
+}
+
+// "HTTP/1.1 200 OK", the reason phrase is ignored
+fn parse_status_line(line: &str) -> Option<(Version, u16)> {
+    let mut parts = line.splitn(3, ' ');
+    let version = match parts.next()? {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        _ => return None,
+    };
+    let code = parts.next()?;
+    if code.len() != 3 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((version, code.parse().ok()?))
+}
+
+// requests that can be sent twice without doing more than once (RFC 9110 9.2.2)
+fn is_idempotent(method: &str) -> bool {
+    matches!(
+        method,
+        "GET" | "HEAD" | "OPTIONS" | "TRACE" | "PUT" | "DELETE"
+    )
+}
+
+fn head_failure(e: ReadError) -> Failure {
+    match e {
+        ReadError::Closed => Failure::Closed,
+        ReadError::TimedOut => Failure::TimedOut,
+        ReadError::Io(e) => e.into(),
+        ReadError::HeadersTooLarge | ReadError::UriTooLong => {
+            Failure::Invalid("response head too large")
+        }
+        _ => Failure::Invalid("malformed response head"),
+    }
+}
+
+// a failure partway through a body that's already being relayed
+fn body_error(address: &str, e: ReadError) -> io::Error {
+    let (kind, reason) = match e {
+        ReadError::Io(e) => (e.kind(), e.to_string()),
+        ReadError::TimedOut => (ErrorKind::TimedOut, "timed out".to_string()),
+        ReadError::Closed => (
+            ErrorKind::Other,
+            "connection closed during the body".to_string(),
+        ),
+        _ => (
+            ErrorKind::InvalidData,
+            "malformed response body".to_string(),
+        ),
+    };
+    io::Error::new(kind, format!("upstream {}: {}", address, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Request;
+    use crate::url;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn request(method: &str, target: &str, fields: &[(&str, &str)], body: &[u8]) -> Request {
+        let mut headers = Headers::new();
+        for (name, value) in fields {
+            headers.append(name, value);
+        }
+        Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            path: url::split_target(target).0.to_string(),
+            query: HashMap::new(),
+            version: Version::Http11,
+            headers,
+            body: body.to_vec(),
+        }
+    }
+
+    fn context(request: &Request) -> Context<'_> {
+        Context {
+            request,
+            params: HashMap::new(),
+            directory: "",
+            client: "10.0.0.2",
+            secure: true,
+        }
+    }
+
+    fn proxy(upstream: &str) -> Proxy {
+        let route = ProxyRoute::parse(&format!("/api={upstream}")).unwrap();
+        Proxy::new(
+            &route,
+            Balance::RoundRobin,
+            Duration::from_secs(2),
+            Limits::default(),
+        )
+    }
+
+    #[test]
+    fn parses_routes() {
+        let route = ProxyRoute::parse("/api/=127.0.0.1:8001, localhost:8002").unwrap();
+        assert_eq!(route.prefix, "/api");
+        assert_eq!(route.upstreams, ["127.0.0.1:8001", "localhost:8002"]);
+        assert_eq!(route.pattern(), "/api/*");
+        assert_eq!(ProxyRoute::parse("/=[::1]:80").unwrap().pattern(), "/*");
+
+        for value in [
+            "api=h:1",
+            "/api",
+            "/api=",
+            "/api=h",
+            "/api=:80",
+            "/api=h:99999",
+            "/api=h:1,",
+        ] {
+            assert!(ProxyRoute::parse(value).is_none(), "{value}");
+        }
+    }
+
+    #[test]
+    fn parses_status_lines() {
+        assert!(parse_status_line("HTTP/1.1 200 OK") == Some((Version::Http11, 200)));
+        assert!(parse_status_line("HTTP/1.0 404") == Some((Version::Http10, 404)));
+        assert!(parse_status_line("HTTP/1.1 204 No Content Here") == Some((Version::Http11, 204)));
+        for line in [
+            "HTTP/2 200 OK",
+            "HTTP/1.1 20 OK",
+            "HTTP/1.1 2000",
+            "HTTP/1.1 +20",
+            "",
+        ] {
+            assert!(parse_status_line(line).is_none(), "{line}");
+        }
+    }
+
+    #[test]
+    fn rewrites_the_request_for_the_upstream() {
+        let request = request(
+            "POST",
+            "/api/items?x=1",
+            &[
+                ("Host", "example.com"),
+                ("Connection", "keep-alive, X-Secret"),
+                ("X-Secret", "1"),
+                ("Keep-Alive", "timeout=5"),
+                ("Transfer-Encoding", "chunked"),
+                ("X-Forwarded-For", "192.0.2.1"),
+                ("Accept", "*/*"),
+            ],
+            b"body",
+        );
+        let message = request_message(&context(&request), "127.0.0.1:8001");
+        assert_eq!(
+            String::from_utf8(message).unwrap(),
+            "POST /api/items?x=1 HTTP/1.1\r\n\
+             Host: 127.0.0.1:8001\r\n\
+             Accept: */*\r\n\
+             X-Forwarded-For: 192.0.2.1, 10.0.0.2\r\n\
+             X-Forwarded-Proto: https\r\n\
+             X-Forwarded-Host: example.com\r\n\
+             Content-Length: 4\r\n\
+             \r\n\
+             body"
+        );
+    }
+
+    #[test]
+    fn relays_responses_over_a_pooled_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        // one connection that answers two requests, a second connection
+        // would never be accepted
+        let upstream = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            for response in [
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\
+                 Connection: X-Hop\r\nX-Hop: 1\r\nX-Kept: 1\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\n\r\ngone",
+            ] {
+                let mut line = String::new();
+                while line != "\r\n" {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                }
+                writer.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let proxy = proxy(&address);
+        let read = |response: Response| {
+            let (status, headers, body) = response.into_http2();
+            let mut text = String::new();
+            body.unwrap().read_to_string(&mut text).unwrap();
+            (status, headers, text)
+        };
+
+        let first = request("GET", "/api/a", &[], b"");
+        let (status, headers, text) = read(proxy.forward(&context(&first)));
+        assert_eq!((status, text.as_str()), (200, "hello"));
+        assert_eq!(headers.get("Content-Type"), Some("text/plain"));
+        assert_eq!(headers.get("X-Kept"), Some("1"));
+        assert!(headers.get("X-Hop").is_none());
+
+        let second = request("GET", "/api/b", &[], b"");
+        let (status, _, text) = read(proxy.forward(&context(&second)));
+        assert_eq!((status, text.as_str()), (404, "gone"));
+        upstream.join().unwrap();
+    }
+
+    #[test]
+    fn answers_502_when_the_upstream_is_down() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let request = request("GET", "/api", &[], b"");
+        assert_eq!(
+            proxy(&address).forward(&context(&request)).status_code(),
+            502
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use crate::response::{ContentType, Response};
+use crate::statuscode::StatusCode;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_RANGES: usize = 16;
+
+pub enum Ranges {
+    // inclusive first and last byte positions
+    Satisfiable(Vec<(u64, u64)>),
+    Unsatisfiable,
+}
+
+// None when the header isn't a valid bytes range set and has to be ignored
+pub fn parse(header: &str, len: u64) -> Option<Ranges> {
+    let (unit, set) = header.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let specs: Vec<&str> = set
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None;
+    }
+
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for spec in specs {
+        let (first, last) = spec.split_once('-')?;
+        let (first, last) = (first.trim(), last.trim());
+
+        if first.is_empty() {
+            // suffix range, the final n bytes
+            let suffix: u64 = last.parse().ok()?;
+            if suffix > 0 && len > 0 {
+                ranges.push((len.saturating_sub(suffix), len - 1));
+            }
+            continue;
+        }
+
+        let first: u64 = first.parse().ok()?;
+        let last: u64 = if last.is_empty() {
+            u64::MAX
+        } else {
+            last.parse().ok()?
+        };
+        if last < first {
+            return None;
+        }
+        if first < len {
+            ranges.push((first, last.min(len - 1)));
+        }
+    }
+
+    if ranges.is_empty() {
+        Some(Ranges::Unsatisfiable)
+    } else {
+        Some(Ranges::Satisfiable(ranges))
+    }
+}
+
+pub fn unsatisfiable(len: u64) -> Response {
+    Response::new(StatusCode::RangeNotSatisfiable, ContentType::TextPlain, "")
+        .with_header("Content-Range", &format!("bytes */{len}"))
+}
+
+// 206 with a single part, or multipart/byteranges for several ranges; the parts
+// are read from path as the response is written
+pub fn partial(
+    ranges: &[(u64, u64)],
+    path: &Path,
+    len: u64,
+    content_type: ContentType,
+) -> io::Result<Response> {
+    let section = |(first, last): (u64, u64)| -> io::Result<io::Take<File>> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(first))?;
+        Ok(file.take(last - first + 1))
+    };
+
+    if let [range] = ranges {
+        let response = Response::stream(
+            StatusCode::PartialContent,
+            content_type,
+            Box::new(section(*range)?),
+            range.1 - range.0 + 1,
+        );
+        return Ok(response.with_header(
+            "Content-Range",
+            &format!("bytes {}-{}/{len}", range.0, range.1),
+        ));
+    }
+
+    let boundary = boundary();
+    let mut body: Box<dyn Read + Send> = Box::new(io::empty());
+    let mut body_len = 0;
+    for &(first, last) in ranges {
+        let part_head = format!(
+            "\r\n--{boundary}\r\nContent-Type: {}\r\nContent-Range: bytes {first}-{last}/{len}\r\n\r\n",
+            content_type.str()
+        );
+        body_len += part_head.len() as u64 + last - first + 1;
+        body = Box::new(
+            body.chain(Cursor::new(part_head))
+                .chain(section((first, last))?),
+        );
+    }
+    let closing = format!("\r\n--{boundary}--\r\n");
+    body_len += closing.len() as u64;
+    body = Box::new(body.chain(Cursor::new(closing)));
+
+    Ok(Response::stream(
+        StatusCode::PartialContent,
+        ContentType::Mime(format!("multipart/byteranges; boundary={boundary}")),
+        body,
+        body_len,
+    ))
+}
+
+fn boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+    format!("byteranges_{:x}", nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(header: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+        match parse(header, len)? {
+            Ranges::Satisfiable(ranges) => Some(ranges),
+            Ranges::Unsatisfiable => Some(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn parses_range_sets() {
+        assert_eq!(ranges("bytes=0-99", 1000), Some(vec![(0, 99)]));
+        assert_eq!(ranges("bytes=500-", 1000), Some(vec![(500, 999)]));
+        assert_eq!(ranges("bytes=-200", 1000), Some(vec![(800, 999)]));
+        assert_eq!(ranges("bytes=-2000", 1000), Some(vec![(0, 999)]));
+        assert_eq!(ranges("bytes=900-1200", 1000), Some(vec![(900, 999)]));
+        assert_eq!(ranges("Bytes = 0-0, 5-9 ,", 10), Some(vec![(0, 0), (5, 9)]));
+        // unsatisfiable ranges are dropped as long as one is left
+        assert_eq!(ranges("bytes=2000-, 0-1", 1000), Some(vec![(0, 1)]));
+    }
+
+    #[test]
+    fn reports_unsatisfiable_sets() {
+        assert_eq!(ranges("bytes=1000-", 1000), Some(vec![]));
+        assert_eq!(ranges("bytes=-0", 1000), Some(vec![]));
+        assert_eq!(ranges("bytes=0-", 0), Some(vec![]));
+        assert_eq!(ranges("bytes=-5", 0), Some(vec![]));
+    }
+
+    #[test]
+    fn ignores_invalid_headers() {
+        for header in [
+            "",
+            "bytes",
+            "items=0-1",
+            "bytes=",
+            "bytes=,",
+            "bytes=5",
+            "bytes=5-4",
+            "bytes=a-b",
+            "bytes=-",
+            "bytes=--1",
+            "bytes=0-1-2",
+            "bytes=18446744073709551616-",
+        ] {
+            assert!(ranges(header, 1000).is_none(), "{header}");
+        }
+        let many = vec!["0-0"; MAX_RANGES + 1].join(",");
+        assert!(ranges(&format!("bytes={many}"), 1000).is_none());
+    }
+
+    #[test]
+    fn writes_single_and_multipart_bodies() {
+        let path = std::env::temp_dir().join(format!("range-{}", std::process::id()));
+        std::fs::write(&path, "0123456789").unwrap();
+
+        let (status, headers, body) = partial(&[(2, 4)], &path, 10, ContentType::TextPlain)
+            .unwrap()
+            .into_http2();
+        let mut text = String::new();
+        body.unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(status, 206);
+        assert_eq!(headers.get("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(text, "234");
+
+        let (_, headers, body) = partial(&[(0, 1), (8, 9)], &path, 10, ContentType::TextPlain)
+            .unwrap()
+            .into_http2();
+        let mut text = String::new();
+        body.unwrap().read_to_string(&mut text).unwrap();
+        let content_type = headers.get("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        assert_eq!(
+            text,
+            format!(
+                "\r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+                 \r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+                 \r\n--{boundary}--\r\n"
+            )
+        );
+        assert_eq!(
+            headers.get("Content-Length"),
+            Some(text.len().to_string().as_str())
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,656 @@
+use crate::headers::Headers;
+use crate::url;
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+const READ_CHUNK: usize = 1024;
+
+#[derive(Clone)]
+pub struct Limits {
+    pub max_header_bytes: usize,
+    pub max_headers: usize,
+    pub max_uri_len: usize,
+    pub max_body_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: 8 * 1024,
+            max_headers: 100,
+            max_uri_len: 4 * 1024,
+            max_body_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+pub enum ReadError {
+    Closed,
+    Io(io::Error),
+    HeadersTooLarge,
+    UriTooLong,
+    BodyTooLarge,
+    Malformed,
+    UnsupportedVersion,
+    UnsupportedTransferCoding,
+    TimedOut,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Version {
+    Http10,
+    Http11,
+    Http2,
+}
+
+impl Version {
+    pub fn str(&self) -> &str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+            Version::Http2 => "HTTP/2",
+        }
+    }
+}
+
+// what parse_head found out, including how the body is framed
+pub struct Head {
+    pub method: String,
+    pub target: String,
+    pub version: Version,
+    pub headers: Headers,
+    pub content_length: usize,
+    pub chunked: bool,
+}
+
+impl Head {
+    pub fn into_request(self, body: Vec<u8>) -> Request {
+        let (path, query) = url::split_target(&self.target);
+        Request {
+            path: path.to_string(),
+            query: url::parse_query(query),
+            method: self.method,
+            target: self.target,
+            version: self.version,
+            headers: self.headers,
+            body,
+        }
+    }
+}
+
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    // still percent-encoded, the router decodes it segment by segment
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    // HTTP/1.1 connections persist unless closed explicitly, HTTP/1.0 ones
+    // only when the client asks for keep-alive
+    pub fn persistent(&self) -> bool {
+        if self.headers.has_token("Connection", "close") {
+            return false;
+        }
+        match self.version {
+            Version::Http11 | Version::Http2 => true,
+            Version::Http10 => self.headers.has_token("Connection", "keep-alive"),
+        }
+    }
+}
+
+// idle is how long a connection may wait for the next request, header bounds
+// reading a whole head once it started arriving, read and write bound every
+// single socket operation after that
+#[derive(Clone, Copy)]
+pub struct Timeouts {
+    pub idle: Duration,
+    pub header: Duration,
+    pub read: Duration,
+    pub write: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            idle: Duration::from_secs(5),
+            header: Duration::from_secs(10),
+            read: Duration::from_secs(30),
+            write: Duration::from_secs(30),
+        }
+    }
+}
+
+// how many requests a persistent connection may carry
+pub struct KeepAlive {
+    pub max_requests: usize,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self { max_requests: 100 }
+    }
+}
+
+// streams whose reads can be bounded in time
+pub trait ReadTimeout {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+// a client connection with the bytes that have been read from it but not yet
+// consumed, several pipelined requests may arrive in one read and one request
+// may arrive over many
+pub struct Connection<S> {
+    stream: S,
+    buffer: Vec<u8>,
+    timeouts: Timeouts,
+}
+
+impl<S: Read + ReadTimeout> Connection<S> {
+    pub fn new(stream: S, timeouts: Timeouts) -> Self {
+        Self {
+            stream,
+            buffer: Vec::new(),
+            timeouts,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    // what was read past the last request, for a protocol taking over
+    pub fn take_buffered(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+
+    // reads until the blank line that ends the header section, whatever follows
+    // it stays buffered
+    pub fn read_head(&mut self, limits: &Limits) -> Result<String, ReadError> {
+        let mut deadline: Option<Instant> = None;
+
+        loop {
+            // empty lines ahead of the request line are ignored
+            while self.buffer.starts_with(b"\r\n") {
+                self.buffer.drain(..2);
+            }
+
+            if let Some(end) = find_head_end(&self.buffer) {
+                if end > limits.max_header_bytes {
+                    return Err(too_large(&self.buffer, limits));
+                }
+                let head = String::from_utf8_lossy(&self.buffer[..end]).to_string();
+                self.buffer.drain(..end + 4);
+                return Ok(head);
+            }
+
+            if self.buffer.len() > limits.max_header_bytes {
+                return Err(too_large(&self.buffer, limits));
+            }
+
+            // the header deadline starts with the first byte of the request
+            if deadline.is_none() && !self.buffer.is_empty() {
+                deadline = Some(Instant::now() + self.timeouts.header);
+            }
+            let timeout = match deadline {
+                Some(deadline) => deadline
+                    .checked_duration_since(Instant::now())
+                    .filter(|left| !left.is_zero())
+                    .ok_or(ReadError::TimedOut)?,
+                None => self.timeouts.idle,
+            };
+            self.stream.set_read_timeout(Some(timeout))?;
+
+            match self.read_more() {
+                // nothing of a next request arrived, not worth an answer
+                Err(ReadError::TimedOut) if deadline.is_none() => return Err(ReadError::Closed),
+                result => result?,
+            }
+        }
+    }
+
+    // reads exactly content_len body bytes
+    pub fn read_body(&mut self, content_len: usize, limits: &Limits) -> Result<Vec<u8>, ReadError> {
+        if content_len > limits.max_body_bytes {
+            return Err(ReadError::BodyTooLarge);
+        }
+
+        self.stream.set_read_timeout(Some(self.timeouts.read))?;
+        self.fill(content_len)?;
+        Ok(self.buffer.drain(..content_len).collect())
+    }
+
+    // decodes a chunked body, returns the payload and the raw trailer fields
+    pub fn read_chunked_body(
+        &mut self,
+        limits: &Limits,
+    ) -> Result<(Vec<u8>, Vec<String>), ReadError> {
+        let mut body: Vec<u8> = Vec::new();
+        self.stream.set_read_timeout(Some(self.timeouts.read))?;
+
+        loop {
+            let size = parse_chunk_size(&self.read_line(limits)?)?;
+            if size == 0 {
+                break;
+            }
+
+            // size is whatever the client sent, adding to it could overflow
+            if size > limits.max_body_bytes - body.len() {
+                return Err(ReadError::BodyTooLarge);
+            }
+
+            self.fill(size + 2)?;
+            if &self.buffer[size..size + 2] != b"\r\n" {
+                return Err(ReadError::Malformed);
+            }
+            body.extend(self.buffer.drain(..size + 2).take(size));
+        }
+
+        // the trailer section is held to the limits of a header section
+        let mut trailers: Vec<String> = Vec::new();
+        let mut trailer_bytes = 0;
+        loop {
+            let line = self.read_line(limits)?;
+            if line.is_empty() {
+                break;
+            }
+            trailer_bytes += line.len() + 2;
+            if trailer_bytes > limits.max_header_bytes || trailers.len() >= limits.max_headers {
+                return Err(ReadError::HeadersTooLarge);
+            }
+            trailers.push(line);
+        }
+
+        Ok((body, trailers))
+    }
+
+    // whatever body bytes come next, up to buf.len(), for a body passed on as
+    // it arrives
+    pub fn read_some(&mut self, buf: &mut [u8]) -> Result<usize, ReadError> {
+        if self.buffer.is_empty() {
+            return self.read_stream(buf);
+        }
+        let bytes = buf.len().min(self.buffer.len());
+        buf[..bytes].copy_from_slice(&self.buffer[..bytes]);
+        self.buffer.drain(..bytes);
+        Ok(bytes)
+    }
+
+    pub fn read_line(&mut self, limits: &Limits) -> Result<String, ReadError> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|w| w == b"\r\n") {
+                let line = String::from_utf8_lossy(&self.buffer[..end]).to_string();
+                self.buffer.drain(..end + 2);
+                return Ok(line);
+            }
+
+            if self.buffer.len() > limits.max_header_bytes {
+                return Err(ReadError::HeadersTooLarge);
+            }
+
+            self.read_more()?;
+        }
+    }
+
+    fn fill(&mut self, len: usize) -> Result<(), ReadError> {
+        while self.buffer.len() < len {
+            self.read_more()?;
+        }
+        Ok(())
+    }
+
+    fn read_more(&mut self) -> Result<(), ReadError> {
+        let mut chunk = [0; READ_CHUNK];
+        let bytes = self.read_stream(&mut chunk)?;
+        self.buffer.extend_from_slice(&chunk[..bytes]);
+        Ok(())
+    }
+
+    fn read_stream(&mut self, buf: &mut [u8]) -> Result<usize, ReadError> {
+        let bytes = match self.stream.read(buf) {
+            Ok(bytes) => bytes,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(ReadError::TimedOut);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if bytes == 0 {
+            return Err(ReadError::Closed);
+        }
+        Ok(bytes)
+    }
+}
+
+// the size line of a chunk, its extensions are ignored
+pub fn parse_chunk_size(line: &str) -> Result<usize, ReadError> {
+    let size = line.split(';').next().unwrap_or("").trim();
+    usize::from_str_radix(size, 16).map_err(|_| ReadError::Malformed)
+}
+
+// an oversized head is blamed on the target when the request line alone is too long
+fn too_large(buffer: &[u8], limits: &Limits) -> ReadError {
+    let request_line = buffer
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .unwrap_or(buffer.len());
+    if request_line > limits.max_uri_len {
+        ReadError::UriTooLong
+    } else {
+        ReadError::HeadersTooLarge
+    }
+}
+
+// validates the request line and header section as read by read_head
+pub fn parse_head(head: &str, limits: &Limits) -> Result<Head, ReadError> {
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or("");
+
+    let parts: Vec<&str> = request_line.split(' ').collect();
+    if parts.len() != 3 {
+        return Err(ReadError::Malformed);
+    }
+    let (method, target, version) = (parts[0], parts[1], parts[2]);
+
+    if method.is_empty() || !method.bytes().all(is_tchar) {
+        return Err(ReadError::Malformed);
+    }
+    if target.len() > limits.max_uri_len {
+        return Err(ReadError::UriTooLong);
+    }
+    let target = origin_form(method, target)?;
+    if url::percent_decode(url::split_target(&target).0).is_none() {
+        return Err(ReadError::Malformed);
+    }
+    let version = parse_version(version)?;
+
+    let headers = parse_fields(lines, limits)?;
+    if version == Version::Http11 && headers.get_all("Host").count() != 1 {
+        return Err(ReadError::Malformed);
+    }
+
+    let content_lengths: Vec<&str> = headers.get_all("Content-Length").collect();
+    let codings: Vec<String> = headers
+        .list("Transfer-Encoding")
+        .map(|coding| coding.to_ascii_lowercase())
+        .collect();
+    // both framings at once is how requests get smuggled
+    if !codings.is_empty() && !content_lengths.is_empty() {
+        return Err(ReadError::Malformed);
+    }
+
+    let content_length = parse_content_length(&content_lengths)?;
+    let chunked = if codings.is_empty() {
+        if headers.get("Transfer-Encoding").is_some() {
+            return Err(ReadError::Malformed);
+        }
+        false
+    } else {
+        match codings.last().map(|coding| coding.as_str()) {
+            Some("chunked") if codings.len() == 1 => true,
+            // a body without chunked as its final coding can't be delimited
+            Some("chunked") => return Err(ReadError::UnsupportedTransferCoding),
+            _ => return Err(ReadError::Malformed),
+        }
+    };
+
+    Ok(Head {
+        method: method.to_string(),
+        target,
+        version,
+        headers,
+        content_length,
+        chunked,
+    })
+}
+
+// the header section below the first line of a head, requests' and
+// responses' alike
+pub fn parse_fields<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    limits: &Limits,
+) -> Result<Headers, ReadError> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in lines {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            // obs-fold, the continuation becomes part of the previous value
+            let (_, value) = fields.last_mut().ok_or(ReadError::Malformed)?;
+            let continuation = line.trim_matches([' ', '\t']);
+            if !valid_value(continuation) {
+                return Err(ReadError::Malformed);
+            }
+            value.push(' ');
+            value.push_str(continuation);
+            continue;
+        }
+
+        let (name, value) = line.split_once(':').ok_or(ReadError::Malformed)?;
+        // no whitespace is allowed between the field name and the colon
+        if name.is_empty() || !name.bytes().all(is_tchar) {
+            return Err(ReadError::Malformed);
+        }
+        let value = value.trim_matches([' ', '\t']);
+        if !valid_value(value) {
+            return Err(ReadError::Malformed);
+        }
+
+        fields.push((name.to_string(), value.to_string()));
+        if fields.len() > limits.max_headers {
+            return Err(ReadError::HeadersTooLarge);
+        }
+    }
+
+    Ok(Headers::from(fields))
+}
+
+pub fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+pub fn valid_value(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|b| b == b'\t' || (b >= 0x20 && b != 0x7f))
+}
+
+// origin-form is used as is, absolute-form is reduced to its path and query
+fn origin_form(method: &str, target: &str) -> Result<String, ReadError> {
+    if target.starts_with('/') {
+        return Ok(target.to_string());
+    }
+    if target == "*" && method == "OPTIONS" {
+        return Ok(target.to_string());
+    }
+
+    let lower = target.to_ascii_lowercase();
+    let rest = lower
+        .strip_prefix("http://")
+        .or_else(|| lower.strip_prefix("https://"))
+        .ok_or(ReadError::Malformed)?;
+    let start = target.len() - rest.len();
+    match rest.find(['/', '?']) {
+        Some(i) if rest.as_bytes()[i] == b'/' => Ok(target[start + i..].to_string()),
+        Some(i) => Ok(format!("/{}", &target[start + i..])),
+        None => Ok("/".to_string()),
+    }
+}
+
+fn parse_version(version: &str) -> Result<Version, ReadError> {
+    match version {
+        "HTTP/1.1" => Ok(Version::Http11),
+        "HTTP/1.0" => Ok(Version::Http10),
+        _ => {
+            let digits = version.strip_prefix("HTTP/").ok_or(ReadError::Malformed)?;
+            let valid = digits.len() == 3
+                && digits.as_bytes()[0].is_ascii_digit()
+                && digits.as_bytes()[1] == b'.'
+                && digits.as_bytes()[2].is_ascii_digit();
+            if valid {
+                Err(ReadError::UnsupportedVersion)
+            } else {
+                Err(ReadError::Malformed)
+            }
+        }
+    }
+}
+
+// repeated Content-Length fields, or a list in one, have to agree on a single value
+pub fn parse_content_length(values: &[&str]) -> Result<usize, ReadError> {
+    let mut content_length: Option<usize> = None;
+
+    for value in values.iter().flat_map(|value| value.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ReadError::Malformed);
+        }
+        let len: usize = value.parse().map_err(|_| ReadError::Malformed)?;
+        if content_length.is_some_and(|previous| previous != len) {
+            return Err(ReadError::Malformed);
+        }
+        content_length = Some(len);
+    }
+
+    Ok(content_length.unwrap_or(0))
+}
+
+fn find_head_end(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    impl ReadTimeout for Cursor<Vec<u8>> {
+        fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn connection(bytes: &[u8]) -> Connection<Cursor<Vec<u8>>> {
+        Connection::new(Cursor::new(bytes.to_vec()), Timeouts::default())
+    }
+
+    fn chunked(bytes: &[u8], limits: &Limits) -> Result<(Vec<u8>, Vec<String>), ReadError> {
+        connection(bytes).read_chunked_body(limits)
+    }
+
+    fn head(text: &str) -> Result<Head, ReadError> {
+        parse_head(text, &Limits::default())
+    }
+
+    #[test]
+    fn parses_a_request_head() {
+        let head = head("GET http://example.com/a%20b?q=1 HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5, 5")
+            .ok()
+            .unwrap();
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.target, "/a%20b?q=1");
+        assert!(head.version == Version::Http11);
+        assert_eq!(head.content_length, 5);
+        assert!(!head.chunked);
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        for text in [
+            "GET / HTTP/1.1",
+            "GET /  HTTP/1.1\r\nHost: a",
+            "G(T / HTTP/1.1\r\nHost: a",
+            "GET /%zz HTTP/1.1\r\nHost: a",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 1, 2",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: -1",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 99999999999999999999999",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\nTransfer-Encoding: chunked",
+        ] {
+            assert!(matches!(head(text), Err(ReadError::Malformed)), "{}", text);
+        }
+        assert!(matches!(
+            head("GET / HTTP/2.0\r\nHost: a"),
+            Err(ReadError::UnsupportedVersion)
+        ));
+        assert!(matches!(
+            head("GET / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked"),
+            Err(ReadError::UnsupportedTransferCoding)
+        ));
+        let long = format!("GET /{} HTTP/1.1\r\nHost: a", "a".repeat(8192));
+        assert!(matches!(head(&long), Err(ReadError::UriTooLong)));
+    }
+
+    #[test]
+    fn decodes_chunks_and_trailers() {
+        let bytes = b"4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\n";
+        let (body, trailers) = chunked(bytes, &Limits::default()).ok().unwrap();
+        assert_eq!(body, b"Wikipedia");
+        assert_eq!(trailers, vec!["Expires: never"]);
+    }
+
+    #[test]
+    fn rejects_chunk_sizes_past_the_limit() {
+        let bytes = b"1\r\na\r\nffffffffffffffff\r\n";
+        let result = chunked(bytes, &Limits::default());
+        assert!(matches!(result, Err(ReadError::BodyTooLarge)));
+
+        let limits = Limits {
+            max_body_bytes: 4,
+            ..Limits::default()
+        };
+        assert!(chunked(b"4\r\nabcd\r\n0\r\n\r\n", &limits).is_ok());
+        let result = chunked(b"4\r\nabcd\r\n1\r\n", &limits);
+        assert!(matches!(result, Err(ReadError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn rejects_malformed_chunks() {
+        let limits = Limits::default();
+        assert!(matches!(
+            chunked(b"zz\r\n", &limits),
+            Err(ReadError::Malformed)
+        ));
+        assert!(matches!(
+            chunked(b"2\r\nabc\r\n", &limits),
+            Err(ReadError::Malformed)
+        ));
+        assert!(matches!(
+            chunked(b"5\r\nab", &limits),
+            Err(ReadError::Closed)
+        ));
+    }
+
+    #[test]
+    fn bounds_the_trailer_section() {
+        let limits = Limits {
+            max_header_bytes: 64,
+            ..Limits::default()
+        };
+        let mut bytes = b"0\r\n".to_vec();
+        for _ in 0..10 {
+            bytes.extend_from_slice(b"X-Trailer: 0123456789\r\n");
+        }
+        bytes.extend_from_slice(b"\r\n");
+        let result = chunked(&bytes, &limits);
+        assert!(matches!(result, Err(ReadError::HeadersTooLarge)));
+    }
+}
@@ -1,79 +1,402 @@
+use crate::deflate;
+use crate::headers::Headers;
+use crate::request::Version;
 use crate::statuscode::StatusCode;
-use std::io::{Error, Write};
-use std::process::{Command, Stdio};
+use crate::websocket::Upgrade;
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+const CHUNK_SIZE: usize = 8 * 1024;
+const MIN_COMPRESS_LEN: usize = 64;
 
 pub struct Response {
     status: StatusCode,
     content_type: ContentType,
     accept_encoding: Option<AcceptEncoding>,
-    body: Vec<u8>,
+    body: Body,
+    omit_body: bool,
+    headers: Headers,
     connection_close: bool,
+    // a chunked body sent to an HTTP/1.0 client, delimited by closing instead
+    close_delimited: bool,
+    // the protocol the connection switches to after a 101
+    upgrade: Option<Upgrade>,
+    // a chunked body that runs until the client disconnects, which then
+    // isn't an error
+    open_ended: bool,
+    // a body from an upstream, which keeps the encoding it came with
+    relayed: bool,
+    // the body depends on Accept-Encoding, which Vary then names
+    negotiated: bool,
+}
+
+pub enum Body {
+    Full(Vec<u8>),
+    // copied to the socket in CHUNK_SIZE pieces, exactly len bytes
+    Stream(Box<dyn Read + Send>, u64),
+    Chunked(Box<dyn Read + Send>),
 }
 
 impl Response {
-    pub fn new(
+    pub fn new<B: Into<Vec<u8>>>(status: StatusCode, content_type: ContentType, body: B) -> Self {
+        Self {
+            status,
+            accept_encoding: None,
+            content_type,
+            body: Body::Full(body.into()),
+            omit_body: false,
+            headers: Headers::new(),
+            connection_close: false,
+            close_delimited: false,
+            upgrade: None,
+            open_ended: false,
+            relayed: false,
+            negotiated: false,
+        }
+    }
+
+    // compresses the body unless it's too small or wouldn't get any smaller;
+    // an eligible body varies by Accept-Encoding even when left as it is
+    pub fn compress(mut self, accept_encoding: Option<AcceptEncoding>) -> Self {
+        if !self.has_compressible_body() {
+            return self;
+        }
+        self.negotiated = true;
+        let encoding = match accept_encoding {
+            Some(encoding) => encoding,
+            None => return self,
+        };
+
+        match self.body {
+            Body::Full(ref body) if body.len() >= MIN_COMPRESS_LEN => {
+                let compressed = encoding.compress(body);
+                if compressed.len() < body.len() {
+                    self.body = Body::Full(compressed);
+                    self.accept_encoding = Some(encoding);
+                    self.weaken_etag();
+                }
+            }
+            // the compressed length isn't known up front, so it goes out chunked
+            Body::Stream(_, len) if len >= MIN_COMPRESS_LEN as u64 => {
+                if let Body::Stream(source, _) =
+                    std::mem::replace(&mut self.body, Body::Full(Vec::new()))
+                {
+                    let encoder = deflate::EncoderReader::new(encoding.container(), source);
+                    self.body = Body::Chunked(Box::new(encoder));
+                    self.accept_encoding = Some(encoding);
+                    self.weaken_etag();
+                }
+            }
+            _ => {}
+        }
+
+        self
+    }
+
+    // a body Accept-Encoding has a say in, the empty bodies of errors,
+    // redirects and the like go out as they are
+    pub fn has_compressible_body(&self) -> bool {
+        let empty = match self.body {
+            Body::Full(ref bytes) => bytes.is_empty(),
+            Body::Stream(_, len) => len == 0,
+            Body::Chunked(_) => false,
+        };
+        // byte ranges refer to the unencoded representation
+        !empty
+            && !self.relayed
+            && self.status.has_body()
+            && self.content_type.is_compressible()
+            && !matches!(self.status, StatusCode::PartialContent)
+    }
+
+    // keeps the headers of the full response, as needed for HEAD
+    pub fn without_body(mut self) -> Self {
+        self.omit_body = true;
+        self
+    }
+
+    pub fn with_allow(self, methods: &[String]) -> Self {
+        self.with_header("Allow", &methods.join(", "))
+    }
+
+    // adds a field, earlier ones of the same name are kept
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn open_ended(mut self) -> Self {
+        self.open_ended = true;
+        self
+    }
+
+    pub fn relayed(mut self) -> Self {
+        self.relayed = true;
+        self
+    }
+
+    pub fn with_upgrade(mut self, upgrade: Upgrade) -> Self {
+        self.upgrade = Some(upgrade);
+        self
+    }
+
+    pub fn take_upgrade(&mut self) -> Option<Upgrade> {
+        self.upgrade.take()
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    // the compressed bytes differ from the stored file, so a strong validator
+    // would no longer hold
+    fn weaken_etag(&mut self) {
+        if let Some(etag) = self.headers.get("ETag") {
+            if !etag.starts_with("W/") {
+                let weak = format!("W/{etag}");
+                self.headers.set("ETag", &weak);
+            }
+        }
+    }
+
+    pub fn close_connection(mut self, connection_close: bool) -> Self {
+        self.connection_close = connection_close;
+        self
+    }
+
+    pub fn closes_connection(&self) -> bool {
+        self.connection_close
+    }
+
+    pub fn is_open_ended(&self) -> bool {
+        self.open_ended
+    }
+
+    // HTTP/1.0 has no chunked transfer coding
+    pub fn for_version(mut self, version: Version) -> Self {
+        if version == Version::Http10 && matches!(self.body, Body::Chunked(_)) {
+            self.close_delimited = true;
+            self.connection_close = true;
+        }
+        self
+    }
+
+    pub fn stream(
+        status: StatusCode,
+        content_type: ContentType,
+        source: Box<dyn Read + Send>,
+        len: u64,
+    ) -> Self {
+        let mut response = Self::new(status, content_type, Vec::new());
+        response.body = Body::Stream(source, len);
+        response
+    }
+
+    // a body of unknown length, every read from source goes out as it comes
+    pub fn chunked(
         status: StatusCode,
-        accept_encoding: Option<AcceptEncoding>,
         content_type: ContentType,
-        body: &str,
-        connection_close: bool,
+        source: Box<dyn Read + Send>,
     ) -> Self {
-        let compressed_body = match accept_encoding {
-            Some(AcceptEncoding::Gzip) => match AcceptEncoding::compress_gzip(body) {
-                Ok(compressed) => compressed,
-                Err(e) => {
-                    println!("err while compressing: {}", e);
-                    body.as_bytes().to_vec()
+        let mut response = Self::new(status, content_type, Vec::new());
+        response.body = Body::Chunked(source);
+        response
+    }
+
+    // returns how many body bytes went out, framing not included
+    pub fn write_to<W: Write>(&mut self, stream: &mut W) -> Result<u64, Error> {
+        stream.write_all(self.format_head().as_bytes())?;
+
+        if self.omit_body || !self.status.has_body() {
+            stream.flush()?;
+            return Ok(0);
+        }
+
+        let written = match self.body {
+            Body::Full(ref bytes) => {
+                stream.write_all(bytes)?;
+                bytes.len() as u64
+            }
+            Body::Stream(ref mut source, len) => {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let mut remaining = len;
+                while remaining > 0 {
+                    let want = remaining.min(CHUNK_SIZE as u64) as usize;
+                    let bytes = source.read(&mut chunk[..want])?;
+                    if bytes == 0 {
+                        // Content-Length was already sent, the connection can't be reused
+                        return Err(Error::new(
+                            ErrorKind::UnexpectedEof,
+                            "body source ended early",
+                        ));
+                    }
+                    stream.write_all(&chunk[..bytes])?;
+                    remaining -= bytes as u64;
                 }
-            },
-            None => body.as_bytes().to_vec(),
+                len
+            }
+            Body::Chunked(ref mut source) => {
+                let mut written = 0;
+                match write_chunks(source, stream, self.close_delimited, &mut written) {
+                    Ok(()) => written,
+                    Err(e) if self.open_ended && is_disconnect(&e) => {
+                        self.connection_close = true;
+                        return Ok(written);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
         };
 
-        Self {
-            status,
-            accept_encoding,
-            content_type,
-            body: compressed_body,
-            connection_close,
-        }
+        stream.flush()?;
+        Ok(written)
+    }
+
+    pub fn status_code(&self) -> u16 {
+        self.status.code()
+    }
+
+    // status, header fields and body for an HTTP/2 stream; the fields that
+    // only concern an HTTP/1 connection are left out and None means no DATA
+    pub fn into_http2(self) -> (u16, Headers, Option<Box<dyn Read + Send>>) {
+        let fields: Vec<(String, String)> = self
+            .fields()
+            .into_iter()
+            .filter(|(name, _)| !is_connection_specific(name))
+            .collect();
+
+        let body: Option<Box<dyn Read + Send>> = if self.omit_body || !self.status.has_body() {
+            None
+        } else {
+            match self.body {
+                Body::Full(bytes) if bytes.is_empty() => None,
+                Body::Full(bytes) => Some(Box::new(io::Cursor::new(bytes))),
+                Body::Stream(source, len) => Some(Box::new(source.take(len))),
+                Body::Chunked(source) => Some(source),
+            }
+        };
+        (self.status.code(), Headers::from(fields), body)
     }
 
-    pub fn format_bytes(&self) -> Vec<u8> {
+    fn format_head(&self) -> String {
         let http_version = "HTTP/1.1";
-        let content_length = self.body.len();
-        let mut headers = format!(
+        let mut head = format!(
             "{} {} {}\r\n",
             http_version,
             self.status.code(),
             self.status.reason_phrase()
         );
+        for (name, value) in self.fields() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    fn fields(&self) -> Vec<(String, String)> {
+        let mut fields: Vec<(String, String)> = Vec::new();
+        let mut push = |name: &str, value: &str| fields.push((name.to_string(), value.to_string()));
 
         if let Some(ref encoding) = self.accept_encoding {
-            headers.push_str(&format!("Content-Encoding: {}\r\n", encoding.str()));
+            push("Content-Encoding", encoding.str());
+        }
+
+        // a Vary set on the response joins the one for Accept-Encoding
+        let mut vary = Vec::new();
+        if self.negotiated {
+            vary.push("Accept-Encoding");
+        }
+        for token in self.headers.list("Vary") {
+            if !vary.iter().any(|seen| seen.eq_ignore_ascii_case(token)) {
+                vary.push(token);
+            }
+        }
+        if vary.contains(&"*") {
+            push("Vary", "*");
+        } else if !vary.is_empty() {
+            push("Vary", &vary.join(", "));
         }
 
-        headers.push_str(&format!(
-            "Content-Type: {}\r\nContent-Length: {}\r\n",
-            self.content_type.str(),
-            content_length
-        ));
+        if self.status.has_body() {
+            push("Content-Type", self.content_type.str());
+
+            match self.body {
+                Body::Full(ref bytes) => push("Content-Length", &bytes.len().to_string()),
+                Body::Stream(_, len) => push("Content-Length", &len.to_string()),
+                Body::Chunked(_) if self.close_delimited => {}
+                Body::Chunked(_) => push("Transfer-Encoding", "chunked"),
+            }
+        }
+
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Vary") {
+                push(name, value);
+            }
+        }
 
         if self.connection_close {
-            headers.push_str("Connection: Close\r\n");
+            push("Connection", "Close");
         }
 
-        headers.push_str("\r\n");
+        fields
+    }
+}
+
+// fields HTTP/2 forbids, the framing layer does their job
+pub fn is_connection_specific(name: &str) -> bool {
+    [
+        "Connection",
+        "Keep-Alive",
+        "Proxy-Connection",
+        "Transfer-Encoding",
+        "Upgrade",
+    ]
+    .iter()
+    .any(|field| field.eq_ignore_ascii_case(name))
+}
 
-        let mut response_bytes = headers.into_bytes();
-        response_bytes.extend(&self.body);
-        response_bytes
+// every read from source becomes a chunk, flushed right away so streamed
+// events aren't held back; written counts the body bytes that went out
+fn write_chunks<W: Write>(
+    source: &mut Box<dyn Read + Send>,
+    stream: &mut W,
+    close_delimited: bool,
+    written: &mut u64,
+) -> Result<(), Error> {
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let bytes = source.read(&mut chunk)?;
+        if bytes == 0 {
+            break;
+        }
+        if close_delimited {
+            stream.write_all(&chunk[..bytes])?;
+        } else {
+            stream.write_all(format!("{:x}\r\n", bytes).as_bytes())?;
+            stream.write_all(&chunk[..bytes])?;
+            stream.write_all(b"\r\n")?;
+        }
+        stream.flush()?;
+        *written += bytes as u64;
     }
+
+    if !close_delimited {
+        stream.write_all(b"0\r\n\r\n")?;
+    }
+    Ok(())
+}
+
+pub fn is_disconnect(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+    )
 }
 
 pub enum ContentType {
     TextPlain,
     TextHtml,
     ApplicationOctetStream,
+    Mime(String),
 }
 
 impl ContentType {
@@ -82,41 +405,223 @@ impl ContentType {
             ContentType::TextPlain => "text/plain",
             ContentType::TextHtml => "text/html",
             ContentType::ApplicationOctetStream => "application/octet-stream",
+            ContentType::Mime(mime) => mime,
+        }
+    }
+
+    pub fn is_compressible(&self) -> bool {
+        match self {
+            ContentType::TextPlain | ContentType::TextHtml => true,
+            ContentType::ApplicationOctetStream => false,
+            ContentType::Mime(mime) => {
+                let essence = mime.split(';').next().unwrap_or("").trim();
+                essence.starts_with("text/")
+                    || essence.ends_with("+json")
+                    || essence.ends_with("+xml")
+                    || matches!(
+                        essence,
+                        "application/json" | "application/xml" | "application/wasm"
+                    )
+            }
         }
     }
 }
 
 pub enum AcceptEncoding {
     Gzip,
+    Deflate,
 }
 
+pub struct NotAcceptable;
+
 impl AcceptEncoding {
     pub fn str(&self) -> &str {
         match self {
             AcceptEncoding::Gzip => "gzip",
+            AcceptEncoding::Deflate => "deflate",
         }
     }
 
-    pub fn compress_gzip(body: &str) -> Result<Vec<u8>, Error> {
-        let mut child = Command::new("gzip")
-            .arg("-c")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
+    pub fn compress(&self, body: &[u8]) -> Vec<u8> {
+        match self {
+            AcceptEncoding::Gzip => deflate::gzip(body),
+            AcceptEncoding::Deflate => deflate::zlib(body),
+        }
+    }
 
-        if let Some(mut stdin) = child.stdin.take() {
-            stdin.write_all(body.as_bytes())?;
+    pub fn container(&self) -> deflate::Container {
+        match self {
+            AcceptEncoding::Gzip => deflate::Container::Gzip,
+            AcceptEncoding::Deflate => deflate::Container::Zlib,
         }
+    }
 
-        let output = child.wait_with_output()?;
+    // picks the coding with the highest q-value from the Accept-Encoding values,
+    // None stands for identity; ties go to gzip, then deflate, then identity
+    pub fn negotiate(accept_encoding: &[String]) -> Result<Option<AcceptEncoding>, NotAcceptable> {
+        // without the header any coding is acceptable, keep the body as is
+        if accept_encoding.is_empty() {
+            return Ok(None);
+        }
 
-        if output.status.success() {
-            Ok(output.stdout)
-        } else {
-            Err(Error::new(
-                std::io::ErrorKind::Other,
-                format!("err gzip processing: {:?}", output.status),
-            ))
+        let mut qvalues: Vec<(String, f32)> = Vec::new();
+        for entry in accept_encoding {
+            let mut params = entry.split(';');
+            let coding = params.next().unwrap_or("").trim().to_lowercase();
+            if coding.is_empty() {
+                continue;
+            }
+
+            let mut q = Some(1.0);
+            for param in params {
+                if let Some((name, value)) = param.split_once('=') {
+                    if name.trim().eq_ignore_ascii_case("q") {
+                        q = parse_qvalue(value.trim());
+                    }
+                }
+            }
+
+            if let Some(q) = q {
+                qvalues.push((coding, q));
+            }
+        }
+
+        let lookup = |names: &[&str]| {
+            qvalues
+                .iter()
+                .find(|(coding, _)| names.contains(&coding.as_str()))
+                .map(|&(_, q)| q)
+        };
+        let wildcard = lookup(&["*"]);
+
+        let candidates = [
+            (
+                Some(AcceptEncoding::Gzip),
+                lookup(&["gzip", "x-gzip"]).or(wildcard).unwrap_or(0.0),
+            ),
+            (
+                Some(AcceptEncoding::Deflate),
+                lookup(&["deflate"]).or(wildcard).unwrap_or(0.0),
+            ),
+            // identity stays acceptable unless it's excluded explicitly or through *
+            (None, lookup(&["identity"]).or(wildcard).unwrap_or(1.0)),
+        ];
+
+        let mut best: Option<(Option<AcceptEncoding>, f32)> = None;
+        for (encoding, q) in candidates {
+            if q > 0.0 && best.as_ref().is_none_or(|&(_, best_q)| q > best_q) {
+                best = Some((encoding, q));
+            }
+        }
+
+        best.map(|(encoding, _)| encoding).ok_or(NotAcceptable)
+    }
+}
+
+// qvalue = ( "0" [ "." 0*3DIGIT ] ) / ( "1" [ "." 0*3("0") ] )
+fn parse_qvalue(value: &str) -> Option<f32> {
+    let (int, frac) = value.split_once('.').unwrap_or((value, ""));
+    let valid = frac.len() <= 3
+        && frac.bytes().all(|b| b.is_ascii_digit())
+        && (int == "0" || (int == "1" && frac.bytes().all(|b| b == b'0')));
+
+    if valid {
+        value.parse::<f32>().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(values: &[&str]) -> Option<&'static str> {
+        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        match AcceptEncoding::negotiate(&values) {
+            Ok(Some(AcceptEncoding::Gzip)) => Some("gzip"),
+            Ok(Some(AcceptEncoding::Deflate)) => Some("deflate"),
+            Ok(None) => Some("identity"),
+            Err(NotAcceptable) => None,
         }
     }
+
+    fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn negotiates_by_qvalue() {
+        assert!(negotiate(&[]) == Some("identity"));
+        assert!(negotiate(&["gzip", "deflate"]) == Some("gzip"));
+        assert!(negotiate(&["deflate", "gzip;q=0.5"]) == Some("deflate"));
+        assert!(negotiate(&["x-gzip"]) == Some("gzip"));
+        assert!(negotiate(&["GZIP;Q=1"]) == Some("gzip"));
+        assert!(negotiate(&["gzip;q=0.8"]) == Some("identity"));
+        assert!(negotiate(&["br"]) == Some("identity"));
+        assert!(negotiate(&["*"]) == Some("gzip"));
+        assert!(negotiate(&["*;q=0", "deflate"]) == Some("deflate"));
+        assert!(negotiate(&["identity;q=0"]).is_none());
+        assert!(negotiate(&["*;q=0"]).is_none());
+        assert!(negotiate(&["gzip;q=0", "identity;q=0"]).is_none());
+    }
+
+    #[test]
+    fn ignores_malformed_qvalues() {
+        assert!(parse_qvalue("0.5") == Some(0.5));
+        assert!(parse_qvalue("1.000") == Some(1.0));
+        assert!(parse_qvalue("1.5").is_none());
+        assert!(parse_qvalue("0.1234").is_none());
+        assert!(parse_qvalue("-0").is_none());
+        assert!(parse_qvalue("").is_none());
+        // an entry with a bad q-value counts as absent
+        assert!(negotiate(&["gzip;q=2", "deflate"]) == Some("deflate"));
+    }
+
+    #[test]
+    fn varies_only_for_negotiated_bodies() {
+        let text = "hello ".repeat(100);
+
+        let fields = Response::new(StatusCode::Ok, ContentType::TextPlain, text.clone())
+            .compress(Some(AcceptEncoding::Gzip))
+            .fields();
+        assert!(field(&fields, "Content-Encoding") == Some("gzip"));
+        assert!(field(&fields, "Vary") == Some("Accept-Encoding"));
+
+        // eligible but left as is, the representation still depends on the header
+        let fields = Response::new(StatusCode::Ok, ContentType::TextPlain, text.clone())
+            .compress(None)
+            .fields();
+        assert!(field(&fields, "Content-Encoding").is_none());
+        assert!(field(&fields, "Vary") == Some("Accept-Encoding"));
+
+        let fields = Response::new(StatusCode::Ok, ContentType::ApplicationOctetStream, text)
+            .compress(Some(AcceptEncoding::Gzip))
+            .fields();
+        assert!(field(&fields, "Content-Encoding").is_none());
+        assert!(field(&fields, "Vary").is_none());
+
+        let fields = Response::new(StatusCode::NotFound, ContentType::TextPlain, "")
+            .compress(Some(AcceptEncoding::Gzip))
+            .fields();
+        assert!(field(&fields, "Vary").is_none());
+    }
+
+    #[test]
+    fn merges_vary_fields() {
+        let fields = Response::new(StatusCode::Ok, ContentType::TextHtml, "<p>hi</p>")
+            .with_header("Vary", "Origin, accept-encoding")
+            .compress(None)
+            .fields();
+        assert!(field(&fields, "Vary") == Some("Accept-Encoding, Origin"));
+
+        let fields = Response::new(StatusCode::Ok, ContentType::TextHtml, "<p>hi</p>")
+            .with_header("Vary", "*")
+            .compress(None)
+            .fields();
+        assert!(field(&fields, "Vary") == Some("*"));
+    }
 }
@@ -0,0 +1,376 @@
+use crate::request::Request;
+use crate::response::Response;
+use crate::url;
+use crate::websocket::{self, WebSocket};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub struct Context<'a> {
+    pub request: &'a Request,
+    pub params: HashMap<String, String>,
+    pub directory: &'a str,
+    // the peer's IP address, and whether it came in over TLS
+    pub client: &'a str,
+    pub secure: bool,
+}
+
+impl Context<'_> {
+    // decoded value captured by a :name segment, or by * under the name "*"
+    pub fn param(&self, name: &str) -> &str {
+        self.params.get(name).map_or("", |value| value.as_str())
+    }
+
+    pub fn method(&self) -> &str {
+        &self.request.method
+    }
+
+    pub fn path(&self) -> &str {
+        &self.request.path
+    }
+
+    // the first value of a query parameter, decoded
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.request.query.get(name).map(|value| value.as_str())
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.request.body
+    }
+
+    // repeated fields are combined into one comma separated value
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.request.headers.combined(name)
+    }
+}
+
+type Handler = Box<dyn Fn(&Context) -> Response + Send + Sync>;
+
+// the method of a route that takes every method
+const ANY: &str = "*";
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard,
+}
+
+struct Route {
+    method: String,
+    segments: Vec<Segment>,
+    handler: Handler,
+}
+
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    pub fn get<F>(&mut self, pattern: &str, handler: F)
+    where
+        F: Fn(&Context) -> Response + Send + Sync + 'static,
+    {
+        self.route("GET", pattern, handler);
+    }
+
+    pub fn post<F>(&mut self, pattern: &str, handler: F)
+    where
+        F: Fn(&Context) -> Response + Send + Sync + 'static,
+    {
+        self.route("POST", pattern, handler);
+    }
+
+    // a route for every method, HEAD and OPTIONS included
+    pub fn any<F>(&mut self, pattern: &str, handler: F)
+    where
+        F: Fn(&Context) -> Response + Send + Sync + 'static,
+    {
+        self.route(ANY, pattern, handler);
+    }
+
+    // a GET route that upgrades to a WebSocket, the handler runs on a thread
+    // of its own and receives until it returns; a Sender taken from the
+    // socket sends from other threads meanwhile
+    pub fn websocket<F>(&mut self, pattern: &str, handler: F)
+    where
+        F: Fn(&Context, &mut WebSocket) + Send + Sync + 'static,
+    {
+        let upgrade: websocket::Upgrade = Arc::new(handler);
+        self.get(pattern, move |ctx| {
+            websocket::handshake(ctx, Arc::clone(&upgrade))
+        });
+    }
+
+    // pattern segments are literals, :name captures one segment and a trailing *
+    // captures the rest of the path, e.g. "/files/:name" or "/static/*"
+    pub fn route<F>(&mut self, method: &str, pattern: &str, handler: F)
+    where
+        F: Fn(&Context) -> Response + Send + Sync + 'static,
+    {
+        let segments = split_path(pattern)
+            .map(|segment| {
+                if segment == "*" {
+                    Segment::Wildcard
+                } else if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else {
+                    Segment::Literal(segment.to_string())
+                }
+            })
+            .collect();
+
+        self.routes.push(Route {
+            method: method.to_string(),
+            segments,
+            handler: Box::new(handler),
+        });
+    }
+
+    // routes are tried in registration order, HEAD falls back to the GET handler
+    pub fn dispatch(&self, ctx: &mut Context) -> Dispatch {
+        let mut allowed: Vec<String> = Vec::new();
+        let mut head_fallback = None;
+
+        for route in &self.routes {
+            let params = match match_segments(&route.segments, ctx.path()) {
+                Some(params) => params,
+                None => continue,
+            };
+
+            if route.method == ctx.method() || route.method == ANY {
+                ctx.params = params;
+                return Dispatch::Handled((route.handler)(ctx));
+            }
+
+            if ctx.method() == "HEAD" && route.method == "GET" && head_fallback.is_none() {
+                head_fallback = Some((route, params));
+            }
+            allowed.push(route.method.clone());
+        }
+
+        if let Some((route, params)) = head_fallback {
+            ctx.params = params;
+            return Dispatch::Handled((route.handler)(ctx).without_body());
+        }
+
+        if allowed.is_empty() {
+            return Dispatch::NotFound;
+        }
+
+        let allowed = with_implicit_methods(allowed);
+        if ctx.method() == "OPTIONS" {
+            Dispatch::Options(allowed)
+        } else {
+            Dispatch::MethodNotAllowed(allowed)
+        }
+    }
+
+    // every method some route accepts, the answer to OPTIONS *
+    pub fn methods(&self) -> Vec<String> {
+        with_implicit_methods(
+            self.routes
+                .iter()
+                .filter(|route| route.method != ANY)
+                .map(|route| route.method.clone())
+                .collect(),
+        )
+    }
+}
+
+pub enum Dispatch {
+    Handled(Response),
+    Options(Vec<String>),
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+fn with_implicit_methods(mut methods: Vec<String>) -> Vec<String> {
+    if methods.iter().any(|method| method == "GET") {
+        methods.push("HEAD".to_string());
+    }
+    methods.push("OPTIONS".to_string());
+
+    let mut unique: Vec<String> = Vec::new();
+    for method in methods {
+        if !unique.contains(&method) {
+            unique.push(method);
+        }
+    }
+    unique
+}
+
+fn split_path(path: &str) -> std::str::Split<'_, char> {
+    path.strip_prefix('/').unwrap_or(path).split('/')
+}
+
+fn match_segments(segments: &[Segment], path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    // an escaped "/" stays inside its segment when matching
+    let parts: Vec<String> = split_path(path)
+        .map(url::percent_decode)
+        .collect::<Option<_>>()?;
+
+    for (i, segment) in segments.iter().enumerate() {
+        match segment {
+            Segment::Wildcard => {
+                params.insert("*".to_string(), parts.get(i..)?.join("/"));
+                return Some(params);
+            }
+            Segment::Param(name) => {
+                params.insert(name.clone(), parts.get(i)?.clone());
+            }
+            Segment::Literal(literal) => {
+                if parts.get(i)? != literal {
+                    return None;
+                }
+            }
+        }
+    }
+
+    if parts.len() == segments.len() {
+        Some(params)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+    use crate::request::Version;
+    use crate::response::ContentType;
+    use crate::statuscode::StatusCode;
+    use std::io::Read;
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router.get("/", |_| text("index"));
+        router.get("/files/:name", |ctx| {
+            text(&format!("file {}", ctx.param("name")))
+        });
+        router.post("/files/:name", |ctx| {
+            text(&format!("upload {}", ctx.param("name")))
+        });
+        router.get("/static/*", |ctx| {
+            text(&format!("static {}", ctx.param("*")))
+        });
+        router.any("/echo", |ctx| text(&format!("echo {}", ctx.method())));
+        router
+    }
+
+    fn text(body: &str) -> Response {
+        Response::new(StatusCode::Ok, ContentType::TextPlain, body)
+    }
+
+    fn dispatch(router: &Router, method: &str, path: &str) -> Dispatch {
+        let request = Request {
+            method: method.to_string(),
+            target: path.to_string(),
+            path: path.to_string(),
+            query: HashMap::new(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+        };
+        let mut ctx = Context {
+            request: &request,
+            params: HashMap::new(),
+            directory: "",
+            client: "127.0.0.1",
+            secure: false,
+        };
+        router.dispatch(&mut ctx)
+    }
+
+    // the body a route answered with, None if no route handled the request
+    fn handled(router: &Router, method: &str, path: &str) -> Option<String> {
+        match dispatch(router, method, path) {
+            Dispatch::Handled(response) => {
+                let mut body = String::new();
+                if let (_, _, Some(mut source)) = response.into_http2() {
+                    source.read_to_string(&mut body).unwrap();
+                }
+                Some(body)
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn matches_literals_params_and_wildcards() {
+        let router = router();
+        assert_eq!(handled(&router, "GET", "/").as_deref(), Some("index"));
+        assert_eq!(
+            handled(&router, "GET", "/files/a.txt").as_deref(),
+            Some("file a.txt")
+        );
+        assert_eq!(
+            handled(&router, "POST", "/files/b").as_deref(),
+            Some("upload b")
+        );
+        assert_eq!(
+            handled(&router, "GET", "/static/css/site.css").as_deref(),
+            Some("static css/site.css")
+        );
+        assert_eq!(
+            handled(&router, "GET", "/static/").as_deref(),
+            Some("static ")
+        );
+        assert_eq!(
+            handled(&router, "DELETE", "/echo").as_deref(),
+            Some("echo DELETE")
+        );
+        assert!(handled(&router, "GET", "/files").is_none());
+        assert!(handled(&router, "GET", "/files/a/b").is_none());
+    }
+
+    #[test]
+    fn decodes_segments_after_splitting() {
+        let router = router();
+        assert_eq!(
+            handled(&router, "GET", "/files/a%2Fb").as_deref(),
+            Some("file a/b")
+        );
+        assert_eq!(
+            handled(&router, "GET", "/fil%65s/x").as_deref(),
+            Some("file x")
+        );
+        assert!(handled(&router, "GET", "/files/%zz").is_none());
+    }
+
+    #[test]
+    fn head_falls_back_to_get() {
+        let router = router();
+        match dispatch(&router, "HEAD", "/files/a") {
+            Dispatch::Handled(response) => {
+                assert_eq!(response.status_code(), 200);
+                assert!(response.into_http2().2.is_none());
+            }
+            _ => panic!("HEAD was not handled"),
+        }
+    }
+
+    #[test]
+    fn lists_allowed_methods() {
+        let router = router();
+        match dispatch(&router, "DELETE", "/files/a") {
+            Dispatch::MethodNotAllowed(allowed) => {
+                assert_eq!(allowed, ["GET", "POST", "HEAD", "OPTIONS"]);
+            }
+            _ => panic!("expected 405"),
+        }
+        match dispatch(&router, "OPTIONS", "/") {
+            Dispatch::Options(allowed) => assert_eq!(allowed, ["GET", "HEAD", "OPTIONS"]),
+            _ => panic!("expected OPTIONS"),
+        }
+        assert!(matches!(
+            dispatch(&router, "GET", "/missing"),
+            Dispatch::NotFound
+        ));
+        assert_eq!(router.methods(), ["GET", "POST", "HEAD", "OPTIONS"]);
+    }
+}
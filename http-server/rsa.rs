@@ -0,0 +1,384 @@
+// RSASSA-PSS signing with SHA-256 (RFC 8017), on a minimal unsigned big
+// integer of little endian 32-bit limbs and Montgomery multiplication
+use crate::sha2::{sha256, Sha256};
+use std::sync::Mutex;
+
+const HASH_LEN: usize = 32;
+
+pub struct RsaKey {
+    modulus: Vec<u32>,
+    public_exponent: Vec<u32>,
+    // padded to the modulus length so every signature walks the same windows
+    exponent: Vec<u32>,
+    // -modulus^-1 mod 2^32 and R^2 mod modulus, R being 2^(32 * limbs)
+    inverse: u32,
+    r_squared: Vec<u32>,
+    bits: usize,
+    blinding: Mutex<Option<Blinding>>,
+}
+
+// r^e and r^-1 for a random r: the base is multiplied by the first before
+// exponentiation and the result by the second after, and both are squared
+// for the next signature so no two bases are exponentiated alike
+struct Blinding {
+    blind: Vec<u32>,
+    unblind: Vec<u32>,
+}
+
+impl RsaKey {
+    // big endian modulus and exponents, as stored in the key file
+    pub fn new(modulus: &[u8], public_exponent: &[u8], private_exponent: &[u8]) -> Option<Self> {
+        let modulus = from_be_bytes(modulus);
+        if modulus.is_empty() || modulus[0] & 1 == 0 {
+            return None;
+        }
+        let bits = 32 * modulus.len() - modulus[modulus.len() - 1].leading_zeros() as usize;
+        if bits < 1024 {
+            return None;
+        }
+        let public_exponent = from_be_bytes(public_exponent);
+        let mut exponent = from_be_bytes(private_exponent);
+        if public_exponent.is_empty() || exponent.is_empty() || exponent.len() > modulus.len() {
+            return None;
+        }
+        exponent.resize(modulus.len(), 0);
+
+        // Newton iteration for the inverse of an odd number modulo 2^32
+        let mut inverse: u32 = 1;
+        for _ in 0..5 {
+            inverse = inverse.wrapping_mul(2u32.wrapping_sub(modulus[0].wrapping_mul(inverse)));
+        }
+
+        // 1 doubled 2 * 32 * limbs times, reduced as it goes
+        let mut r_squared = vec![0u32; modulus.len()];
+        r_squared[0] = 1;
+        for _ in 0..64 * modulus.len() {
+            let overflow = shift_left(&mut r_squared);
+            if overflow || !less_than(&r_squared, &modulus) {
+                subtract(&mut r_squared, &modulus);
+            }
+        }
+
+        Some(Self {
+            public_exponent,
+            exponent,
+            inverse: inverse.wrapping_neg(),
+            r_squared,
+            modulus,
+            bits,
+            blinding: Mutex::new(None),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits.div_ceil(8)
+    }
+
+    // seed is random and only read until the blinding pair exists
+    pub fn sign_pss(&self, message: &[u8], salt: &[u8; HASH_LEN], seed: &[u8]) -> Vec<u8> {
+        let encoded = from_be_bytes(&pss_encode(message, salt, self.bits - 1));
+
+        let mut blinding = match self.blinding.lock() {
+            Ok(blinding) => blinding,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let pair = blinding.get_or_insert_with(|| self.blinding_pair(seed));
+        let blinded = self.multiply(&encoded, &pair.blind);
+        let unblind = pair.unblind.clone();
+        pair.blind = self.multiply(&pair.blind, &pair.blind);
+        pair.unblind = self.multiply(&pair.unblind, &pair.unblind);
+        drop(blinding);
+
+        let signature = self.multiply(&self.power(&blinded, &self.exponent), &unblind);
+        to_be_bytes(&signature, self.len())
+    }
+
+    // r^(e * d - 2) is r^-1 as r^(e * d) is r, which spares a modular inverse
+    fn blinding_pair(&self, seed: &[u8]) -> Blinding {
+        let mut r = from_be_bytes(&seed[..seed.len().min(self.len() - 1)]);
+        r.resize(self.modulus.len(), 0);
+        r[0] |= 1;
+
+        let mut exponent = product(&self.public_exponent, &self.exponent);
+        let mut two = vec![0u32; exponent.len()];
+        two[0] = 2;
+        subtract(&mut exponent, &two);
+
+        Blinding {
+            blind: self.power(&r, &self.public_exponent),
+            unblind: self.power(&r, &exponent),
+        }
+    }
+
+    // a * b mod modulus
+    fn multiply(&self, a: &[u32], b: &[u32]) -> Vec<u32> {
+        let mut a = a.to_vec();
+        a.resize(self.modulus.len(), 0);
+        self.montgomery(&self.montgomery(&a, b), &self.r_squared)
+    }
+
+    // base^exponent mod modulus over fixed 4-bit windows: every window
+    // squares four times and multiplies once by an entry read in full from
+    // the table, whatever the exponent bits
+    fn power(&self, base: &[u32], exponent: &[u32]) -> Vec<u32> {
+        let mut base = base.to_vec();
+        base.resize(self.modulus.len(), 0);
+
+        let mut one = vec![0u32; self.modulus.len()];
+        one[0] = 1;
+        let mut table = vec![self.montgomery(&one, &self.r_squared)];
+        table.push(self.montgomery(&base, &self.r_squared));
+        for i in 2..16 {
+            table.push(self.montgomery(&table[i - 1], &table[1]));
+        }
+
+        let mut result = table[0].clone();
+        for limb in exponent.iter().rev() {
+            for shift in (0..32).step_by(4).rev() {
+                for _ in 0..4 {
+                    result = self.montgomery(&result, &result);
+                }
+                result = self.montgomery(&result, &select(&table, (limb >> shift) & 0xf));
+            }
+        }
+
+        self.montgomery(&result, &one)
+    }
+
+    // a * b / R mod modulus, a and b below the modulus
+    fn montgomery(&self, a: &[u32], b: &[u32]) -> Vec<u32> {
+        let n = self.modulus.len();
+        let mut t = vec![0u32; n + 2];
+
+        for &a_limb in a {
+            let mut carry = 0u64;
+            for j in 0..n {
+                let sum = u64::from(t[j]) + u64::from(a_limb) * u64::from(b[j]) + carry;
+                t[j] = sum as u32;
+                carry = sum >> 32;
+            }
+            let sum = u64::from(t[n]) + carry;
+            t[n] = sum as u32;
+            t[n + 1] = (sum >> 32) as u32;
+
+            let m = t[0].wrapping_mul(self.inverse);
+            let mut carry = (u64::from(t[0]) + u64::from(m) * u64::from(self.modulus[0])) >> 32;
+            for j in 1..n {
+                let sum = u64::from(t[j]) + u64::from(m) * u64::from(self.modulus[j]) + carry;
+                t[j - 1] = sum as u32;
+                carry = sum >> 32;
+            }
+            let sum = u64::from(t[n]) + carry;
+            t[n - 1] = sum as u32;
+            t[n] = t[n + 1] + (sum >> 32) as u32;
+        }
+
+        // t is below 2 * modulus: subtract always and keep t only when that
+        // borrowed past the top limb, choosing by mask rather than branch
+        let mut reduced = t[..n].to_vec();
+        let borrow = subtract(&mut reduced, &self.modulus);
+        let keep = (borrow & !t[n]).wrapping_neg();
+        for (limb, &original) in reduced.iter_mut().zip(&t[..n]) {
+            *limb = (original & keep) | (*limb & !keep);
+        }
+        reduced
+    }
+}
+
+// the entry at index, touching every entry so the access pattern is the same
+fn select(table: &[Vec<u32>], index: u32) -> Vec<u32> {
+    let mut selected = vec![0u32; table[0].len()];
+    for (i, entry) in table.iter().enumerate() {
+        let difference = i as u32 ^ index;
+        let mask = ((difference | difference.wrapping_neg()) >> 31).wrapping_sub(1);
+        for (limb, &value) in selected.iter_mut().zip(entry) {
+            *limb |= value & mask;
+        }
+    }
+    selected
+}
+
+fn product(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let sum = u64::from(result[i + j]) + u64::from(x) * u64::from(y) + carry;
+            result[i + j] = sum as u32;
+            carry = sum >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    result
+}
+
+fn from_be_bytes(bytes: &[u8]) -> Vec<u32> {
+    let mut limbs: Vec<u32> = bytes
+        .rchunks(4)
+        .map(|chunk| chunk.iter().fold(0, |limb, &b| (limb << 8) | u32::from(b)))
+        .collect();
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+    limbs
+}
+
+fn to_be_bytes(limbs: &[u32], len: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = limbs
+        .iter()
+        .rev()
+        .flat_map(|limb| limb.to_be_bytes())
+        .collect();
+    if bytes.len() > len {
+        bytes.drain(..bytes.len() - len);
+    } else {
+        let mut padded = vec![0u8; len - bytes.len()];
+        padded.extend_from_slice(&bytes);
+        bytes = padded;
+    }
+    bytes
+}
+
+fn shift_left(a: &mut [u32]) -> bool {
+    let mut carry = 0;
+    for limb in a.iter_mut() {
+        let next = *limb >> 31;
+        *limb = (*limb << 1) | carry;
+        carry = next;
+    }
+    carry == 1
+}
+
+fn less_than(a: &[u32], b: &[u32]) -> bool {
+    for (x, y) in a.iter().rev().zip(b.iter().rev()) {
+        if x != y {
+            return x < y;
+        }
+    }
+    false
+}
+
+// a - b in place, wrapping around when a < b; returns the borrow, 0 or 1
+fn subtract(a: &mut [u32], b: &[u32]) -> u32 {
+    let mut borrow = 0u64;
+    for (limb, &other) in a.iter_mut().zip(b) {
+        let diff = u64::from(*limb)
+            .wrapping_sub(u64::from(other))
+            .wrapping_sub(borrow);
+        *limb = diff as u32;
+        borrow = diff >> 63;
+    }
+    borrow as u32
+}
+
+// EMSA-PSS-ENCODE with MGF1 and a salt as long as the hash
+fn pss_encode(message: &[u8], salt: &[u8; HASH_LEN], em_bits: usize) -> Vec<u8> {
+    let em_len = em_bits.div_ceil(8);
+
+    let mut prefixed = Sha256::new();
+    prefixed.update(&[0; 8]);
+    prefixed.update(&sha256(message));
+    prefixed.update(salt);
+    let h = prefixed.finish();
+
+    let mut db = vec![0u8; em_len - HASH_LEN - 1];
+    let one = db.len() - HASH_LEN - 1;
+    db[one] = 1;
+    db[one + 1..].copy_from_slice(salt);
+
+    for (byte, mask) in db.iter_mut().zip(mgf1(&h, em_len - HASH_LEN - 1)) {
+        *byte ^= mask;
+    }
+    db[0] &= 0xff >> (8 * em_len - em_bits);
+
+    let mut encoded = db;
+    encoded.extend_from_slice(&h);
+    encoded.push(0xbc);
+    encoded
+}
+
+fn mgf1(seed: &[u8], len: usize) -> Vec<u8> {
+    let mut mask: Vec<u8> = Vec::with_capacity(len + HASH_LEN);
+    let mut counter = 0u32;
+    while mask.len() < len {
+        let mut hasher = Sha256::new();
+        hasher.update(seed);
+        hasher.update(&counter.to_be_bytes());
+        mask.extend_from_slice(&hasher.finish());
+        counter += 1;
+    }
+    mask.truncate(len);
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULUS: &str = "b40013ec70b5c51a39676c566a998be9e0591fbc384ed7e257f0e81533027128\
+        bd2f6688521f420184591980c3089da28e97f6fcf29945a76032c3f782a54b60\
+        bd1c2fbbabf361e66354acfb1963176e9e952f5908a3f962aa4cc500efa7c3bc\
+        59dddfc5f1f8e8eed6c0fabc4bbe481e52b394bc9c682f4c1a9f9ed79890e1eb";
+    const PRIVATE_EXPONENT: &str =
+        "1977fd59b224f60b9cddb6658fd5c407469835b6531d4ccc98f6dc4fcb94e7e0\
+        04ccc52c3aba7383756f89fd3f1b63fa91448d4d03228ec97d0211da5ea0f6c6\
+        2f97d8ddff81c0fb80afd135e80ee12f95c736376ff6d93b01fc9a1faa4e35bd\
+        9d36308cdfb03b014ef2a49c1e0db7eed6d0007cebfa891dd71608b264ca1451";
+
+    fn hex(digits: &str) -> Vec<u8> {
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn key() -> RsaKey {
+        RsaKey::new(&hex(MODULUS), &[1, 0, 1], &hex(PRIVATE_EXPONENT)).unwrap()
+    }
+
+    #[test]
+    fn power_matches_square_and_multiply() {
+        let key = key();
+        let mut base = from_be_bytes(b"some base well below the modulus");
+        base.resize(key.modulus.len(), 0);
+        let exponent = [0x8000_0001u32, 0xf0f0_0f0f, 0, 7];
+
+        let mut expected = vec![0u32; key.modulus.len()];
+        expected[0] = 1;
+        for limb in exponent.iter().rev() {
+            for bit in (0..32).rev() {
+                expected = key.multiply(&expected, &expected);
+                if (limb >> bit) & 1 == 1 {
+                    expected = key.multiply(&expected, &base);
+                }
+            }
+        }
+        assert!(key.power(&base, &exponent) == expected);
+    }
+
+    #[test]
+    fn signatures_verify_under_the_public_exponent() {
+        let key = key();
+        let salt = [7u8; HASH_LEN];
+        let seed = [0x5au8; 128];
+        let encoded = from_be_bytes(&pss_encode(b"handshake", &salt, key.bits - 1));
+
+        // each signature goes through a fresh square of the blinding pair
+        let first = key.sign_pss(b"handshake", &salt, &seed);
+        for signature in [first.clone(), key.sign_pss(b"handshake", &salt, &seed)] {
+            assert_eq!(signature.len(), 128);
+            let mut recovered = key.power(&from_be_bytes(&signature), &key.public_exponent);
+            while recovered.last() == Some(&0) {
+                recovered.pop();
+            }
+            assert!(recovered == encoded);
+        }
+        assert!(key.sign_pss(b"handshake", &salt, &seed) == first);
+    }
+
+    #[test]
+    fn rejects_short_and_even_moduli() {
+        assert!(RsaKey::new(&[0xff; 64], &[1, 0, 1], &[3]).is_none());
+        assert!(RsaKey::new(&[0xfe; 128], &[1, 0, 1], &[3]).is_none());
+    }
+}
@@ -0,0 +1,163 @@
+// Server-Sent Events (text/event-stream): a chunked response that stays open
+// and writes every event sent through its channel, until all senders are gone
+use crate::response::{ContentType, Response};
+use crate::router::Context;
+use crate::statuscode::StatusCode;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+// a comment line is written when nothing else was, so a client that went
+// away shows up as a failed write instead of holding a worker forever
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    retry: Option<Duration>,
+    data: String,
+}
+
+impl Event {
+    pub fn new(data: &str) -> Self {
+        Self {
+            id: None,
+            event: None,
+            retry: None,
+            data: data.to_string(),
+        }
+    }
+
+    // sent back by the client as Last-Event-ID when it reconnects
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.id = Some(single_line(id));
+        self
+    }
+
+    // the type a client listens for, "message" when not given
+    pub fn with_event(mut self, event: &str) -> Self {
+        self.event = Some(single_line(event));
+        self
+    }
+
+    // how long the client waits before reconnecting
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    fn encode(&self) -> String {
+        let mut encoded = String::new();
+        if let Some(ref event) = self.event {
+            encoded.push_str(&format!("event: {event}\n"));
+        }
+        if let Some(ref id) = self.id {
+            encoded.push_str(&format!("id: {id}\n"));
+        }
+        if let Some(retry) = self.retry {
+            encoded.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+
+        // every line of the data gets its own field, the client joins them
+        for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+            encoded.push_str(&format!("data: {line}\n"));
+        }
+        encoded.push('\n');
+        encoded
+    }
+}
+
+// line breaks would end the field early, NUL makes clients ignore an id
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n', '\0'], "")
+}
+
+// the response for an event stream and the sender that feeds it, which can
+// be cloned and moved to other threads
+pub fn stream() -> (Sender<Event>, Response) {
+    let (sender, events) = mpsc::channel();
+    let source = EventSource {
+        events,
+        pending: Vec::new(),
+    };
+
+    let response = Response::chunked(
+        StatusCode::Ok,
+        ContentType::Mime("text/event-stream".to_string()),
+        Box::new(source),
+    )
+    .with_header("Cache-Control", "no-cache")
+    .open_ended();
+    (sender, response)
+}
+
+// the id of the last event a reconnecting client received
+pub fn last_event_id<'a>(ctx: &'a Context) -> Option<&'a str> {
+    ctx.request.headers.get("Last-Event-ID")
+}
+
+struct EventSource {
+    events: Receiver<Event>,
+    // the part of an encoded event that didn't fit the last read
+    pending: Vec<u8>,
+}
+
+impl Read for EventSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            self.pending = match self.events.recv_timeout(KEEP_ALIVE) {
+                Ok(event) => event.encode().into_bytes(),
+                Err(RecvTimeoutError::Timeout) => b": keep-alive\n\n".to_vec(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+        }
+
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_fields_and_multiline_data() {
+        let event = Event::new("one\r\ntwo\rthree\n")
+            .with_id("7\n")
+            .with_event("tick")
+            .with_retry(Duration::from_secs(3));
+        assert_eq!(
+            event.encode(),
+            "event: tick\nid: 7\nretry: 3000\ndata: one\ndata: two\ndata: three\ndata: \n\n"
+        );
+        assert_eq!(Event::new("").encode(), "data: \n\n");
+    }
+
+    #[test]
+    fn streams_events_until_the_senders_are_gone() {
+        let (sender, response) = stream();
+        sender.send(Event::new("first")).unwrap();
+        sender.send(Event::new("second").with_id("2")).unwrap();
+        drop(sender);
+
+        let (status, headers, body) = response.into_http2();
+        assert_eq!(status, 200);
+        assert_eq!(headers.get("Content-Type"), Some("text/event-stream"));
+        assert_eq!(headers.get("Cache-Control"), Some("no-cache"));
+
+        // reads smaller than an event pick up where the last one stopped
+        let mut body = body.unwrap();
+        let mut text = Vec::new();
+        let mut buf = [0; 5];
+        loop {
+            match body.read(&mut buf).unwrap() {
+                0 => break,
+                n => text.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(text, b"data: first\n\nid: 2\ndata: second\n\n");
+    }
+}
@@ -1,26 +1,132 @@
 pub enum StatusCode {
+    SwitchingProtocols,
     Ok,
     Created,
+    NoContent,
+    PartialContent,
+    NotModified,
+    BadRequest,
+    Forbidden,
     NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
+    Conflict,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UriTooLong,
+    RangeNotSatisfiable,
+    UpgradeRequired,
+    RequestHeaderFieldsTooLarge,
     InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported,
+    // any other code, as relayed from an upstream server
+    Other(u16),
 }
 
 impl StatusCode {
+    pub fn from_code(code: u16) -> StatusCode {
+        match code {
+            101 => StatusCode::SwitchingProtocols,
+            200 => StatusCode::Ok,
+            201 => StatusCode::Created,
+            204 => StatusCode::NoContent,
+            206 => StatusCode::PartialContent,
+            304 => StatusCode::NotModified,
+            400 => StatusCode::BadRequest,
+            403 => StatusCode::Forbidden,
+            404 => StatusCode::NotFound,
+            405 => StatusCode::MethodNotAllowed,
+            406 => StatusCode::NotAcceptable,
+            408 => StatusCode::RequestTimeout,
+            409 => StatusCode::Conflict,
+            412 => StatusCode::PreconditionFailed,
+            413 => StatusCode::PayloadTooLarge,
+            414 => StatusCode::UriTooLong,
+            416 => StatusCode::RangeNotSatisfiable,
+            426 => StatusCode::UpgradeRequired,
+            431 => StatusCode::RequestHeaderFieldsTooLarge,
+            500 => StatusCode::InternalServerError,
+            501 => StatusCode::NotImplemented,
+            502 => StatusCode::BadGateway,
+            503 => StatusCode::ServiceUnavailable,
+            504 => StatusCode::GatewayTimeout,
+            505 => StatusCode::HttpVersionNotSupported,
+            code => StatusCode::Other(code),
+        }
+    }
+
     pub fn code(&self) -> u16 {
         match self {
+            StatusCode::SwitchingProtocols => 101,
             StatusCode::Ok => 200,
             StatusCode::Created => 201,
+            StatusCode::NoContent => 204,
+            StatusCode::PartialContent => 206,
+            StatusCode::NotModified => 304,
+            StatusCode::BadRequest => 400,
+            StatusCode::Forbidden => 403,
             StatusCode::NotFound => 404,
+            StatusCode::MethodNotAllowed => 405,
+            StatusCode::NotAcceptable => 406,
+            StatusCode::RequestTimeout => 408,
+            StatusCode::Conflict => 409,
+            StatusCode::PreconditionFailed => 412,
+            StatusCode::PayloadTooLarge => 413,
+            StatusCode::UriTooLong => 414,
+            StatusCode::RangeNotSatisfiable => 416,
+            StatusCode::UpgradeRequired => 426,
+            StatusCode::RequestHeaderFieldsTooLarge => 431,
             StatusCode::InternalServerError => 500,
+            StatusCode::NotImplemented => 501,
+            StatusCode::BadGateway => 502,
+            StatusCode::ServiceUnavailable => 503,
+            StatusCode::GatewayTimeout => 504,
+            StatusCode::HttpVersionNotSupported => 505,
+            StatusCode::Other(code) => *code,
         }
     }
 
     pub fn reason_phrase(&self) -> &str {
         match self {
+            StatusCode::SwitchingProtocols => "Switching Protocols",
             StatusCode::Ok => "OK",
             StatusCode::Created => "Created",
+            StatusCode::NoContent => "No Content",
+            StatusCode::PartialContent => "Partial Content",
+            StatusCode::NotModified => "Not Modified",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::Forbidden => "Forbidden",
             StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::NotAcceptable => "Not Acceptable",
+            StatusCode::RequestTimeout => "Request Timeout",
+            StatusCode::Conflict => "Conflict",
+            StatusCode::PreconditionFailed => "Precondition Failed",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::UriTooLong => "URI Too Long",
+            StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            StatusCode::UpgradeRequired => "Upgrade Required",
+            StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
             StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::NotImplemented => "Not Implemented",
+            StatusCode::BadGateway => "Bad Gateway",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+            StatusCode::GatewayTimeout => "Gateway Timeout",
+            StatusCode::HttpVersionNotSupported => "HTTP Version Not Supported",
+            // the reason phrase is optional, clients go by the code
+            StatusCode::Other(_) => "",
         }
     }
+
+    pub fn has_body(&self) -> bool {
+        !matches!(
+            self,
+            StatusCode::SwitchingProtocols | StatusCode::NoContent | StatusCode::NotModified
+        )
+    }
 }
@@ -1,5 +1,9 @@
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
 };
 
@@ -54,6 +58,57 @@ impl Drop for ThreadPool {
     }
 }
 
+// threads of their own for connections that stay open indefinitely, such as
+// event streams, so they can't take every worker of the pool; at most max
+// run at once
+#[derive(Clone)]
+pub struct Detached {
+    running: Arc<AtomicUsize>,
+    max: usize,
+}
+
+impl Detached {
+    pub fn new(max: usize) -> Detached {
+        Detached {
+            running: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    // None when max threads are running already
+    pub fn reserve(&self) -> Option<Slot> {
+        self.running
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+                (running < self.max).then_some(running + 1)
+            })
+            .ok()?;
+        Some(Slot(Arc::clone(&self.running)))
+    }
+}
+
+// a reserved thread, given back once the job it runs is done
+pub struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    pub fn spawn<F>(self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        thread::spawn(move || {
+            let _slot = self;
+            if panic::catch_unwind(AssertUnwindSafe(f)).is_err() {
+                println!("err: detached connection recovered from a panic");
+            }
+        });
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 struct Worker {
     id: usize,
     thread: Option<thread::JoinHandle<()>>,
@@ -65,10 +120,11 @@ impl Worker {
             let message = receiver.lock().unwrap().recv();
 
             match message {
+                // a job that panics takes its connection down, not the worker
                 Ok(job) => {
-                    println!("Worker {id} got a job; executing.");
-
-                    job();
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        println!("err: worker {id} recovered from a panicking job");
+                    }
                 }
                 Err(_) => {
                     println!("Worker {id} disconnected; shutting down.");
@@ -83,3 +139,37 @@ impl Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn survives_a_panicking_job() {
+        let pool = ThreadPool::new(1);
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(|| panic!("job failed"));
+        pool.execute(move || sender.send(()).unwrap());
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn detached_threads_are_bounded() {
+        let detached = Detached::new(1);
+        let slot = detached.reserve().unwrap();
+        assert!(detached.reserve().is_none());
+
+        let (sender, receiver) = mpsc::channel();
+        slot.spawn(move || sender.send(()).unwrap());
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        // the slot is given back once the thread is done with it
+        for _ in 0..100 {
+            if detached.reserve().is_some() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("slot never given back");
+    }
+}
@@ -0,0 +1,863 @@
+// a TLS 1.3 server (RFC 8446): x25519 key exchange, AES-128-GCM or
+// ChaCha20-Poly1305 with SHA-256, an Ed25519 or RSA-PSS certificate, no
+// session resumption or early data and no client certificates
+use crate::aesgcm;
+use crate::chacha20poly1305;
+use crate::curve25519::x25519;
+use crate::curve25519::x25519_base;
+use crate::pem::{self, PrivateKey};
+use crate::request::ReadTimeout;
+use crate::sha2::{hkdf_expand, hkdf_extract, hmac_sha256, sha256, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+// record content types
+const CHANGE_CIPHER_SPEC: u8 = 20;
+const ALERT: u8 = 21;
+const HANDSHAKE: u8 = 22;
+const APPLICATION_DATA: u8 = 23;
+
+// handshake message types
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const ENCRYPTED_EXTENSIONS: u8 = 8;
+const CERTIFICATE: u8 = 11;
+const CERTIFICATE_VERIFY: u8 = 15;
+const FINISHED: u8 = 20;
+const KEY_UPDATE: u8 = 24;
+const MESSAGE_HASH: u8 = 254;
+
+// extensions
+const SUPPORTED_GROUPS: u16 = 10;
+const SIGNATURE_ALGORITHMS: u16 = 13;
+const ALPN: u16 = 16;
+const SUPPORTED_VERSIONS: u16 = 43;
+const KEY_SHARE: u16 = 51;
+
+// alert levels and descriptions
+const WARNING: u8 = 1;
+const FATAL: u8 = 2;
+const CLOSE_NOTIFY: u8 = 0;
+const UNEXPECTED_MESSAGE: u8 = 10;
+const BAD_RECORD_MAC: u8 = 20;
+const RECORD_OVERFLOW: u8 = 22;
+const HANDSHAKE_FAILURE: u8 = 40;
+const ILLEGAL_PARAMETER: u8 = 47;
+const DECODE_ERROR: u8 = 50;
+const DECRYPT_ERROR: u8 = 51;
+const PROTOCOL_VERSION: u8 = 70;
+
+const TLS13: u16 = 0x0304;
+const X25519: u16 = 0x001d;
+const ED25519: u16 = 0x0807;
+const RSA_PSS_RSAE_SHA256: u16 = 0x0804;
+
+const MAX_FRAGMENT: usize = 1 << 14;
+const MAX_HANDSHAKE_MESSAGE: usize = 1 << 16;
+
+// the random of a ServerHello that is really a HelloRetryRequest
+const RETRY_RANDOM: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+// the certificate chain and key, loaded once and shared by every connection
+pub struct TlsConfig {
+    certificates: Vec<Vec<u8>>,
+    key: PrivateKey,
+}
+
+impl TlsConfig {
+    pub fn load(cert_path: &str, key_path: &str) -> Result<Self, String> {
+        let read = |path: &str| fs::read_to_string(path).map_err(|e| format!("{path}: {e}"));
+
+        let certificates =
+            pem::certificates(&read(cert_path)?).map_err(|e| format!("{cert_path}: {e}"))?;
+        let key = pem::private_key(&read(key_path)?).map_err(|e| format!("{key_path}: {e}"))?;
+
+        Ok(Self { certificates, key })
+    }
+
+    fn scheme(&self) -> u16 {
+        match self.key {
+            PrivateKey::Ed25519(_) => ED25519,
+            PrivateKey::Rsa(_) => RSA_PSS_RSAE_SHA256,
+        }
+    }
+
+    fn sign(&self, content: &[u8]) -> io::Result<Vec<u8>> {
+        match self.key {
+            PrivateKey::Ed25519(ref key) => Ok(key.sign(content).to_vec()),
+            PrivateKey::Rsa(ref key) => {
+                let mut salt = [0u8; 32];
+                random(&mut salt)?;
+                let mut seed = vec![0u8; key.len()];
+                random(&mut seed)?;
+                Ok(key.sign_pss(content, &salt, &seed))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Suite {
+    Aes128GcmSha256,
+    ChaCha20Poly1305Sha256,
+}
+
+impl Suite {
+    fn from_id(id: u16) -> Option<Self> {
+        match id {
+            0x1301 => Some(Suite::Aes128GcmSha256),
+            0x1303 => Some(Suite::ChaCha20Poly1305Sha256),
+            _ => None,
+        }
+    }
+
+    fn id(self) -> u16 {
+        match self {
+            Suite::Aes128GcmSha256 => 0x1301,
+            Suite::ChaCha20Poly1305Sha256 => 0x1303,
+        }
+    }
+
+    fn key_len(self) -> usize {
+        match self {
+            Suite::Aes128GcmSha256 => aesgcm::KEY_LEN,
+            Suite::ChaCha20Poly1305Sha256 => chacha20poly1305::KEY_LEN,
+        }
+    }
+
+    fn seal(self, key: &[u8], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        match self {
+            Suite::Aes128GcmSha256 => {
+                let mut k = [0u8; aesgcm::KEY_LEN];
+                k.copy_from_slice(key);
+                aesgcm::seal(&k, nonce, aad, plaintext)
+            }
+            Suite::ChaCha20Poly1305Sha256 => {
+                let mut k = [0u8; chacha20poly1305::KEY_LEN];
+                k.copy_from_slice(key);
+                chacha20poly1305::seal(&k, nonce, aad, plaintext)
+            }
+        }
+    }
+
+    fn open(self, key: &[u8], nonce: &[u8; 12], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Suite::Aes128GcmSha256 => {
+                let mut k = [0u8; aesgcm::KEY_LEN];
+                k.copy_from_slice(key);
+                aesgcm::open(&k, nonce, aad, sealed)
+            }
+            Suite::ChaCha20Poly1305Sha256 => {
+                let mut k = [0u8; chacha20poly1305::KEY_LEN];
+                k.copy_from_slice(key);
+                chacha20poly1305::open(&k, nonce, aad, sealed)
+            }
+        }
+    }
+}
+
+// the keys of one direction, derived from its traffic secret
+struct Protection {
+    suite: Suite,
+    secret: [u8; 32],
+    key: Vec<u8>,
+    iv: [u8; 12],
+    sequence: u64,
+}
+
+impl Protection {
+    fn new(suite: Suite, secret: [u8; 32]) -> Self {
+        let mut iv = [0u8; 12];
+        iv.copy_from_slice(&expand_label(&secret, "iv", &[], 12));
+
+        Self {
+            suite,
+            secret,
+            key: expand_label(&secret, "key", &[], suite.key_len()),
+            iv,
+            sequence: 0,
+        }
+    }
+
+    // KeyUpdate moves on to the next generation of the secret
+    fn update(&mut self) {
+        *self = Protection::new(self.suite, next_secret(&self.secret, "traffic upd", &[]));
+    }
+
+    fn nonce(&mut self) -> [u8; 12] {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[4..].iter_mut().zip(self.sequence.to_be_bytes()) {
+            *n ^= s;
+        }
+        self.sequence += 1;
+        nonce
+    }
+
+    // the real content type goes at the end of the plaintext
+    fn seal(&mut self, content_type: u8, fragment: &[u8]) -> Vec<u8> {
+        let mut inner = fragment.to_vec();
+        inner.push(content_type);
+
+        let len = inner.len() + aesgcm::TAG_LEN;
+        let mut record = vec![APPLICATION_DATA, 3, 3, (len >> 8) as u8, len as u8];
+        let nonce = self.nonce();
+        let sealed = self.suite.seal(&self.key, &nonce, &record, &inner);
+        record.extend_from_slice(&sealed);
+        record
+    }
+
+    fn open(&mut self, header: &[u8], body: &[u8]) -> Option<(u8, Vec<u8>)> {
+        let nonce = self.nonce();
+        let mut inner = self.suite.open(&self.key, &nonce, header, body)?;
+
+        // zero padding may follow the content type
+        while inner.last() == Some(&0) {
+            inner.pop();
+        }
+        let content_type = inner.pop()?;
+        Some((content_type, inner))
+    }
+}
+
+enum Failure {
+    Io(io::Error),
+    // a fatal alert is sent to the client before giving up
+    Alert(u8, &'static str),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::Io(e)
+    }
+}
+
+pub struct TlsStream<S: Read + Write> {
+    stream: S,
+    // received bytes that don't make a whole record yet
+    incoming: Vec<u8>,
+    // handshake messages can span records
+    handshake: Vec<u8>,
+    // decrypted application data not read yet
+    plaintext: Vec<u8>,
+    read: Option<Protection>,
+    write: Option<Protection>,
+    closed: bool,
+}
+
+// runs the server side of the handshake over a freshly accepted stream
+pub fn accept<S: Read + Write>(stream: S, config: &TlsConfig) -> io::Result<TlsStream<S>> {
+    let mut tls = TlsStream {
+        stream,
+        incoming: Vec::new(),
+        handshake: Vec::new(),
+        plaintext: Vec::new(),
+        read: None,
+        write: None,
+        closed: false,
+    };
+
+    match tls.handshake(config) {
+        Ok(()) => Ok(tls),
+        Err(failure) => Err(tls.fail(failure)),
+    }
+}
+
+impl<S: Read + Write> TlsStream<S> {
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    // whether a read can return without waiting on the stream: data that's
+    // decrypted already, a whole record to decrypt or the end of the session
+    pub fn has_pending(&self) -> bool {
+        if self.closed || !self.plaintext.is_empty() {
+            return true;
+        }
+        self.incoming.len() >= 5
+            && self.incoming.len()
+                >= 5 + usize::from(u16::from_be_bytes([self.incoming[3], self.incoming[4]]))
+    }
+
+    fn handshake(&mut self, config: &TlsConfig) -> Result<(), Failure> {
+        let mut transcript = Sha256::new();
+        let mut message = self.read_handshake()?;
+        let mut hello = ClientHello::parse(&message)
+            .ok_or(Failure::Alert(DECODE_ERROR, "malformed ClientHello"))?;
+
+        if !hello.versions.contains(&TLS13) {
+            return Err(Failure::Alert(
+                PROTOCOL_VERSION,
+                "client doesn't offer TLS 1.3",
+            ));
+        }
+        let suite = hello.suite().ok_or(Failure::Alert(
+            HANDSHAKE_FAILURE,
+            "no supported cipher suite",
+        ))?;
+        if !hello.schemes.contains(&config.scheme()) {
+            return Err(Failure::Alert(
+                HANDSHAKE_FAILURE,
+                "client doesn't accept the certificate's signature scheme",
+            ));
+        }
+        let mut sent_change_cipher_spec = false;
+
+        // asks for an x25519 share when the client guessed another group
+        if hello.key_share.is_none() {
+            if !hello.groups.contains(&X25519) {
+                return Err(Failure::Alert(
+                    HANDSHAKE_FAILURE,
+                    "client doesn't support x25519",
+                ));
+            }
+
+            transcript.update(&[MESSAGE_HASH, 0, 0, 32]);
+            transcript.update(&sha256(&message));
+            let retry = server_hello(
+                &RETRY_RANDOM,
+                &hello.session_id,
+                suite,
+                &X25519.to_be_bytes(),
+            );
+            transcript.update(&retry);
+
+            let mut flight = self.records(HANDSHAKE, &retry);
+            if !hello.session_id.is_empty() {
+                flight.extend_from_slice(&[CHANGE_CIPHER_SPEC, 3, 3, 0, 1, 1]);
+                sent_change_cipher_spec = true;
+            }
+            self.stream.write_all(&flight)?;
+
+            message = self.read_handshake()?;
+            hello = ClientHello::parse(&message)
+                .ok_or(Failure::Alert(DECODE_ERROR, "malformed ClientHello"))?;
+            if hello.key_share.is_none() || hello.suite() != Some(suite) {
+                return Err(Failure::Alert(
+                    ILLEGAL_PARAMETER,
+                    "ClientHello ignores the retry",
+                ));
+            }
+        }
+        transcript.update(&message);
+        if !self.handshake.is_empty() {
+            return Err(Failure::Alert(UNEXPECTED_MESSAGE, "data after ClientHello"));
+        }
+
+        let client_share = match hello.key_share {
+            Some(ref share) if share.len() == 32 => {
+                let mut point = [0u8; 32];
+                point.copy_from_slice(share);
+                point
+            }
+            _ => return Err(Failure::Alert(ILLEGAL_PARAMETER, "bad x25519 key share")),
+        };
+
+        let mut private = [0u8; 32];
+        random(&mut private)?;
+        let shared = x25519(&private, &client_share);
+        if shared == [0u8; 32] {
+            return Err(Failure::Alert(
+                ILLEGAL_PARAMETER,
+                "x25519 key share of low order",
+            ));
+        }
+
+        let mut server_random = [0u8; 32];
+        random(&mut server_random)?;
+        let mut key_share = X25519.to_be_bytes().to_vec();
+        key_share.extend_from_slice(&prefixed(2, &x25519_base(&private)));
+        let hello_message = server_hello(&server_random, &hello.session_id, suite, &key_share);
+        transcript.update(&hello_message);
+
+        let mut flight = self.records(HANDSHAKE, &hello_message);
+        if !hello.session_id.is_empty() && !sent_change_cipher_spec {
+            flight.extend_from_slice(&[CHANGE_CIPHER_SPEC, 3, 3, 0, 1, 1]);
+        }
+
+        // key schedule, without a pre-shared key
+        let empty_hash = sha256(&[]);
+        let early_secret = hkdf_extract(&[0; 32], &[0; 32]);
+        let handshake_secret =
+            hkdf_extract(&next_secret(&early_secret, "derived", &empty_hash), &shared);
+        let hash = transcript.clone().finish();
+        let client_handshake = next_secret(&handshake_secret, "c hs traffic", &hash);
+        let server_handshake = next_secret(&handshake_secret, "s hs traffic", &hash);
+        self.read = Some(Protection::new(suite, client_handshake));
+        self.write = Some(Protection::new(suite, server_handshake));
+
+        let mut extensions = Vec::new();
+        if hello
+            .protocols
+            .iter()
+            .any(|protocol| protocol == b"http/1.1")
+        {
+            extensions = extension(ALPN, &prefixed(2, &prefixed(1, b"http/1.1")));
+        }
+        let mut messages = message_of(ENCRYPTED_EXTENSIONS, &prefixed(2, &extensions));
+
+        let mut chain = Vec::new();
+        for certificate in &config.certificates {
+            chain.extend_from_slice(&prefixed(3, certificate));
+            chain.extend_from_slice(&[0, 0]);
+        }
+        let mut body = vec![0];
+        body.extend_from_slice(&prefixed(3, &chain));
+        messages.extend_from_slice(&message_of(CERTIFICATE, &body));
+        transcript.update(&messages);
+
+        let mut signed = vec![0x20; 64];
+        signed.extend_from_slice(b"TLS 1.3, server CertificateVerify\0");
+        signed.extend_from_slice(&transcript.clone().finish());
+        let mut body = config.scheme().to_be_bytes().to_vec();
+        body.extend_from_slice(&prefixed(2, &config.sign(&signed)?));
+        let verify = message_of(CERTIFICATE_VERIFY, &body);
+        transcript.update(&verify);
+        messages.extend_from_slice(&verify);
+
+        let finished = message_of(
+            FINISHED,
+            &finished_mac(&server_handshake, &transcript.clone().finish()),
+        );
+        transcript.update(&finished);
+        messages.extend_from_slice(&finished);
+
+        flight.extend_from_slice(&self.records(HANDSHAKE, &messages));
+        self.stream.write_all(&flight)?;
+        self.stream.flush()?;
+
+        let hash = transcript.finish();
+        let master_secret = hkdf_extract(
+            &next_secret(&handshake_secret, "derived", &empty_hash),
+            &[0; 32],
+        );
+        self.write = Some(Protection::new(
+            suite,
+            next_secret(&master_secret, "s ap traffic", &hash),
+        ));
+
+        let message = self.read_handshake()?;
+        let expected = message_of(FINISHED, &finished_mac(&client_handshake, &hash));
+        if !same(&message, &expected) {
+            return Err(Failure::Alert(
+                DECRYPT_ERROR,
+                "client Finished doesn't verify",
+            ));
+        }
+        if !self.handshake.is_empty() {
+            return Err(Failure::Alert(UNEXPECTED_MESSAGE, "data after Finished"));
+        }
+        self.read = Some(Protection::new(
+            suite,
+            next_secret(&master_secret, "c ap traffic", &hash),
+        ));
+
+        Ok(())
+    }
+
+    // the next whole handshake message, header included
+    fn read_handshake(&mut self) -> Result<Vec<u8>, Failure> {
+        loop {
+            if let Some(message) = self.next_handshake_message()? {
+                return Ok(message);
+            }
+
+            match self.read_record()? {
+                Some((HANDSHAKE, fragment)) => self.handshake.extend_from_slice(&fragment),
+                // sent for middlebox compatibility only
+                Some((CHANGE_CIPHER_SPEC, _)) => {}
+                Some((ALERT, alert)) => {
+                    return Err(Failure::Io(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        format!("alert {} from client", alert.get(1).unwrap_or(&0)),
+                    )))
+                }
+                Some(_) => return Err(Failure::Alert(UNEXPECTED_MESSAGE, "expected a handshake")),
+                None => {
+                    return Err(Failure::Io(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed during the handshake",
+                    )))
+                }
+            }
+        }
+    }
+
+    fn next_handshake_message(&mut self) -> Result<Option<Vec<u8>>, Failure> {
+        if self.handshake.len() < 4 {
+            return Ok(None);
+        }
+        let len = u24(&self.handshake[1..4]);
+        if len > MAX_HANDSHAKE_MESSAGE {
+            return Err(Failure::Alert(DECODE_ERROR, "handshake message too long"));
+        }
+        if self.handshake.len() < 4 + len {
+            return Ok(None);
+        }
+        Ok(Some(self.handshake.drain(..4 + len).collect()))
+    }
+
+    // None once the client has closed the connection
+    fn read_record(&mut self) -> Result<Option<(u8, Vec<u8>)>, Failure> {
+        loop {
+            if let Some(&content_type) = self.incoming.first() {
+                if !(CHANGE_CIPHER_SPEC..=APPLICATION_DATA).contains(&content_type) {
+                    return Err(Failure::Alert(UNEXPECTED_MESSAGE, "not a TLS record"));
+                }
+            }
+            if self.incoming.len() >= 5 {
+                let len = usize::from(u16::from_be_bytes([self.incoming[3], self.incoming[4]]));
+                if len > MAX_FRAGMENT + 256 {
+                    return Err(Failure::Alert(RECORD_OVERFLOW, "record too long"));
+                }
+
+                if self.incoming.len() >= 5 + len {
+                    let record: Vec<u8> = self.incoming.drain(..5 + len).collect();
+                    let (header, body) = record.split_at(5);
+
+                    return match self.read {
+                        Some(ref mut protection) if header[0] == APPLICATION_DATA => protection
+                            .open(header, body)
+                            .map(Some)
+                            .ok_or(Failure::Alert(BAD_RECORD_MAC, "record doesn't decrypt")),
+                        Some(_) if header[0] != CHANGE_CIPHER_SPEC => {
+                            Err(Failure::Alert(UNEXPECTED_MESSAGE, "unprotected record"))
+                        }
+                        _ => Ok(Some((header[0], body.to_vec()))),
+                    };
+                }
+            }
+
+            // a timeout leaves a partial record in place for the next read
+            let mut chunk = [0u8; 8192];
+            let n = self.stream.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.incoming.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    // handshake messages once the session is established, only KeyUpdate
+    fn post_handshake(&mut self) -> Result<(), Failure> {
+        while let Some(message) = self.next_handshake_message()? {
+            if message[0] != KEY_UPDATE || message.len() != 5 {
+                return Err(Failure::Alert(
+                    UNEXPECTED_MESSAGE,
+                    "unexpected handshake message",
+                ));
+            }
+            if let Some(ref mut read) = self.read {
+                read.update();
+            }
+
+            match message[4] {
+                0 => {}
+                1 => {
+                    let update = self.records(HANDSHAKE, &message_of(KEY_UPDATE, &[0]));
+                    self.stream.write_all(&update)?;
+                    if let Some(ref mut write) = self.write {
+                        write.update();
+                    }
+                }
+                _ => return Err(Failure::Alert(ILLEGAL_PARAMETER, "bad KeyUpdate")),
+            }
+        }
+        Ok(())
+    }
+
+    // data split into records, protected once keys are in place
+    fn records(&mut self, content_type: u8, data: &[u8]) -> Vec<u8> {
+        let mut records = Vec::new();
+        for fragment in data.chunks(MAX_FRAGMENT) {
+            match self.write {
+                Some(ref mut protection) => {
+                    records.extend_from_slice(&protection.seal(content_type, fragment))
+                }
+                None => {
+                    records.extend_from_slice(&[content_type, 3, 3]);
+                    records.extend_from_slice(&prefixed(2, fragment));
+                }
+            }
+        }
+        records
+    }
+
+    fn send_alert(&mut self, level: u8, description: u8) -> io::Result<()> {
+        let alert = self.records(ALERT, &[level, description]);
+        self.stream.write_all(&alert)
+    }
+
+    // nothing more is written after a fatal alert
+    fn fail(&mut self, failure: Failure) -> io::Error {
+        match failure {
+            Failure::Io(e) => e,
+            Failure::Alert(description, reason) => {
+                let _ = self.send_alert(FATAL, description);
+                self.write = None;
+                io::Error::new(io::ErrorKind::InvalidData, reason)
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.plaintext.is_empty() {
+            if self.closed {
+                return Ok(0);
+            }
+
+            let record = match self.read_record() {
+                Ok(record) => record,
+                Err(failure) => return Err(self.fail(failure)),
+            };
+            match record {
+                Some((APPLICATION_DATA, data)) => self.plaintext = data,
+                Some((ALERT, alert)) if alert.get(1) == Some(&CLOSE_NOTIFY) => self.closed = true,
+                Some((ALERT, alert)) => {
+                    self.write = None;
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        format!("alert {} from client", alert.get(1).unwrap_or(&0)),
+                    ));
+                }
+                Some((HANDSHAKE, fragment)) => {
+                    self.handshake.extend_from_slice(&fragment);
+                    if let Err(failure) = self.post_handshake() {
+                        return Err(self.fail(failure));
+                    }
+                }
+                Some(_) => {
+                    let failure = Failure::Alert(UNEXPECTED_MESSAGE, "unexpected record");
+                    return Err(self.fail(failure));
+                }
+                // closed without close_notify
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.plaintext.len());
+        buf[..n].copy_from_slice(&self.plaintext[..n]);
+        self.plaintext.drain(..n);
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.write.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "tls session is closed",
+            ));
+        }
+        let records = self.records(APPLICATION_DATA, buf);
+        self.stream.write_all(&records)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: Read + Write + ReadTimeout> ReadTimeout for TlsStream<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+impl<S: Read + Write> Drop for TlsStream<S> {
+    fn drop(&mut self) {
+        if self.write.is_some() {
+            let _ = self.send_alert(WARNING, CLOSE_NOTIFY);
+        }
+    }
+}
+
+#[derive(Default)]
+struct ClientHello {
+    session_id: Vec<u8>,
+    suites: Vec<u16>,
+    versions: Vec<u16>,
+    groups: Vec<u16>,
+    // only an x25519 share is of use
+    key_share: Option<Vec<u8>>,
+    schemes: Vec<u16>,
+    protocols: Vec<Vec<u8>>,
+}
+
+impl ClientHello {
+    fn parse(message: &[u8]) -> Option<Self> {
+        let mut reader = Reader::new(message);
+        if reader.u8()? != CLIENT_HELLO {
+            return None;
+        }
+        let mut body = Reader::new(reader.vec(3)?);
+
+        // legacy_version and random
+        body.bytes(2 + 32)?;
+        let mut hello = ClientHello {
+            session_id: body.vec(1)?.to_vec(),
+            suites: u16s(body.vec(2)?)?,
+            ..Default::default()
+        };
+        // legacy_compression_methods
+        body.vec(1)?;
+
+        let mut extensions = Reader::new(body.vec(2)?);
+        while !extensions.is_empty() {
+            let kind = extensions.u16()?;
+            let mut data = Reader::new(extensions.vec(2)?);
+            match kind {
+                SUPPORTED_VERSIONS => hello.versions = u16s(data.vec(1)?)?,
+                SUPPORTED_GROUPS => hello.groups = u16s(data.vec(2)?)?,
+                SIGNATURE_ALGORITHMS => hello.schemes = u16s(data.vec(2)?)?,
+                KEY_SHARE => {
+                    let mut shares = Reader::new(data.vec(2)?);
+                    while !shares.is_empty() {
+                        let group = shares.u16()?;
+                        let key = shares.vec(2)?;
+                        if group == X25519 {
+                            hello.key_share = Some(key.to_vec());
+                        }
+                    }
+                }
+                ALPN => {
+                    let mut protocols = Reader::new(data.vec(2)?);
+                    while !protocols.is_empty() {
+                        hello.protocols.push(protocols.vec(1)?.to_vec());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Some(hello)
+    }
+
+    // the first suite of the client's preference that is supported
+    fn suite(&self) -> Option<Suite> {
+        self.suites.iter().find_map(|&id| Suite::from_id(id))
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.data.len() < n {
+            return None;
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    // a vector preceded by its length in width bytes
+    fn vec(&mut self, width: usize) -> Option<&'a [u8]> {
+        let len = self
+            .bytes(width)?
+            .iter()
+            .fold(0, |len, &b| (len << 8) | usize::from(b));
+        self.bytes(len)
+    }
+}
+
+fn u16s(data: &[u8]) -> Option<Vec<u16>> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(
+        data.chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect(),
+    )
+}
+
+fn u24(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |len, &b| (len << 8) | usize::from(b))
+}
+
+fn prefixed(width: usize, data: &[u8]) -> Vec<u8> {
+    let mut vec = data.len().to_be_bytes()[8 - width..].to_vec();
+    vec.extend_from_slice(data);
+    vec
+}
+
+fn message_of(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = vec![kind];
+    message.extend_from_slice(&prefixed(3, body));
+    message
+}
+
+fn extension(kind: u16, data: &[u8]) -> Vec<u8> {
+    let mut extension = kind.to_be_bytes().to_vec();
+    extension.extend_from_slice(&prefixed(2, data));
+    extension
+}
+
+fn server_hello(random: &[u8; 32], session_id: &[u8], suite: Suite, key_share: &[u8]) -> Vec<u8> {
+    let mut body = vec![3, 3];
+    body.extend_from_slice(random);
+    body.extend_from_slice(&prefixed(1, session_id));
+    body.extend_from_slice(&suite.id().to_be_bytes());
+    body.push(0);
+
+    let mut extensions = extension(SUPPORTED_VERSIONS, &TLS13.to_be_bytes());
+    extensions.extend_from_slice(&extension(KEY_SHARE, key_share));
+    body.extend_from_slice(&prefixed(2, &extensions));
+
+    message_of(SERVER_HELLO, &body)
+}
+
+// HKDF-Expand-Label
+fn expand_label(secret: &[u8], label: &str, context: &[u8], len: usize) -> Vec<u8> {
+    let mut info = (len as u16).to_be_bytes().to_vec();
+    info.extend_from_slice(&prefixed(1, format!("tls13 {label}").as_bytes()));
+    info.extend_from_slice(&prefixed(1, context));
+    hkdf_expand(secret, &info, len)
+}
+
+// Derive-Secret, given the transcript hash
+fn next_secret(secret: &[u8], label: &str, hash: &[u8]) -> [u8; 32] {
+    let mut next = [0u8; 32];
+    next.copy_from_slice(&expand_label(secret, label, hash, 32));
+    next
+}
+
+fn finished_mac(traffic_secret: &[u8], hash: &[u8]) -> [u8; 32] {
+    hmac_sha256(&expand_label(traffic_secret, "finished", &[], 32), hash)
+}
+
+// compared without an early exit
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn random(buf: &mut [u8]) -> io::Result<()> {
+    File::open("/dev/urandom")?.read_exact(buf)
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+// splits a request target into its path and query
+pub fn split_target(target: &str) -> (&str, &str) {
+    target.split_once('?').unwrap_or((target, ""))
+}
+
+// None for a truncated or non-hex escape, or when the result isn't UTF-8
+pub fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+// keeps unreserved characters and the path separator
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for &b in s.as_bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~' | b'/') {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+// application/x-www-form-urlencoded pairs, the first occurrence of a name wins
+// and pairs that don't decode are skipped
+pub fn parse_query(query: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let name = percent_decode(&name.replace('+', " "));
+        let value = percent_decode(&value.replace('+', " "));
+        if let (Some(name), Some(value)) = (name, value) {
+            params.entry(name).or_insert(value);
+        }
+    }
+
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_targets() {
+        assert_eq!(split_target("/a/b?x=1&y"), ("/a/b", "x=1&y"));
+        assert_eq!(split_target("/a?b?c"), ("/a", "b?c"));
+        assert_eq!(split_target("/a"), ("/a", ""));
+    }
+
+    #[test]
+    fn percent_decodes() {
+        assert_eq!(percent_decode("a%20b%2Fc").as_deref(), Some("a b/c"));
+        assert_eq!(percent_decode("%C3%A9t%c3%a9").as_deref(), Some("été"));
+        assert_eq!(percent_decode("a+b").as_deref(), Some("a+b"));
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("%4"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%+1"), None);
+        assert_eq!(percent_decode("%ff"), None);
+        // an escape running into a multi-byte character
+        assert_eq!(percent_decode("%é"), None);
+    }
+
+    #[test]
+    fn percent_encode_round_trips() {
+        assert_eq!(percent_encode("dir/a b&c.txt"), "dir/a%20b%26c.txt");
+        assert_eq!(percent_encode("é"), "%C3%A9");
+        for s in ["plain", "a b/c?d#e", "100%", "été~_-."] {
+            assert_eq!(percent_decode(&percent_encode(s)).as_deref(), Some(s));
+        }
+    }
+
+    #[test]
+    fn parses_form_encoded_queries() {
+        let query = parse_query("q=a+b%21&empty=&flag&q=second&bad=%zz&&%41=1");
+        assert_eq!(query.get("q").map(String::as_str), Some("a b!"));
+        assert_eq!(query.get("empty").map(String::as_str), Some(""));
+        assert_eq!(query.get("flag").map(String::as_str), Some(""));
+        assert_eq!(query.get("A").map(String::as_str), Some("1"));
+        assert!(!query.contains_key("bad"));
+        assert_eq!(query.len(), 4);
+    }
+}
@@ -0,0 +1,653 @@
+// WebSocket connections (RFC 6455): the opening handshake on a GET route,
+// then a frame codec that hands whole messages to the route's handler
+use crate::base64;
+use crate::request::{ReadTimeout, Version};
+use crate::response::{ContentType, Response};
+use crate::router::Context;
+use crate::sha1::sha1;
+use crate::statuscode::StatusCode;
+use crate::tls::TlsStream;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// opcodes
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+// close codes
+pub const NORMAL_CLOSURE: u16 = 1000;
+const GOING_AWAY: u16 = 1001;
+const PROTOCOL_ERROR: u16 = 1002;
+const INVALID_PAYLOAD: u16 = 1007;
+const MESSAGE_TOO_BIG: u16 = 1009;
+
+// how long to wait for the client's close frame after sending ours
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const READ_CHUNK: usize = 4 * 1024;
+
+// what a websocket route runs once the 101 response is written
+pub type Upgrade = Arc<dyn Fn(&Context, &mut WebSocket) + Send + Sync>;
+
+// any connection a WebSocket can run on, plain or TLS
+pub trait Socket: Read + Write + ReadTimeout + Send {
+    // the TCP connection underneath
+    fn tcp(&self) -> &TcpStream;
+    // whether a read returns without waiting on the TCP connection
+    fn has_pending(&self) -> bool;
+}
+
+impl Socket for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+
+    fn has_pending(&self) -> bool {
+        false
+    }
+}
+
+impl Socket for TlsStream<TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        self.get_ref()
+    }
+
+    fn has_pending(&self) -> bool {
+        TlsStream::has_pending(self)
+    }
+}
+
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+// answers the opening handshake, anything but a version 13 upgrade gets 426
+pub fn handshake(ctx: &Context, upgrade: Upgrade) -> Response {
+    let headers = &ctx.request.headers;
+    let is_upgrade = ctx.method() == "GET"
+        && ctx.request.version == Version::Http11
+        && headers.has_token("Upgrade", "websocket")
+        && headers.has_token("Connection", "upgrade")
+        && headers.get("Sec-WebSocket-Version") == Some("13");
+    if !is_upgrade {
+        return Response::new(StatusCode::UpgradeRequired, ContentType::TextPlain, "")
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Version", "13");
+    }
+
+    // the key is a base64 encoded 16 byte nonce
+    let key = match headers.get("Sec-WebSocket-Key") {
+        Some(key) if base64::decode(key).is_some_and(|nonce| nonce.len() == 16) => key,
+        _ => return Response::new(StatusCode::BadRequest, ContentType::TextPlain, ""),
+    };
+
+    Response::new(StatusCode::SwitchingProtocols, ContentType::TextPlain, "")
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", &accept_key(key))
+        .with_upgrade(upgrade)
+}
+
+fn accept_key(key: &str) -> String {
+    base64::encode(&sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+enum Failure {
+    Io(io::Error),
+    // nothing arrived for the idle timeout, not even the answer to a ping
+    TimedOut,
+    // the connection is closed with this code
+    Close(u16, &'static str),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::Io(e)
+    }
+}
+
+// the connection and whether a close frame went out on it, shared by the
+// receiving side and every Sender
+struct Transport {
+    socket: Box<dyn Socket>,
+    close_sent: bool,
+}
+
+impl Transport {
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        if self.close_sent {
+            return Err(io::Error::new(
+                ErrorKind::BrokenPipe,
+                "websocket is closing",
+            ));
+        }
+        match message {
+            Message::Text(text) => self.write_frame(TEXT, text.as_bytes()),
+            Message::Binary(data) => self.write_frame(BINARY, data),
+        }
+    }
+
+    fn send_close(&mut self, code: u16) -> io::Result<()> {
+        self.close_sent = true;
+        self.write_frame(CLOSE, &code.to_be_bytes())
+    }
+
+    // server frames go out unmasked and unfragmented
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xffff => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+
+        self.socket.write_all(&frame)?;
+        self.socket.flush()
+    }
+}
+
+fn lock(transport: &Mutex<Transport>) -> MutexGuard<'_, Transport> {
+    match transport.lock() {
+        Ok(transport) => transport,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// sends messages on a WebSocket from any thread while its handler receives
+#[derive(Clone)]
+pub struct Sender {
+    transport: Arc<Mutex<Transport>>,
+}
+
+impl Sender {
+    pub fn send(&self, message: &Message) -> io::Result<()> {
+        lock(&self.transport).send(message)
+    }
+}
+
+// the receiving side of a WebSocket, handed to the route's handler
+pub struct WebSocket {
+    transport: Arc<Mutex<Transport>>,
+    // the TCP connection once more, waited on for something to read so the
+    // transport stays free for senders meanwhile
+    readable: TcpStream,
+    // bytes received but not decoded into a frame yet
+    buffer: Vec<u8>,
+    max_message: usize,
+    // opcode and payload so far of a fragmented message
+    partial: Option<(u8, Vec<u8>)>,
+    closed: bool,
+    // a ping went out after the idle timeout and nothing came back since
+    ping_sent: bool,
+}
+
+impl WebSocket {
+    // buffered holds whatever the client sent right after the handshake, the
+    // socket's read timeout is how long the client may stay silent
+    pub fn new(socket: Box<dyn Socket>, buffered: Vec<u8>, max_message: usize) -> io::Result<Self> {
+        let readable = socket.tcp().try_clone()?;
+        Ok(Self {
+            transport: Arc::new(Mutex::new(Transport {
+                socket,
+                close_sent: false,
+            })),
+            readable,
+            buffer: buffered,
+            max_message,
+            partial: None,
+            closed: false,
+            ping_sent: false,
+        })
+    }
+
+    // for sending from other threads, e.g. to push updates while the handler
+    // waits in recv
+    pub fn sender(&self) -> Sender {
+        Sender {
+            transport: Arc::clone(&self.transport),
+        }
+    }
+
+    // the next text or binary message, None once the connection is closed;
+    // pings are answered on the way
+    pub fn recv(&mut self) -> io::Result<Option<Message>> {
+        match self.next_message() {
+            Ok(message) => Ok(message),
+            Err(Failure::Io(e)) => Err(e),
+            // the client is gone for all we know, so the closing handshake
+            // isn't waited for
+            Err(Failure::TimedOut) => {
+                let mut transport = lock(&self.transport);
+                if !transport.close_sent {
+                    let _ = transport.send_close(GOING_AWAY);
+                }
+                self.closed = true;
+                Ok(None)
+            }
+            Err(Failure::Close(code, reason)) => {
+                let mut transport = lock(&self.transport);
+                if !transport.close_sent {
+                    let _ = transport.send_close(code);
+                }
+                self.closed = true;
+                Err(io::Error::new(ErrorKind::InvalidData, reason))
+            }
+        }
+    }
+
+    pub fn send(&self, message: &Message) -> io::Result<()> {
+        lock(&self.transport).send(message)
+    }
+
+    // starts the closing handshake and waits a little for the client's answer,
+    // messages arriving in the meantime are dropped
+    pub fn close(&mut self, code: u16) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        {
+            let mut transport = lock(&self.transport);
+            if !transport.close_sent {
+                transport.send_close(code)?;
+            }
+        }
+
+        self.readable.set_read_timeout(Some(CLOSE_TIMEOUT))?;
+        while !self.closed {
+            match self.next_message() {
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                // no answer to our close frame, the connection is dropped anyway
+                Err(Failure::TimedOut) => break,
+                Err(Failure::Io(e)) => return Err(e),
+                Err(Failure::Close(_, reason)) => {
+                    return Err(io::Error::new(ErrorKind::InvalidData, reason))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next_message(&mut self) -> Result<Option<Message>, Failure> {
+        loop {
+            if self.closed {
+                return Ok(None);
+            }
+            let frame = match self.read_frame()? {
+                Some(frame) => frame,
+                // gone without a close frame
+                None => {
+                    self.closed = true;
+                    return Ok(None);
+                }
+            };
+
+            match frame.opcode {
+                TEXT | BINARY if self.partial.is_some() => {
+                    return Err(Failure::Close(
+                        PROTOCOL_ERROR,
+                        "expected a continuation frame",
+                    ))
+                }
+                TEXT | BINARY if frame.fin => {
+                    return message(frame.opcode, frame.payload).map(Some)
+                }
+                TEXT | BINARY => self.partial = Some((frame.opcode, frame.payload)),
+                CONTINUATION => {
+                    let (opcode, mut data) = self.partial.take().ok_or(Failure::Close(
+                        PROTOCOL_ERROR,
+                        "continuation frame without a message",
+                    ))?;
+                    data.extend_from_slice(&frame.payload);
+                    if data.len() > self.max_message {
+                        return Err(Failure::Close(MESSAGE_TOO_BIG, "message too big"));
+                    }
+
+                    if frame.fin {
+                        return message(opcode, data).map(Some);
+                    }
+                    self.partial = Some((opcode, data));
+                }
+                PING => {
+                    let mut transport = lock(&self.transport);
+                    if !transport.close_sent {
+                        transport.write_frame(PONG, &frame.payload)?;
+                    }
+                }
+                PONG => {}
+                CLOSE => {
+                    let code = close_code(&frame.payload)?;
+                    // echoes the client's code, as the closing handshake asks
+                    let mut transport = lock(&self.transport);
+                    if !transport.close_sent {
+                        transport.send_close(code.unwrap_or(NORMAL_CLOSURE))?;
+                    }
+                    self.closed = true;
+                    return Ok(None);
+                }
+                _ => return Err(Failure::Close(PROTOCOL_ERROR, "unknown opcode")),
+            }
+        }
+    }
+
+    // None when the client closed the connection
+    fn read_frame(&mut self) -> Result<Option<Frame>, Failure> {
+        if !self.fill(2)? {
+            return Ok(None);
+        }
+        let (first, second) = (self.buffer[0], self.buffer[1]);
+        let fin = first & 0x80 != 0;
+        let opcode = first & 0x0f;
+
+        // no extension was negotiated that would give meaning to RSV1-3
+        if first & 0x70 != 0 {
+            return Err(Failure::Close(PROTOCOL_ERROR, "reserved bits set"));
+        }
+        if second & 0x80 == 0 {
+            return Err(Failure::Close(PROTOCOL_ERROR, "client frame not masked"));
+        }
+
+        let (len, offset) = match second & 0x7f {
+            126 => {
+                if !self.fill(4)? {
+                    return Ok(None);
+                }
+                (
+                    u64::from(u16::from_be_bytes([self.buffer[2], self.buffer[3]])),
+                    4,
+                )
+            }
+            127 => {
+                if !self.fill(10)? {
+                    return Ok(None);
+                }
+                let mut len = [0u8; 8];
+                len.copy_from_slice(&self.buffer[2..10]);
+                (u64::from_be_bytes(len), 10)
+            }
+            len => (u64::from(len), 2),
+        };
+
+        if opcode >= CLOSE && (!fin || len > 125) {
+            return Err(Failure::Close(PROTOCOL_ERROR, "bad control frame"));
+        }
+        if len > self.max_message as u64 {
+            return Err(Failure::Close(MESSAGE_TOO_BIG, "message too big"));
+        }
+
+        let len = len as usize;
+        if !self.fill(offset + 4 + len)? {
+            return Ok(None);
+        }
+        let mut mask = [0u8; 4];
+        mask.copy_from_slice(&self.buffer[offset..offset + 4]);
+        let payload = self.buffer[offset + 4..offset + 4 + len]
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ mask[i % 4])
+            .collect();
+        self.buffer.drain(..offset + 4 + len);
+
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+
+    // false when the client closed first; a client silent for the read
+    // timeout gets a ping, and one that stays silent after it is given up on
+    fn fill(&mut self, len: usize) -> Result<bool, Failure> {
+        let mut chunk = [0u8; READ_CHUNK];
+        while self.buffer.len() < len {
+            let read = self
+                .wait()
+                .and_then(|()| lock(&self.transport).socket.read(&mut chunk));
+            let bytes = match read {
+                Ok(bytes) => bytes,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    let mut transport = lock(&self.transport);
+                    if self.ping_sent || transport.close_sent {
+                        return Err(Failure::TimedOut);
+                    }
+                    transport.write_frame(PING, &[])?;
+                    self.ping_sent = true;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if bytes == 0 {
+                return Ok(false);
+            }
+            // any traffic shows the client is still there
+            self.ping_sent = false;
+            self.buffer.extend_from_slice(&chunk[..bytes]);
+        }
+        Ok(true)
+    }
+
+    // blocks until a read won't, without holding the transport meanwhile
+    fn wait(&self) -> io::Result<()> {
+        if lock(&self.transport).socket.has_pending() {
+            return Ok(());
+        }
+        self.readable.peek(&mut [0u8; 1]).map(|_| ())
+    }
+}
+
+// senders that outlive the handler find the connection gone
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        let _ = self.readable.shutdown(Shutdown::Both);
+    }
+}
+
+fn message(opcode: u8, data: Vec<u8>) -> Result<Message, Failure> {
+    if opcode == BINARY {
+        return Ok(Message::Binary(data));
+    }
+    String::from_utf8(data)
+        .map(Message::Text)
+        .map_err(|_| Failure::Close(INVALID_PAYLOAD, "text message isn't UTF-8"))
+}
+
+// the status code of a close frame, which may have none
+fn close_code(payload: &[u8]) -> Result<Option<u16>, Failure> {
+    if payload.is_empty() {
+        return Ok(None);
+    }
+    if payload.len() < 2 {
+        return Err(Failure::Close(PROTOCOL_ERROR, "truncated close code"));
+    }
+
+    let code = u16::from_be_bytes([payload[0], payload[1]]);
+    // 1004 to 1006 and 1015 are reserved for reporting, never sent
+    if !matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999) {
+        return Err(Failure::Close(PROTOCOL_ERROR, "invalid close code"));
+    }
+    if std::str::from_utf8(&payload[2..]).is_err() {
+        return Err(Failure::Close(INVALID_PAYLOAD, "close reason isn't UTF-8"));
+    }
+    Ok(Some(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+    use crate::request::Request;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+
+    const MAX_MESSAGE: usize = 1024;
+
+    // the server's WebSocket and the client's end of the connection
+    fn connection() -> (WebSocket, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let socket = WebSocket::new(Box::new(server), Vec::new(), MAX_MESSAGE).unwrap();
+        (socket, client)
+    }
+
+    // a masked client frame
+    fn frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![first];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len @ 126..=0xffff => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    fn read_reply(client: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut reply = vec![0; len];
+        client.read_exact(&mut reply).unwrap();
+        reply
+    }
+
+    fn handshake_status(fields: &[(&str, &str)]) -> u16 {
+        let mut headers = Headers::new();
+        for (name, value) in fields {
+            headers.append(name, value);
+        }
+        let request = Request {
+            method: "GET".to_string(),
+            target: "/ws".to_string(),
+            path: "/ws".to_string(),
+            query: HashMap::new(),
+            version: Version::Http11,
+            headers,
+            body: Vec::new(),
+        };
+        let ctx = Context {
+            request: &request,
+            params: HashMap::new(),
+            directory: "",
+            client: "127.0.0.1",
+            secure: false,
+        };
+        handshake(&ctx, Arc::new(|_: &Context, _: &mut WebSocket| {})).status_code()
+    }
+
+    // RFC 6455 section 1.3
+    #[test]
+    fn answers_the_opening_handshake() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let upgrade = [
+            ("Upgrade", "websocket"),
+            ("Connection", "keep-alive, Upgrade"),
+            ("Sec-WebSocket-Version", "13"),
+            ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+        ];
+        assert_eq!(handshake_status(&upgrade), 101);
+        assert_eq!(handshake_status(&upgrade[..3]), 400);
+        assert_eq!(handshake_status(&upgrade[1..]), 426);
+        let mut old = upgrade;
+        old[2].1 = "8";
+        assert_eq!(handshake_status(&old), 426);
+        let mut short = upgrade;
+        short[3].1 = "c2hvcnQ=";
+        assert_eq!(handshake_status(&short), 400);
+    }
+
+    #[test]
+    fn reassembles_fragments_and_answers_pings() {
+        let (mut socket, mut client) = connection();
+        let mut input = frame(TEXT, b"Hel");
+        input.extend(frame(0x80 | PING, b"hi"));
+        input.extend(frame(0x80 | CONTINUATION, "lo é".as_bytes()));
+        input.extend(frame(0x80 | BINARY, &[7; 300]));
+        client.write_all(&input).unwrap();
+
+        assert!(matches!(socket.recv().unwrap(), Some(Message::Text(text)) if text == "Hello é"));
+        assert_eq!(read_reply(&mut client, 4), [0x80 | PONG, 2, b'h', b'i']);
+        assert!(matches!(socket.recv().unwrap(), Some(Message::Binary(data)) if data == [7; 300]));
+
+        socket.send(&Message::Binary(vec![1; 200])).unwrap();
+        let reply = read_reply(&mut client, 204);
+        assert_eq!(reply[..4], [0x80 | BINARY, 126, 0, 200]);
+        assert!(reply[4..].iter().all(|&b| b == 1));
+    }
+
+    #[test]
+    fn echoes_the_close_code() {
+        let (mut socket, mut client) = connection();
+        client
+            .write_all(&frame(0x80 | CLOSE, &[0x03, 0xe8, b'o', b'k']))
+            .unwrap();
+        assert!(socket.recv().unwrap().is_none());
+        assert_eq!(read_reply(&mut client, 4), [0x80 | CLOSE, 2, 0x03, 0xe8]);
+        assert!(socket.send(&Message::Text("late".to_string())).is_err());
+    }
+
+    #[test]
+    fn closes_on_protocol_errors() {
+        let mut unmasked = frame(0x80 | TEXT, b"hi");
+        unmasked[1] &= 0x7f;
+        for (input, code) in [
+            (unmasked, PROTOCOL_ERROR),
+            (frame(0xc0 | TEXT, b"hi"), PROTOCOL_ERROR),
+            (frame(0x80 | CONTINUATION, b"hi"), PROTOCOL_ERROR),
+            (frame(PING, b""), PROTOCOL_ERROR),
+            (frame(0x80 | 0x3, b""), PROTOCOL_ERROR),
+            (frame(0x80 | CLOSE, &[0x03, 0xed]), PROTOCOL_ERROR),
+            (frame(0x80 | TEXT, &[0xff]), INVALID_PAYLOAD),
+            (frame(0x80 | BINARY, &[0; MAX_MESSAGE + 1]), MESSAGE_TOO_BIG),
+            (
+                [
+                    frame(BINARY, &[0; 1000]),
+                    frame(0x80 | CONTINUATION, &[0; 100]),
+                ]
+                .concat(),
+                MESSAGE_TOO_BIG,
+            ),
+        ] {
+            let (mut socket, mut client) = connection();
+            client.write_all(&input).unwrap();
+            assert!(socket.recv().is_err());
+            let [first, len, high, low] = read_reply(&mut client, 4)[..] else {
+                unreachable!()
+            };
+            assert_eq!((first, len), (0x80 | CLOSE, 2));
+            assert_eq!(u16::from_be_bytes([high, low]), code);
+        }
+    }
+}